@@ -0,0 +1,112 @@
+//! Cache of LLM responses to identical prompts.
+use std::{
+	collections::HashMap,
+	marker::PhantomData,
+	sync::{Mutex, MutexGuard},
+	time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{Config, ContextMessage, Llm};
+
+/// Parameters of the [`Config::PromptModel`].
+type PromptModelParameters<T> = <<T as Config>::PromptModel as Llm<T>>::Parameters;
+
+/// Cached response along with the time it was cached at.
+struct CachedResponse {
+	cached_at: Instant,
+	content: String,
+}
+
+/// In-memory cache of response contents keyed by model name, parameters and prompt messages, used
+/// by [`Loom::weave_ephemeral_cached`](crate::Loom::weave_ephemeral_cached).
+///
+/// Responses expire after [`Config::CACHE_TTL_SECS`] and are lost when the process exits.
+pub struct ResponseCache<T: Config> {
+	responses: Mutex<HashMap<(String, [u8; 32]), CachedResponse>>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config> Default for ResponseCache<T> {
+	fn default() -> Self {
+		Self { responses: Mutex::new(HashMap::new()), _phantom: PhantomData }
+	}
+}
+
+impl<T: Config> ResponseCache<T> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn responses(&self) -> MutexGuard<'_, HashMap<(String, [u8; 32]), CachedResponse>> {
+		self.responses.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Cached response content of `model` with `params` to `msgs`, unless expired.
+	pub fn get(
+		&self,
+		model: &str,
+		params: &PromptModelParameters<T>,
+		msgs: &[ContextMessage<T>],
+	) -> Option<String> {
+		let responses = self.responses();
+		let response = responses.get(&(model.to_string(), prompt_hash::<T>(params, msgs)))?;
+
+		(response.cached_at.elapsed() < ttl::<T>()).then(|| response.content.clone())
+	}
+
+	/// Cache the response `content` of `model` with `params` to `msgs`, evicting all expired
+	/// responses.
+	pub fn insert(
+		&self,
+		model: &str,
+		params: &PromptModelParameters<T>,
+		msgs: &[ContextMessage<T>],
+		content: String,
+	) {
+		let mut responses = self.responses();
+		responses.retain(|_, response| response.cached_at.elapsed() < ttl::<T>());
+		responses.insert(
+			(model.to_string(), prompt_hash::<T>(params, msgs)),
+			CachedResponse { cached_at: Instant::now(), content },
+		);
+	}
+
+	/// Number of cached responses, including expired ones not evicted yet.
+	pub fn len(&self) -> usize {
+		self.responses().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Remove all cached responses.
+	pub fn clear(&self) {
+		self.responses().clear();
+	}
+}
+
+fn ttl<T: Config>() -> Duration {
+	Duration::from_secs(T::CACHE_TTL_SECS)
+}
+
+/// SHA-256 hash of the `params` and of the [`ContextMessage::hash`] and content parts of all
+/// `msgs`, ignoring their timestamps.
+///
+/// The `params` are hashed by their [`Debug`](std::fmt::Debug) representation since
+/// [`Llm::Parameters`] are not required to be hashable.
+fn prompt_hash<T: Config>(
+	params: &PromptModelParameters<T>,
+	msgs: &[ContextMessage<T>],
+) -> [u8; 32] {
+	let mut hasher = Sha256::new();
+	hasher.update(format!("{:?}", params));
+	for msg in msgs {
+		hasher.update(msg.hash());
+		#[cfg(feature = "multimodal")]
+		hasher.update(format!("{:?}", msg.content_parts));
+	}
+	hasher.finalize().into()
+}
@@ -0,0 +1,129 @@
+//! Tree of the tapestry fragment instances of a tapestry, for debugging and visualization.
+use std::{
+	collections::HashMap,
+	fmt::{Display, Formatter},
+};
+
+use num_traits::ToPrimitive;
+
+use crate::{
+	storage::with_storage_timeout,
+	types::{LoomError, StorageError},
+	Config, TapestryChestHandler, TapestryId,
+};
+
+/// A single tapestry fragment instance of a [`ConversationGraph`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphNode {
+	pub instance: u64,
+	pub message_count: usize,
+	pub context_tokens: u64,
+	/// Instance this tapestry fragment was branched from, see
+	/// [`Loom::continue_from`](crate::Loom::continue_from).
+	pub parent_instance: Option<u64>,
+}
+
+/// Tree of the tapestry fragment instances of a tapestry.
+///
+/// Nodes are keyed by `{base_key}:{instance}`. Each instance is a child of its
+/// `parent_instance` if it was branched off, otherwise of the previous instance, which it
+/// continues after summarization.
+///
+/// Formats as a [DOT](https://graphviz.org/doc/info/lang.html) digraph.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversationGraph {
+	pub nodes: HashMap<String, GraphNode>,
+	/// `(parent, child)` node keys.
+	pub edges: Vec<(String, String)>,
+}
+
+/// Build the [`ConversationGraph`] of `root`.
+///
+/// Tapestry fragment instances are loaded from [`Config::Chest`] one at a time. Deleted instances
+/// are skipped, as are edges from them.
+pub async fn build_graph<T: Config, TID: TapestryId>(
+	root: TID,
+) -> crate::Result<ConversationGraph> {
+	let mut graph = ConversationGraph::default();
+
+	let instance_count =
+		match with_storage_timeout::<T, _>(T::Chest::get_tapestry(root.clone())).await? {
+			Some(instance_count) => instance_count as u64,
+			None => return Ok(graph),
+		};
+
+	let base_key = root.base_key();
+	let node_key = |instance: u64| format!("{base_key}:{instance}");
+
+	for instance in 1..=instance_count {
+		let tapestry_fragment = match with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+			root.clone(),
+			Some(instance),
+		))
+		.await
+		{
+			Ok(Some(tapestry_fragment)) => tapestry_fragment,
+			Ok(None) => continue,
+			Err(e) => match LoomError::from(e) {
+				LoomError::Storage(StorageError::NotFound) => continue,
+				e => return Err(e.into()),
+			},
+		};
+
+		graph.nodes.insert(
+			node_key(instance),
+			GraphNode {
+				instance,
+				message_count: tapestry_fragment.context_messages.len(),
+				context_tokens: tapestry_fragment.context_tokens.to_u64().unwrap_or_default(),
+				parent_instance: tapestry_fragment.parent_instance,
+			},
+		);
+	}
+
+	let mut instances = graph.nodes.values().map(|node| node.instance).collect::<Vec<_>>();
+	instances.sort_unstable();
+	for instance in instances {
+		let parent = match graph.nodes[&node_key(instance)].parent_instance {
+			Some(parent_instance) => parent_instance,
+			None => instance - 1,
+		};
+
+		if graph.nodes.contains_key(&node_key(parent)) {
+			graph.edges.push((node_key(parent), node_key(instance)));
+		}
+	}
+
+	Ok(graph)
+}
+
+impl Display for ConversationGraph {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		writeln!(f, "digraph {{")?;
+
+		let mut keys = self.nodes.keys().collect::<Vec<_>>();
+		keys.sort_by_key(|key| self.nodes[*key].instance);
+		for key in keys {
+			let node = &self.nodes[key];
+			writeln!(
+				f,
+				"\t\"{}\" [label=\"{}\\n{} messages, {} tokens\"];",
+				escape(key),
+				escape(key),
+				node.message_count,
+				node.context_tokens
+			)?;
+		}
+
+		for (parent, child) in &self.edges {
+			writeln!(f, "\t\"{}\" -> \"{}\";", escape(parent), escape(child))?;
+		}
+
+		write!(f, "}}")
+	}
+}
+
+/// Escape `id` for use in a quoted DOT identifier.
+fn escape(id: &str) -> String {
+	id.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,90 @@
+//! Paginated access to the message history of a tapestry.
+use crate::{
+	storage::with_storage_timeout,
+	types::{LoomError, StorageError},
+	Config, ContextMessage, TapestryChestHandler, TapestryFragment, TapestryId,
+};
+
+/// Get a page of the messages of `tapestry_id`, oldest first.
+///
+/// Returns the messages of page `page` (starting at `0`) along with whether more pages exist.
+///
+/// Only the tapestry fragment instances covering the requested page are loaded from
+/// [`Config::Chest`]. Which instances to load is estimated from the number of messages in the
+/// oldest tapestry fragment instance, so page boundaries are approximate when tapestry fragments
+/// vary in length. Deleted instances are skipped.
+pub async fn get_messages_page<T: Config, TID: TapestryId>(
+	tapestry_id: TID,
+	page: usize,
+	page_size: usize,
+) -> crate::Result<(Vec<ContextMessage<T>>, bool)> {
+	let instance_count =
+		match with_storage_timeout::<T, _>(T::Chest::get_tapestry(tapestry_id.clone())).await? {
+			Some(instance_count) => instance_count as u64,
+			None => return Ok((vec![], false)),
+		};
+
+	if page_size == 0 {
+		return Ok((vec![], false));
+	}
+
+	// Estimate the number of messages per tapestry fragment from the oldest instance
+	let mut first_instance = 1;
+	let first_tapestry_fragment = loop {
+		if first_instance > instance_count {
+			return Ok((vec![], false));
+		}
+		if let Some(tapestry_fragment) = load_instance(&tapestry_id, first_instance).await? {
+			break tapestry_fragment;
+		}
+		first_instance += 1;
+	};
+	let messages_per_fragment = first_tapestry_fragment.context_messages.len().max(1);
+
+	let start = page.saturating_mul(page_size);
+	let start_instance = first_instance + (start / messages_per_fragment) as u64;
+	let mut skip = start % messages_per_fragment;
+
+	let mut msgs = Vec::with_capacity(page_size);
+	for instance in start_instance..=instance_count {
+		let tapestry_fragment = if instance == first_instance {
+			Some(first_tapestry_fragment.clone())
+		} else {
+			load_instance(&tapestry_id, instance).await?
+		};
+		let Some(tapestry_fragment) = tapestry_fragment else {
+			continue;
+		};
+
+		let mut remaining = tapestry_fragment.context_messages.into_iter().skip(skip);
+		skip = 0;
+
+		msgs.extend(remaining.by_ref().take(page_size - msgs.len()));
+
+		if msgs.len() == page_size {
+			let has_more = remaining.next().is_some() || instance < instance_count;
+			return Ok((msgs, has_more));
+		}
+	}
+
+	Ok((msgs, false))
+}
+
+/// Load a tapestry fragment instance, returning `None` if it does not exist.
+async fn load_instance<T: Config, TID: TapestryId>(
+	tapestry_id: &TID,
+	instance: u64,
+) -> crate::Result<Option<TapestryFragment<T>>> {
+	match with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+		tapestry_id.clone(),
+		Some(instance),
+	))
+	.await
+	{
+		Ok(tapestry_fragment) => Ok(tapestry_fragment),
+		Err(e) => match LoomError::from(e) {
+			LoomError::Storage(StorageError::NotFound) => Ok(None),
+			e => Err(e.into()),
+		},
+	}
+}
@@ -40,32 +40,84 @@
 //! Should there be a need to integrate a distinct storage backend, you have the flexibility to
 //! create a custom handler by implementing the [`TapestryChestHandler`] trait and injecting it
 //! into the [`Config::Chest`] associated type.
-#![feature(async_closure)]
 #![feature(associated_type_defaults)]
-#![feature(more_qualified_paths)]
-#![feature(const_option)]
 #![feature(anonymous_lifetime_in_impl_trait)]
 
 use std::{
-	collections::VecDeque,
+	any::TypeId,
+	collections::{HashMap, HashSet, VecDeque},
 	fmt::{Debug, Display},
+	future::Future,
 	marker::PhantomData,
 	str::FromStr,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex, OnceLock,
+	},
+	time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use async_openai::types::{
+	ChatCompletionRequestAssistantMessage, ChatCompletionRequestFunctionMessage,
+	ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart,
+	ChatCompletionRequestSystemMessage, ChatCompletionRequestToolMessage,
+	ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
+};
+#[cfg(feature = "multimodal")]
+use async_openai::types::{
+	ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+	ImageUrl,
+};
 use async_trait::async_trait;
 pub use bounded_integer::BoundedU8;
 use num_traits::{
 	CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, FromPrimitive, SaturatingAdd, SaturatingMul,
 	SaturatingSub, ToPrimitive, Unsigned,
 };
+use redaction::{NoRedaction, RedactionFilter};
 pub use redis::{RedisWrite, ToRedisArgs};
-use serde::{Deserialize, Serialize};
-use storage::TapestryChest;
-use tracing::{debug, error, instrument};
+use sentiment::{NoSentimentAnalysis, Sentiment, SentimentAnalyzer};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use storage::{with_storage_timeout, TapestryChest, TapestryLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument, warn, Instrument};
+
+/// Create a [`tracing::Span`] identifying the conversation of a [`TapestryId`].
+///
+/// The span has the fields `tapestry.base_key` and `tapestry.instance`, which allow filtering logs
+/// by conversation. `tapestry.instance` is recorded once the tapestry fragment instance is known.
+/// `llm.model` is recorded by [`Loom::weave`] with the name of the model ultimately prompted,
+/// which differs from [`Config::PromptModel`] after falling back to
+/// [`Config::fallback_prompt_model`].
+macro_rules! tapestry_span {
+	($tapestry_id:expr) => {
+		tracing::info_span!(
+			"tapestry",
+			tapestry.base_key = %$tapestry_id.base_key(),
+			tapestry.instance = tracing::field::Empty,
+			llm.model = tracing::field::Empty,
+		)
+	};
+}
 
 pub mod architecture;
+pub mod cache;
+pub mod graph;
+pub mod history;
+#[cfg(feature = "embeddings")]
+pub mod memory;
+pub mod providers;
+pub mod redaction;
+#[cfg(feature = "relevance-compression")]
+pub mod relevance;
+pub mod sentiment;
+pub mod stats;
 pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tokenizer;
+pub mod training;
 pub mod types;
 
 #[cfg(test)]
@@ -75,12 +127,26 @@ mod tests;
 
 use num_traits::Zero;
 pub use storage::TapestryChestHandler;
-use types::{LoomError, SummaryModelTokens, WeaveError, ASSISTANT_ROLE, SYSTEM_ROLE};
+use types::{
+	CoTResponse, CompressionReport, ConfigError, ContextTruncationStrategy, DryRunOutput,
+	FragmentDiff, FunctionCall, FunctionSpec, LoomError, MessageValidationError, ParseError,
+	PromptFormat, ResponseScore, StorageError, StorageFormat, SummaryModelTokens, SummaryQuality,
+	TapestryIdError, TokenLogprob, WeaveError, ASSISTANT_ROLE, FUNCTION_ROLE, SYSTEM_ROLE,
+	USER_ROLE,
+};
 
-use crate::types::{PromptModelRequest, PromptModelTokens, WrapperRole};
+#[cfg(feature = "multimodal")]
+use crate::types::MessageContent;
+use crate::types::{PromptModelTokens, WrapperRole};
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// Maximum length in bytes of a [`TapestryId::base_key`].
+pub const MAX_BASE_KEY_LENGTH: usize = 1024;
+
+/// Age in seconds after which [`Llm::default_pricing`] is considered stale.
+pub const PRICING_STALE_AFTER_SECS: u64 = 90 * 24 * 60 * 60;
+
 /// Represents a unique identifier for any arbitrary entity.
 ///
 /// This trait provides a method for generating a standardized key, which can be utilized across
@@ -108,6 +174,69 @@ pub trait TapestryId: Debug + Clone + Send + Sync + 'static {
 	/// This method should produce a unique string identifier, that will serve as a key for
 	/// associated objects or data within [`TapestryChestHandler`] implementations.
 	fn base_key(&self) -> String;
+	/// Validates that the base key can safely be used as a storage key.
+	///
+	/// The base key must be non-empty, contain no control characters (such as null bytes or
+	/// newlines) and be at most [`MAX_BASE_KEY_LENGTH`] bytes long.
+	fn validate(&self) -> std::result::Result<(), TapestryIdError> {
+		let base_key = self.base_key();
+
+		if base_key.is_empty() {
+			return Err(TapestryIdError::Empty);
+		}
+
+		if let Some(c) = base_key.chars().find(|c| c.is_control()) {
+			return Err(TapestryIdError::ControlCharacter(c));
+		}
+
+		if base_key.len() > MAX_BASE_KEY_LENGTH {
+			return Err(TapestryIdError::TooLong {
+				length: base_key.len(),
+				max: MAX_BASE_KEY_LENGTH,
+			});
+		}
+
+		Ok(())
+	}
+}
+
+/// [`TapestryId`] organizing tapestries in a tree, e.g. a chapter with scenes.
+///
+/// The base key joins the `segments` with `/`, e.g. `chapter-1/scene-2`. The children of a
+/// tapestry are listed with [`TapestryChestHandler::list_children`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct HierarchicalId {
+	pub segments: Vec<String>,
+}
+
+impl HierarchicalId {
+	/// Create a new `HierarchicalId` from its `segments`, root first.
+	pub fn new(segments: impl IntoIterator<Item = impl Into<String>>) -> Self {
+		Self { segments: segments.into_iter().map(Into::into).collect() }
+	}
+
+	/// Parent of this id, i.e. all but the last segment.
+	///
+	/// Returns `None` for root ids, which have at most one segment.
+	pub fn parent(&self) -> Option<HierarchicalId> {
+		match self.segments.split_last() {
+			Some((_, parent)) if !parent.is_empty() => Some(Self { segments: parent.to_vec() }),
+			_ => None,
+		}
+	}
+
+	/// Child of this id named `name`.
+	pub fn child(&self, name: &str) -> HierarchicalId {
+		let mut segments = self.segments.clone();
+		segments.push(name.to_string());
+		Self { segments }
+	}
+}
+
+impl TapestryId for HierarchicalId {
+	fn base_key(&self) -> String {
+		self.segments.join("/")
+	}
 }
 
 #[derive(Debug)]
@@ -124,6 +253,11 @@ pub trait Llm<T: Config>:
 	///
 	/// Defaults to `75%`
 	const TOKEN_WORD_RATIO: BoundedU8<0, 100> = BoundedU8::new(75).unwrap();
+	/// Unix timestamp in seconds of when [`Llm::default_pricing`] was last updated.
+	///
+	/// [`TapestryFragment::estimate_default_cost`] warns if it is older than
+	/// [`PRICING_STALE_AFTER_SECS`]. Defaults to `None`
+	const PRICING_UPDATED_AT: Option<u64> = None;
 
 	/// Tokens are an LLM concept which represents pieces of words. For example, each ChatGPT token
 	/// represents roughly 75% of a word.
@@ -140,6 +274,7 @@ pub trait Llm<T: Config>:
 		+ Debug
 		+ ToString
 		+ Serialize
+		+ DeserializeOwned
 		+ Default
 		+ TryFrom<usize>
 		+ Unsigned
@@ -178,6 +313,8 @@ pub trait Llm<T: Config>:
 	/// Calculates the number of tokens in a string.
 	///
 	/// This may vary depending on the type of tokens used by the LLM. In the case of ChatGPT, can be calculated using the [tiktoken-rs](https://github.com/zurawiki/tiktoken-rs#counting-token-length) crate.
+	///
+	/// The [`tokenizer`] module provides cached tiktoken tokenizers for this purpose.
 	fn count_tokens(content: &str) -> Result<Self::Tokens>;
 	/// Prompt LLM with the supplied messages and parameters.
 	async fn prompt(
@@ -193,6 +330,9 @@ pub trait Llm<T: Config>:
 	///
 	/// This is calculated by multiplying the maximum context length (tokens) for the current
 	/// [`Config::PromptModel`] by the [`Config::TOKEN_THRESHOLD_PERCENTILE`] and dividing by 100.
+	///
+	/// If [`Config::MAX_RESPONSE_TOKENS`] is set, the result never exceeds
+	/// [`Llm::prompt_token_limit`].
 	fn get_max_prompt_token_limit(&self) -> Self::Tokens {
 		let max_context_length = self.max_context_length();
 		let token_threshold = Self::Tokens::from_u8(T::TOKEN_THRESHOLD_PERCENTILE.get()).unwrap();
@@ -200,8 +340,17 @@ pub trait Llm<T: Config>:
 			Some(tokens) => tokens,
 			None => max_context_length,
 		};
+		let tokens = tokens.checked_div(&Self::Tokens::from_u8(100).unwrap()).unwrap();
 
-		tokens.checked_div(&Self::Tokens::from_u8(100).unwrap()).unwrap()
+		match T::MAX_RESPONSE_TOKENS.and_then(Self::Tokens::from_u64) {
+			Some(max_response_tokens) => tokens.min(self.prompt_token_limit(max_response_tokens)),
+			None => tokens,
+		}
+	}
+	/// Number of tokens left for the prompt once `max_response_tokens` are reserved for the
+	/// response.
+	fn prompt_token_limit(&self, max_response_tokens: Self::Tokens) -> Self::Tokens {
+		self.max_context_length().saturating_sub(&max_response_tokens)
 	}
 	/// Get optional max completion token limit.
 	fn get_max_completion_token_limit(&self) -> Option<Self::Tokens> {
@@ -211,6 +360,69 @@ pub trait Llm<T: Config>:
 	fn ctx_msgs_to_prompt_requests(&self, msgs: &[ContextMessage<T>]) -> Vec<Self::Request> {
 		msgs.iter().map(|m| m.clone().into()).collect()
 	}
+	/// Parameters to prompt with when the LLM may call any of the `functions`.
+	///
+	/// Used by [`Loom::weave_agent`]. Defaults to `params` unchanged, meaning the LLM is not made
+	/// aware of any functions.
+	fn params_with_functions(
+		&self,
+		params: &Self::Parameters,
+		_functions: &[FunctionSpec],
+	) -> Self::Parameters {
+		params.clone()
+	}
+	/// Function call requested by the LLM in `response`, if any.
+	///
+	/// Used by [`Loom::weave_agent`]. Defaults to `None`, meaning the LLM never calls functions.
+	fn function_call(_response: &Self::Response) -> Option<FunctionCall> {
+		None
+	}
+	/// Whether the model is a chat model, prompted with a list of messages.
+	///
+	/// Defaults to `true`
+	fn is_chat_model(&self) -> bool {
+		true
+	}
+	/// Whether the model can call functions.
+	///
+	/// [`Loom::weave_agent`] fails with [`WeaveError::UnsupportedModelCapability`] if not.
+	///
+	/// Defaults to `true`
+	fn supports_function_calling(&self) -> bool {
+		true
+	}
+	/// Whether the model accepts images.
+	///
+	/// With the `multimodal` feature, [`Loom::weave`] fails with
+	/// [`WeaveError::UnsupportedModelCapability`] if not and a message has content parts.
+	///
+	/// Defaults to `true`
+	fn supports_vision(&self) -> bool {
+		true
+	}
+	/// List price of the model in dollars per 1000 prompt and completion tokens respectively.
+	///
+	/// Defaults to `None`
+	fn default_pricing(&self) -> Option<(f64, f64)> {
+		None
+	}
+	/// Whether `error`, returned by [`Llm::prompt`], means that the quota of the model is
+	/// exhausted, e.g. an HTTP 429 response.
+	///
+	/// [`Loom::weave`] retries with [`Config::fallback_prompt_model`] if so.
+	///
+	/// Defaults to `false`
+	fn is_quota_error(&self, _error: &(dyn std::error::Error + Send + Sync)) -> bool {
+		false
+	}
+	/// Log probabilities of the tokens of `response`, if any.
+	///
+	/// Only expected when [`Config::RETURN_LOGPROBS`] is enabled. Logprobs are never stored in
+	/// a [`TapestryFragment`], callers extract them from the response returned by
+	/// [`Loom::weave`]. Defaults to `None`.
+	fn logprobs(_response: &Self::Response) -> Option<Vec<TokenLogprob>> {
+		None
+	}
 	/// Convert tokens to words.
 	///
 	/// In the case of ChatGPT, each token represents roughly 75% of a word.
@@ -233,6 +445,160 @@ pub trait Config: Debug + Sized + Clone + Default + Send + Sync + 'static {
 	/// If the maximum completion tokens is less than the minimum response length, a summary
 	/// will be generated and a new tapestry fragment will be created.
 	const MINIMUM_RESPONSE_LENGTH: u64;
+	/// Format used to encode the messages sent to the [`Config::PromptModel`].
+	///
+	/// Defaults to [`PromptFormat::OpenAI`]
+	const PROMPT_FORMAT: PromptFormat = PromptFormat::OpenAI;
+	/// Order in which [`TapestryFragment::truncate_to_tokens`] removes messages.
+	///
+	/// Defaults to [`ContextTruncationStrategy::OldestFirst`]
+	const CONTEXT_TRUNCATION_STRATEGY: ContextTruncationStrategy =
+		ContextTruncationStrategy::OldestFirst;
+	/// Serialization format used by [`Config::Chest`] to store [`TapestryFragment`] data.
+	///
+	/// Defaults to [`StorageFormat::Json`]
+	const STORAGE_FORMAT: StorageFormat = StorageFormat::Json;
+	/// Sequences at which the LLM should stop generating further tokens, e.g. `["<END>", "---"]`.
+	///
+	/// [`Llm`] implementations are expected to forward these to their provider. OpenAI supports
+	/// up to four stop sequences.
+	///
+	/// Defaults to none
+	const STOP_SEQUENCES: &'static [&'static str] = &[];
+	/// Instruction appended to the messages of the [`TapestryFragment`] when prompting the
+	/// [`Config::SummaryModel`] for a summary, e.g. `"Summarize the meeting so far in {words}
+	/// words or less"`.
+	///
+	/// The `{words}` placeholder is replaced with the maximum number of words of the summary.
+	///
+	/// Defaults to none, in which case only the messages are sent
+	const SUMMARIZATION_PROMPT: &'static str = "";
+	/// Seed for sampling, making responses reproducible for the same input.
+	///
+	/// [`Llm`] implementations are expected to forward this to their provider. Determinism is best
+	/// effort for most providers, including OpenAI.
+	///
+	/// Defaults to `None`
+	const SEED: Option<u64> = None;
+	/// Nucleus sampling probability mass, the LLM only samples from the most likely tokens whose
+	/// probabilities add up to `TOP_P`. Must be within `(0.0, 1.0]`.
+	///
+	/// [`Llm`] implementations are expected to forward this to their provider. OpenAI recommends
+	/// altering either the temperature or `TOP_P`, not both. The built-in [`providers`] log a
+	/// warning when both are set.
+	///
+	/// Defaults to `1.0`, sampling from all tokens
+	const TOP_P: f32 = 1.0;
+	/// Maximum number of function calls [`Loom::weave_agent`] executes before giving up.
+	///
+	/// Defaults to `5`
+	const MAX_TOOL_ITERATIONS: u8 = 5;
+	/// Whether [`Loom::weave_with_memory`] recalls similar past messages.
+	///
+	/// Defaults to `false`
+	#[cfg(feature = "embeddings")]
+	const ENABLE_LONG_TERM_MEMORY: bool = false;
+	/// Maximum number of past messages recalled by [`Loom::weave_with_memory`].
+	///
+	/// Defaults to `3`
+	#[cfg(feature = "embeddings")]
+	const MEMORY_TOP_K: usize = 3;
+	/// Maximum number of [`Loom::weave`] calls per tapestry, e.g. to limit free tier
+	/// conversations, see [`TapestryChestHandler::get_weave_count`].
+	///
+	/// [`Loom::weave`] fails with [`WeaveError::QuotaExceeded`] before prompting the LLM once
+	/// exceeded. Only calls which saved a response are counted. Requires a [`Config::Chest`]
+	/// implementing [`TapestryChestHandler::increment_weave_count`] and
+	/// [`TapestryChestHandler::get_weave_count`].
+	///
+	/// Defaults to `None`, unlimited
+	const MAX_WEAVE_CALLS: Option<u64> = None;
+	/// Prefix of the base keys listed by [`TapestryChestHandler::list_all_tapestry_ids`].
+	///
+	/// Used to scope the listing to a namespace. Defaults to `""`, all tapestries
+	const KEY_SCAN_PREFIX: &'static str = "";
+	/// Number of seconds responses are cached for by [`cache::ResponseCache`].
+	///
+	/// Defaults to `300`
+	const CACHE_TTL_SECS: u64 = 300;
+	/// Maximum number of retrieved chunks sent to the LLM by
+	/// [`Loom::weave_with_retrieved_context`], extra chunks are dropped.
+	///
+	/// Defaults to `5`
+	const MAX_RETRIEVED_CHUNKS: usize = 5;
+	/// Whether the [`Config::PromptModel`] should return the log probabilities of the tokens of
+	/// its response, see [`Llm::logprobs`].
+	///
+	/// [`Llm`] implementations are expected to forward this to their provider.
+	///
+	/// Defaults to `false`
+	const RETURN_LOGPROBS: bool = false;
+	/// Number of most likely alternative tokens, along with their log probabilities, returned at
+	/// each token position. Requires [`Config::RETURN_LOGPROBS`].
+	///
+	/// OpenAI supports up to 20 alternatives.
+	///
+	/// Defaults to `0`
+	const TOP_LOGPROBS: u8 = 0;
+	/// Maximum time [`Loom::weave`] waits to acquire the lock on a [`TapestryId`].
+	///
+	/// Concurrent calls to [`Loom::weave`] for the same [`TapestryId`] are serialized so that
+	/// they do not overwrite each other's messages.
+	///
+	/// Defaults to `30000` milliseconds
+	const LOCK_TIMEOUT_MS: u64 = 30_000;
+	/// Maximum time a [`TapestryChestHandler`] operation may take before failing with
+	/// [`StorageError::Timeout`], so that an unreachable storage backend fails fast instead of
+	/// blocking [`Loom::weave`].
+	///
+	/// Acquiring a lock is bounded by [`Config::LOCK_TIMEOUT_MS`] instead.
+	///
+	/// Defaults to `5000` milliseconds
+	const STORAGE_TIMEOUT_MS: u64 = 5_000;
+	/// Number of tokens always reserved for the response of the [`Config::PromptModel`].
+	///
+	/// Guarantees that the prompt and the response never exceed the maximum context length of the
+	/// model. See [`Llm::prompt_token_limit`].
+	///
+	/// Defaults to `None`, in which case only [`Config::TOKEN_THRESHOLD_PERCENTILE`] limits the
+	/// prompt
+	const MAX_RESPONSE_TOKENS: Option<u64> = None;
+	/// Time after which a tapestry expires from [`Config::Chest`] when it is not saved again.
+	///
+	/// Every save of a tapestry fragment resets the expiry of the whole tapestry. Useful for
+	/// enforcing data retention policies at the storage layer. See
+	/// [`TapestryChestHandler::set_ttl`].
+	///
+	/// Defaults to `None`, meaning tapestries never expire
+	const FRAGMENT_TTL_SECONDS: Option<u64> = None;
+	/// Whether [`Loom::weave`] logs the full content of the prompt sent to the
+	/// [`Config::PromptModel`] at `DEBUG` level.
+	///
+	/// When `false`, only the number of messages and tokens is logged.
+	///
+	/// Defaults to `false`
+	const LOG_PROMPTS: bool = false;
+	/// Whether [`Loom::weave`] logs the full content of the [`Config::PromptModel`] response at
+	/// `DEBUG` level.
+	///
+	/// When `false`, only the length of the response is logged.
+	///
+	/// Defaults to `false`
+	const LOG_RESPONSES: bool = false;
+	/// Name of the assistant persona.
+	///
+	/// When non-empty, used as the `name` of assistant messages without an `account_id` when
+	/// converting a [`ContextMessage`] into a [`ChatCompletionRequestMessage`].
+	///
+	/// Defaults to `""`
+	const ASSISTANT_NAME: &'static str = "";
+	/// Name of the user persona.
+	///
+	/// When non-empty, used as the `name` of user messages without an `account_id` when converting
+	/// a [`ContextMessage`] into a [`ChatCompletionRequestMessage`].
+	///
+	/// Defaults to `""`
+	const USER_NAME: &'static str = "";
 
 	/// The LLM to use for generating responses to prompts.
 	type PromptModel: Llm<Self>;
@@ -246,20 +612,173 @@ pub trait Config: Debug + Sized + Clone + Default + Send + Sync + 'static {
 	/// Defaults to [`TapestryChest`]. Using this default requires you to supply the `hostname`,
 	/// `port` and `credentials` to connect to your instance.
 	type Chest: TapestryChestHandler<Self> = TapestryChest;
+	/// Masks sensitive content of [`ContextMessage`]s before they are saved by
+	/// [`Config::Chest`].
+	///
+	/// The LLM is still prompted with the original content.
+	///
+	/// Defaults to [`NoRedaction`]
+	type RedactionFilter: RedactionFilter = NoRedaction;
+	/// Sets the `sentiment` of [`ContextMessage`]s before they are saved by [`Config::Chest`],
+	/// unless it is already set.
+	///
+	/// The sentiment is analyzed before the [`Config::RedactionFilter`] is applied.
+	///
+	/// Defaults to [`NoSentimentAnalysis`]
+	type SentimentAnalyzer: SentimentAnalyzer = NoSentimentAnalysis;
+
+	/// Check that all constants are within their valid ranges.
+	///
+	/// Called once per [`Config`] type by [`Loom::weave`], which fails with
+	/// [`WeaveError::BadConfig`] if the configuration is invalid.
+	fn validate_config() -> std::result::Result<(), ConfigError> {
+		let mut invalid_fields = vec![];
+
+		if Self::TOKEN_THRESHOLD_PERCENTILE.get() == 0 {
+			invalid_fields.push("TOKEN_THRESHOLD_PERCENTILE must be greater than 0".to_string());
+		}
+		if Self::STOP_SEQUENCES.iter().any(|sequence| sequence.is_empty()) {
+			invalid_fields.push("STOP_SEQUENCES must not contain empty sequences".to_string());
+		}
+		if Self::MAX_RESPONSE_TOKENS == Some(0) {
+			invalid_fields.push("MAX_RESPONSE_TOKENS must be greater than 0".to_string());
+		}
+		if !(Self::TOP_P > 0.0 && Self::TOP_P <= 1.0) {
+			invalid_fields.push("TOP_P must be greater than 0 and at most 1".to_string());
+		}
+		if Self::TOP_LOGPROBS > 20 {
+			invalid_fields.push("TOP_LOGPROBS must be at most 20".to_string());
+		}
+		if Self::TOP_LOGPROBS > 0 && !Self::RETURN_LOGPROBS {
+			invalid_fields.push("TOP_LOGPROBS requires RETURN_LOGPROBS".to_string());
+		}
 
+		match invalid_fields.is_empty() {
+			true => Ok(()),
+			false => Err(ConfigError { invalid_fields }),
+		}
+	}
+	/// Map an error returned by [`Llm::prompt`] before it is surfaced by [`Loom`].
+	///
+	/// Override to convert provider errors into the error type of the application, e.g. HTTP
+	/// status codes or domain specific errors. Returns `error` unchanged by default.
+	fn map_prompt_error(
+		error: Box<dyn std::error::Error + Send + Sync>,
+	) -> Box<dyn std::error::Error + Send + Sync> {
+		error
+	}
+	/// Model [`Loom::weave`] falls back to when [`Config::PromptModel`] fails with a quota error,
+	/// see [`Llm::is_quota_error`].
+	///
+	/// The fallback model is prompted with the same messages and maximum number of tokens, so its
+	/// context should be at least as large. Defaults to `None`, never falling back.
+	fn fallback_prompt_model() -> Option<Self::PromptModel> {
+		None
+	}
+	/// AES-256 key used by [`storage::encrypted::EncryptedStorage`] to encrypt tapestry fragments.
+	///
+	/// Defaults to the 64 hexadecimal characters of the `TAPESTRY_ENCRYPTION_KEY` environment
+	/// variable.
+	///
+	/// # Panics
+	///
+	/// If the environment variable is missing or is not a valid key.
+	#[cfg(feature = "encryption")]
+	fn encryption_key() -> [u8; 32] {
+		let key = std::env::var("TAPESTRY_ENCRYPTION_KEY")
+			.expect("TAPESTRY_ENCRYPTION_KEY environment variable must be set");
+
+		match storage::encrypted::parse_hex_key(&key) {
+			Some(key) => key,
+			None => {
+				let m = "TAPESTRY_ENCRYPTION_KEY must be 64 hexadecimal characters";
+				error!(m);
+				panic!("{}", m)
+			},
+		}
+	}
 	/// Convert [`Config::PromptModel`] to [`Config::SummaryModel`] tokens.
 	fn convert_prompt_tokens_to_summary_model_tokens(
 		tokens: PromptModelTokens<Self>,
 	) -> SummaryModelTokens<Self>;
 }
 
+/// [`Config::validate_config`] results cached per [`Config`] type.
+static VALIDATED_CONFIGS: OnceLock<Mutex<HashMap<TypeId, std::result::Result<(), ConfigError>>>> =
+	OnceLock::new();
+
+/// Validate `T` once, returning the cached result on subsequent calls.
+fn validate_config<T: Config>() -> Result<()> {
+	let result = VALIDATED_CONFIGS
+		.get_or_init(Default::default)
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.entry(TypeId::of::<T>())
+		.or_insert_with(T::validate_config)
+		.clone();
+
+	result.map_err(|e| {
+		error!("{}", e);
+		LoomError::from(WeaveError::BadConfig(e.to_string())).into()
+	})
+}
+
+tokio::task_local! {
+	/// Set by [`Loom::weave`] once it starts saving the tapestry fragment, see
+	/// [`interruptible_weave`].
+	static WEAVE_SAVING: Arc<AtomicBool>;
+}
+
+/// Mark the [`Loom::weave`] running in the current task, if any, as saving.
+fn mark_weave_saving() {
+	let _ = WEAVE_SAVING.try_with(|saving| saving.store(true, Ordering::SeqCst));
+}
+
+/// Run `weave` until it completes, failing with the error of `interrupt` if it completes first.
+///
+/// Once `weave` starts saving the tapestry fragment, it is no longer interrupted so that a saved
+/// response is never reported as failed.
+async fn interruptible_weave<R>(
+	weave: impl Future<Output = Result<R>>,
+	interrupt: impl Future<Output = LoomError>,
+) -> Result<R> {
+	let saving = Arc::new(AtomicBool::new(false));
+	let weave = WEAVE_SAVING.scope(saving.clone(), weave);
+	tokio::pin!(weave);
+
+	tokio::select! {
+		biased;
+		e = interrupt => match saving.load(Ordering::SeqCst) {
+			true => {
+				warn!("{}, waiting for the tapestry fragment to be saved", e);
+				weave.await
+			},
+			false => Err(e.into()),
+		},
+		res = &mut weave => res,
+	}
+}
+
 /// Context message that represent a single message in a [`TapestryFragment`] instance.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ContextMessage<T: Config> {
 	pub role: WrapperRole,
 	pub content: String,
 	pub account_id: Option<String>,
 	pub timestamp: String,
+	/// Additional parts, such as image URLs, sent to the LLM alongside the `content`.
+	///
+	/// Only the `content` counts towards the token limit of a [`TapestryFragment`].
+	#[cfg(feature = "multimodal")]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub content_parts: Option<Vec<MessageContent>>,
+	/// Sentiment of the `content`, see [`Config::SentimentAnalyzer`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sentiment: Option<Sentiment>,
+	/// Pinned messages are sent right after the instructions and are never summarized, see
+	/// [`TapestryFragment::pin_message`].
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub pinned: bool,
 
 	_phantom: PhantomData<T>,
 }
@@ -272,10 +791,339 @@ impl<T: Config> ContextMessage<T> {
 		account_id: Option<String>,
 		timestamp: String,
 	) -> Self {
-		Self { role, content, account_id, timestamp, _phantom: PhantomData }
+		Self {
+			role,
+			content,
+			account_id,
+			timestamp,
+			#[cfg(feature = "multimodal")]
+			content_parts: None,
+			sentiment: None,
+			pinned: false,
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Build a `ContextMessage` with a [`ContextMessageBuilder`], e.g.
+	/// `ContextMessage::builder().role(USER_ROLE.into()).content("Hello").build()`.
+	pub fn builder() -> ContextMessageBuilder<T> {
+		ContextMessageBuilder::default()
+	}
+
+	/// Set the `sentiment` of the message, which is then not analyzed by
+	/// [`Config::SentimentAnalyzer`].
+	pub fn with_sentiment(mut self, sentiment: Sentiment) -> Self {
+		self.sentiment = Some(sentiment);
+		self
+	}
+
+	/// Set the additional `content_parts` of the message.
+	#[cfg(feature = "multimodal")]
+	pub fn with_content_parts(mut self, content_parts: Vec<MessageContent>) -> Self {
+		self.content_parts = Some(content_parts);
+		self
+	}
+
+	/// Build the OpenAI user message content.
+	///
+	/// Emits [`ChatCompletionRequestUserMessageContent::Array`] with the `content` followed by the
+	/// `content_parts` when there are any, otherwise
+	/// [`ChatCompletionRequestUserMessageContent::Text`].
+	#[cfg(feature = "multimodal")]
+	pub fn to_user_message_content(&self) -> ChatCompletionRequestUserMessageContent {
+		let content_parts = match &self.content_parts {
+			Some(content_parts) if !content_parts.is_empty() => content_parts,
+			_ => return ChatCompletionRequestUserMessageContent::Text(self.content.clone()),
+		};
+
+		let text_part = |text: &String| {
+			ChatCompletionRequestMessageContentPart::Text(
+				ChatCompletionRequestMessageContentPartText {
+					r#type: "text".to_string(),
+					text: text.clone(),
+				},
+			)
+		};
+
+		let mut parts = Vec::with_capacity(content_parts.len() + 1);
+		if !self.content.is_empty() {
+			parts.push(text_part(&self.content));
+		}
+		parts.extend(content_parts.iter().map(|part| match part {
+			MessageContent::Text(text) => text_part(text),
+			MessageContent::ImageUrl(url) => ChatCompletionRequestMessageContentPart::Image(
+				ChatCompletionRequestMessageContentPartImage {
+					r#type: "image_url".to_string(),
+					image_url: ImageUrl { url: url.clone(), detail: Default::default() },
+				},
+			),
+		}));
+
+		ChatCompletionRequestUserMessageContent::Array(parts)
+	}
+
+	/// SHA-256 hash of the `role`, `account_id` and `content`, identifying duplicate messages.
+	///
+	/// The `timestamp` is not hashed since the same message may be imported at different times.
+	pub fn hash(&self) -> [u8; 32] {
+		let mut hasher = Sha256::new();
+		hasher.update(self.role.as_str());
+		hasher.update([0]);
+		hasher.update(self.account_id.as_deref().unwrap_or_default());
+		hasher.update([0]);
+		hasher.update(&self.content);
+		hasher.finalize().into()
+	}
+
+	/// Number of whitespace separated words in the `content`.
+	pub fn word_count(&self) -> usize {
+		self.content.split_whitespace().count()
+	}
+
+	/// Keep only the first `max_words` whitespace separated words of the `content`.
+	///
+	/// Truncated content is suffixed with `"..."`. Content with at most `max_words` words is left
+	/// untouched.
+	pub fn truncate_content(mut self, max_words: usize) -> Self {
+		if self.word_count() > max_words {
+			let mut content =
+				self.content.split_whitespace().take(max_words).collect::<Vec<_>>().join(" ");
+			content.push_str("...");
+			self.content = content;
+		}
+		self
+	}
+}
+
+/// Converts an OpenAI message into a [`ContextMessage`] timestamped with the current time.
+///
+/// The `name` of the message becomes the `account_id`. Fails with [`WeaveError::BadOpenAIRole`]
+/// for tool messages and with [`WeaveError::MissingContent`] for messages without content.
+impl<T: Config> TryFrom<ChatCompletionRequestMessage> for ContextMessage<T> {
+	type Error = LoomError;
+
+	fn try_from(msg: ChatCompletionRequestMessage) -> std::result::Result<Self, Self::Error> {
+		let (role, content, account_id) = match msg {
+			ChatCompletionRequestMessage::System(msg) => (Role::System, msg.content, msg.name),
+			ChatCompletionRequestMessage::User(msg) => {
+				let content = match msg.content {
+					ChatCompletionRequestUserMessageContent::Text(text) => text,
+					ChatCompletionRequestUserMessageContent::Array(parts) =>
+						return Ok(Self::from_user_message_parts(parts, msg.name)),
+				};
+				(Role::User, content, msg.name)
+			},
+			ChatCompletionRequestMessage::Assistant(msg) =>
+				(Role::Assistant, msg.content.ok_or(WeaveError::MissingContent)?, msg.name),
+			ChatCompletionRequestMessage::Function(msg) =>
+				(Role::Function, msg.content.ok_or(WeaveError::MissingContent)?, Some(msg.name)),
+			ChatCompletionRequestMessage::Tool(msg) =>
+				return Err(WeaveError::BadOpenAIRole(msg.role).into()),
+		};
+
+		Ok(Self::new(WrapperRole::Role(role), content, account_id, chrono::Utc::now().to_rfc3339()))
+	}
+}
+
+/// Builder of a [`ContextMessage`], see [`ContextMessage::builder`].
+#[derive(Debug, Clone)]
+pub struct ContextMessageBuilder<T: Config> {
+	role: Option<WrapperRole>,
+	content: String,
+	account_id: Option<String>,
+	timestamp: Option<chrono::DateTime<chrono::Utc>>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config> Default for ContextMessageBuilder<T> {
+	fn default() -> Self {
+		Self {
+			role: None,
+			content: String::new(),
+			account_id: None,
+			timestamp: None,
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: Config> ContextMessageBuilder<T> {
+	pub fn role(mut self, role: WrapperRole) -> Self {
+		self.role = Some(role);
+		self
+	}
+
+	pub fn content(mut self, content: &str) -> Self {
+		self.content = content.to_string();
+		self
+	}
+
+	pub fn account_id(mut self, account_id: &str) -> Self {
+		self.account_id = Some(account_id.to_string());
+		self
+	}
+
+	/// Defaults to the time [`ContextMessageBuilder::build`] is called.
+	pub fn timestamp(mut self, timestamp: chrono::DateTime<chrono::Utc>) -> Self {
+		self.timestamp = Some(timestamp);
+		self
+	}
+
+	/// Build the [`ContextMessage`].
+	///
+	/// Fails with [`WeaveError::InvalidMessages`] if the `content` is empty, or the `role` is
+	/// missing or is [`Role::Tool`], which is not supported.
+	pub fn build(self) -> Result<ContextMessage<T>> {
+		let mut errors = vec![];
+
+		if self.content.trim().is_empty() {
+			errors.push(MessageValidationError::EmptyContent);
+		}
+
+		match &self.role {
+			Some(WrapperRole::Role(Role::Tool)) =>
+				errors.push(MessageValidationError::InvalidRole(format!("{:?}", Role::Tool))),
+			Some(_) => {},
+			None => errors.push(MessageValidationError::InvalidRole("missing".to_string())),
+		}
+
+		match (self.role, errors.is_empty()) {
+			(Some(role), true) => Ok(ContextMessage::new(
+				role,
+				self.content,
+				self.account_id,
+				self.timestamp.unwrap_or_else(chrono::Utc::now).to_rfc3339(),
+			)),
+			_ => {
+				error!("Invalid message: {:?}", errors);
+				Err(LoomError::from(WeaveError::InvalidMessages(errors)).into())
+			},
+		}
+	}
+}
+
+impl<T: Config> ContextMessage<T> {
+	/// Build a user message from the parts of an OpenAI user message.
+	///
+	/// Text parts are joined into the `content`. Image parts are kept as `content_parts` with the
+	/// `multimodal` feature and dropped otherwise.
+	fn from_user_message_parts(
+		parts: Vec<ChatCompletionRequestMessageContentPart>,
+		account_id: Option<String>,
+	) -> Self {
+		let mut texts = vec![];
+		#[cfg(feature = "multimodal")]
+		let mut content_parts = vec![];
+
+		for part in parts {
+			match part {
+				ChatCompletionRequestMessageContentPart::Text(part) => texts.push(part.text),
+				#[cfg(feature = "multimodal")]
+				ChatCompletionRequestMessageContentPart::Image(part) =>
+					content_parts.push(MessageContent::ImageUrl(part.image_url.url)),
+				#[cfg(not(feature = "multimodal"))]
+				ChatCompletionRequestMessageContentPart::Image(_) =>
+					warn!("Dropping image part, enable the multimodal feature to keep it"),
+			}
+		}
+
+		let msg = Self::new(
+			WrapperRole::Role(Role::User),
+			texts.join("\n"),
+			account_id,
+			chrono::Utc::now().to_rfc3339(),
+		);
+
+		#[cfg(feature = "multimodal")]
+		let msg = match content_parts.is_empty() {
+			true => msg,
+			false => msg.with_content_parts(content_parts),
+		};
+
+		msg
+	}
+}
+
+/// Converts a [`ContextMessage`] into an OpenAI message.
+///
+/// The `account_id` becomes the `name` of the message. For tool messages, it is used as the
+/// `tool_call_id`.
+impl<T: Config> From<ContextMessage<T>> for ChatCompletionRequestMessage {
+	fn from(msg: ContextMessage<T>) -> Self {
+		#[cfg(feature = "multimodal")]
+		let user_content = msg.to_user_message_content();
+		#[cfg(not(feature = "multimodal"))]
+		let user_content = ChatCompletionRequestUserMessageContent::Text(msg.content.clone());
+
+		let WrapperRole::Role(role) = msg.role;
+		match role {
+			Role::System => Self::System(ChatCompletionRequestSystemMessage {
+				content: msg.content,
+				role,
+				name: msg.account_id,
+			}),
+			Role::User => Self::User(ChatCompletionRequestUserMessage {
+				content: user_content,
+				role,
+				name: msg.account_id.or_else(|| persona_name(T::USER_NAME)),
+			}),
+			Role::Assistant => Self::Assistant(ChatCompletionRequestAssistantMessage {
+				content: Some(msg.content),
+				role,
+				name: msg.account_id.or_else(|| persona_name(T::ASSISTANT_NAME)),
+				..Default::default()
+			}),
+			Role::Tool => Self::Tool(ChatCompletionRequestToolMessage {
+				role,
+				content: msg.content,
+				tool_call_id: msg.account_id.unwrap_or_default(),
+			}),
+			Role::Function => Self::Function(ChatCompletionRequestFunctionMessage {
+				role,
+				content: Some(msg.content),
+				name: msg.account_id.unwrap_or_default(),
+			}),
+		}
 	}
 }
 
+/// Split a chat log line into its role and content if it has a `{role}: ` prefix.
+fn parse_chat_log_line(line: &str) -> Option<(Role, &str)> {
+	let (role, content) = line.split_once(':')?;
+	let role = match role.trim().to_lowercase().as_str() {
+		"user" => Role::User,
+		"assistant" => Role::Assistant,
+		"system" => Role::System,
+		_ => return None,
+	};
+
+	Some((role, content.trim_start()))
+}
+
+/// Category of `categories` matching `reply`, ignoring case and surrounding punctuation.
+///
+/// Falls back to the longest category contained in `reply` when it is not exactly a category.
+fn match_category<'a>(reply: &str, categories: &[&'a str]) -> Option<&'a str> {
+	let reply = reply.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+
+	categories
+		.iter()
+		.copied()
+		.find(|category| category.to_lowercase() == reply)
+		.or_else(|| {
+			categories
+				.iter()
+				.copied()
+				.filter(|category| !category.is_empty() && reply.contains(&category.to_lowercase()))
+				.max_by_key(|category| category.len())
+		})
+}
+
+/// `name` of a persona, unless it is empty.
+fn persona_name(name: &str) -> Option<String> {
+	(!name.is_empty()).then(|| name.to_string())
+}
+
 /// Represents a single part of a conversation containing a list of messages along with other
 /// metadata.
 ///
@@ -283,12 +1131,16 @@ impl<T: Config> ContextMessage<T> {
 /// The total number of `context_tokens` is tracked when [`Loom::weave`] is executed and if it
 /// exceeds the maximum number of tokens allowed for the current GPT [`Config::PromptModel`], then a
 /// summary is generated and a new [`TapestryFragment`] instance is created.
-#[derive(Debug, Serialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(bound = "")]
 pub struct TapestryFragment<T: Config> {
 	/// Total number of _GPT tokens_ in the `context_messages`.
 	pub context_tokens: <T::PromptModel as Llm<T>>::Tokens,
 	/// List of [`ContextMessage`]s that represents the message history.
 	pub context_messages: Vec<ContextMessage<T>>,
+	/// Instance this tapestry fragment was branched from by [`Loom::continue_from`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub parent_instance: Option<u64>,
 }
 
 impl<T: Config> TapestryFragment<T> {
@@ -340,20 +1192,477 @@ impl<T: Config> TapestryFragment<T> {
 
 		Ok(())
 	}
-}
 
-/// The machine that drives all of the core methods that should be used across any service
-/// that needs to prompt LLM and receive a response.
-///
-/// This is implemented over the [`Config`] trait.
-#[async_trait]
-pub trait Loom<T: Config> {
-	/// Prompt LLM Weaver for a response for [`TapestryId`].
+	/// Remove messages until `context_tokens` is at most `max_tokens`, in the order of
+	/// [`Config::CONTEXT_TRUNCATION_STRATEGY`].
 	///
-	/// Prompts LLM with the current [`TapestryFragment`] instance and the new `msgs`.
+	/// A leading system message and pinned messages are always preserved, even if they alone
+	/// exceed `max_tokens`.
+	pub fn truncate_to_tokens(mut self, max_tokens: PromptModelTokens<T>) -> Self {
+		let preserved =
+			self.context_messages
+				.first()
+				.is_some_and(|m| matches!(m.role, WrapperRole::Role(Role::System))) as usize;
+
+		while self.context_tokens > max_tokens {
+			let removable = (preserved..self.context_messages.len())
+				.filter(|i| !self.context_messages[*i].pinned)
+				.collect::<Vec<_>>();
+
+			let index = match T::CONTEXT_TRUNCATION_STRATEGY {
+				ContextTruncationStrategy::MiddleOut => {
+					let center = (preserved + self.context_messages.len()) / 2;
+					removable.into_iter().min_by_key(|i| i.abs_diff(center))
+				},
+				ContextTruncationStrategy::ByTokenDensity =>
+					removable.into_iter().rev().max_by_key(|i| {
+						T::PromptModel::count_tokens(&self.context_messages[*i].content)
+							.unwrap_or_default()
+					}),
+				ContextTruncationStrategy::OldestFirst => removable.first().copied(),
+			};
+			let Some(index) = index else {
+				break;
+			};
+
+			let msg = self.context_messages.remove(index);
+			let msg_tokens = T::PromptModel::count_tokens(&msg.content).unwrap_or_default();
+			self.context_tokens = self.context_tokens.saturating_sub(&msg_tokens);
+		}
+
+		if self.context_tokens > max_tokens {
+			warn!(
+				"Preserved messages of {} tokens exceed the {} max tokens",
+				self.context_tokens, max_tokens
+			);
+		}
+
+		self
+	}
+
+	/// Keep the system messages and the messages most relevant to `query` which fit within
+	/// `max_tokens`, in their original order.
 	///
-	/// A summary will be generated of the current [`TapestryFragment`] instance if the total number
-	/// of tokens in the `context_messages` exceeds the maximum number of tokens allowed for the
+	/// Messages are scored against `query` with [`relevance::bm25_scores`] and kept from the
+	/// highest score down as long as they fit. System messages are always kept, even if they alone
+	/// exceed `max_tokens`. The `context_tokens` are the tokens of the kept messages.
+	#[cfg(feature = "relevance-compression")]
+	pub fn compress_by_relevance(
+		mut self,
+		query: &str,
+		max_tokens: PromptModelTokens<T>,
+	) -> TapestryFragment<T> {
+		let is_system = |m: &ContextMessage<T>| matches!(m.role, WrapperRole::Role(Role::System));
+
+		let scores =
+			relevance::bm25_scores(query, self.context_messages.iter().map(|m| m.content.as_str()));
+
+		let mut tokens = PromptModelTokens::<T>::default();
+		let mut keep = vec![false; self.context_messages.len()];
+		for (i, m) in self.context_messages.iter().enumerate().filter(|(_, m)| is_system(m)) {
+			tokens = tokens
+				.saturating_add(&T::PromptModel::count_tokens(&m.content).unwrap_or_default());
+			keep[i] = true;
+		}
+
+		let mut ranked = (0..self.context_messages.len())
+			.filter(|i| !is_system(&self.context_messages[*i]))
+			.collect::<Vec<_>>();
+		// Most relevant first, the most recent message first among equally relevant ones
+		ranked.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]).then(b.cmp(a)));
+		for i in ranked {
+			let msg_tokens =
+				T::PromptModel::count_tokens(&self.context_messages[i].content).unwrap_or_default();
+			if tokens.saturating_add(&msg_tokens) <= max_tokens {
+				tokens = tokens.saturating_add(&msg_tokens);
+				keep[i] = true;
+			}
+		}
+
+		let mut keep = keep.into_iter();
+		self.context_messages.retain(|_| keep.next().unwrap_or(false));
+		self.context_tokens = tokens;
+
+		self
+	}
+
+	/// Truncate the content of every message in `context_messages` to `max_words` words.
+	///
+	/// See [`ContextMessage::truncate_content`]. The `context_tokens` are recounted afterwards, see
+	/// [`TapestryFragment::recount_tokens`].
+	pub fn truncate_all_messages(&mut self, max_words: usize) -> &mut Self {
+		self.context_messages = std::mem::take(&mut self.context_messages)
+			.into_iter()
+			.map(|msg| msg.truncate_content(max_words))
+			.collect();
+		if let Err(e) = self.recount_tokens() {
+			warn!("Failed to recount tokens of truncated messages: {}", e);
+		}
+		self
+	}
+
+	/// New tapestry fragment with only `context_messages[start..end]`, with its `context_tokens`
+	/// recounted.
+	///
+	/// Fails with [`WeaveError::InvalidRange`] if `start >= end` or `end` exceeds the number of
+	/// messages.
+	pub fn window(&self, start: usize, end: usize) -> Result<TapestryFragment<T>> {
+		let len = self.context_messages.len();
+		if start >= end || end > len {
+			return Err(LoomError::from(WeaveError::InvalidRange { start, end, len }).into());
+		}
+
+		let mut tapestry_fragment = TapestryFragment {
+			context_tokens: Default::default(),
+			context_messages: self.context_messages[start..end].to_vec(),
+			parent_instance: self.parent_instance,
+		};
+		tapestry_fragment.recount_tokens()?;
+
+		Ok(tapestry_fragment)
+	}
+
+	/// Pin the message at `index` of `context_messages`.
+	///
+	/// [`Loom::weave`] sends pinned messages right after the instructions and carries them over to
+	/// the new tapestry fragment when summarizing instead of summarizing them.
+	///
+	/// Fails with [`WeaveError::InvalidIndex`] if `index` is out of bounds.
+	pub fn pin_message(&mut self, index: usize) -> Result<()> {
+		self.set_pinned(index, true)
+	}
+
+	/// Unpin the message at `index` of `context_messages`, see
+	/// [`TapestryFragment::pin_message`].
+	///
+	/// Fails with [`WeaveError::InvalidIndex`] if `index` is out of bounds.
+	pub fn unpin_message(&mut self, index: usize) -> Result<()> {
+		self.set_pinned(index, false)
+	}
+
+	fn set_pinned(&mut self, index: usize, pinned: bool) -> Result<()> {
+		let len = self.context_messages.len();
+		let msg = self
+			.context_messages
+			.get_mut(index)
+			.ok_or_else(|| LoomError::from(WeaveError::InvalidIndex { index, len }))?;
+		msg.pinned = pinned;
+
+		Ok(())
+	}
+
+	/// Split `context_messages` into the pinned and unpinned messages, preserving their order.
+	fn partition_pinned(&self) -> (Vec<ContextMessage<T>>, Vec<ContextMessage<T>>) {
+		self.context_messages.iter().cloned().partition(|msg| msg.pinned)
+	}
+
+	/// Sort `context_messages` by their `timestamp`, oldest first.
+	///
+	/// Useful after injecting messages out of order, for example when importing them from another
+	/// system. Messages with unparseable timestamps are placed at the end. The relative order of
+	/// messages with equal timestamps is preserved.
+	///
+	/// Returns the number of messages that changed position.
+	pub fn reorder_messages(&mut self) -> Result<usize> {
+		let mut msgs = std::mem::take(&mut self.context_messages)
+			.into_iter()
+			.enumerate()
+			.map(|(index, msg)| {
+				let timestamp = chrono::DateTime::parse_from_rfc3339(&msg.timestamp).ok();
+				if timestamp.is_none() {
+					warn!("Unparseable timestamp {:?}, placing message at the end", msg.timestamp);
+				}
+				(timestamp, index, msg)
+			})
+			.collect::<Vec<_>>();
+
+		msgs.sort_by_key(|(timestamp, index, _)| (timestamp.is_none(), *timestamp, *index));
+
+		let moved = msgs
+			.iter()
+			.enumerate()
+			.filter(|(position, (_, index, _))| position != index)
+			.count();
+		self.context_messages = msgs.into_iter().map(|(_, _, msg)| msg).collect();
+
+		Ok(moved)
+	}
+
+	/// Sort `context_messages` by the precedence of their role, see [`WrapperRole`].
+	///
+	/// The sort is stable, so messages of the same role keep their relative order. Call
+	/// [`TapestryFragment::reorder_messages`] first to order them by `timestamp`.
+	pub fn sort_by_role_precedence(&mut self) {
+		self.context_messages.sort_by(|a, b| a.role.cmp(&b.role));
+	}
+
+	/// Compare this tapestry fragment with `other`, for example the same tapestry fragment
+	/// before and after summarization.
+	///
+	/// Messages are matched by their `content`.
+	pub fn diff<'a>(&'a self, other: &'a TapestryFragment<T>) -> FragmentDiff<'a, T> {
+		let contents = |tapestry_fragment: &'a TapestryFragment<T>| {
+			tapestry_fragment
+				.context_messages
+				.iter()
+				.map(|m| m.content.as_str())
+				.collect::<HashSet<_>>()
+		};
+		let (self_contents, other_contents) = (contents(self), contents(other));
+
+		FragmentDiff {
+			added: other
+				.context_messages
+				.iter()
+				.filter(|m| !self_contents.contains(m.content.as_str()))
+				.collect(),
+			removed: self
+				.context_messages
+				.iter()
+				.filter(|m| !other_contents.contains(m.content.as_str()))
+				.collect(),
+			token_delta: other.context_tokens.to_i64().unwrap_or_default() -
+				self.context_tokens.to_i64().unwrap_or_default(),
+		}
+	}
+
+	/// Report how much this tapestry fragment shrank compared to `previous`, e.g. the tapestry
+	/// fragment it was summarized from.
+	pub fn compression_report(&self, previous: &TapestryFragment<T>) -> CompressionReport<T> {
+		let compression_ratio = match previous.context_tokens.to_f32() {
+			Some(tokens_before) if tokens_before > 0.0 =>
+				self.context_tokens.to_f32().unwrap_or_default() / tokens_before,
+			_ => 1.0,
+		};
+
+		CompressionReport {
+			messages_before: previous.context_messages.len(),
+			messages_after: self.context_messages.len(),
+			tokens_before: previous.context_tokens,
+			tokens_after: self.context_tokens,
+			compression_ratio,
+		}
+	}
+
+	/// Serialize the tapestry fragment as MessagePack.
+	#[cfg(feature = "msgpack")]
+	pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+		Ok(StorageFormat::MessagePack.serialize(self)?)
+	}
+
+	/// Deserialize a tapestry fragment from MessagePack.
+	#[cfg(feature = "msgpack")]
+	pub fn from_msgpack(bytes: &[u8]) -> Result<Self> {
+		Ok(StorageFormat::MessagePack.deserialize(bytes)?)
+	}
+
+	/// Total number of words across all `context_messages`.
+	///
+	/// See [`ContextMessage::word_count`].
+	pub fn total_word_count(&self) -> usize {
+		self.context_messages.iter().map(ContextMessage::word_count).sum()
+	}
+
+	/// Parse a plain text chat log made of `{role}: {content}` lines, such as `User: Hello`.
+	///
+	/// The `User`, `Assistant` and `System` roles are recognized, case-insensitive. Lines without
+	/// a role prefix are added to the content of the previous message, and skipped if no message
+	/// precedes them. All messages are timestamped with the current time.
+	///
+	/// Fails with [`ParseError::UnrecognizedFormat`] if none of the first 10 lines has a role
+	/// prefix.
+	pub fn from_chat_log(text: &str) -> Result<Self> {
+		if !text.lines().take(10).any(|line| parse_chat_log_line(line).is_some()) {
+			error!("Unrecognized chat log format");
+			return Err(LoomError::from(ParseError::UnrecognizedFormat).into());
+		}
+
+		let mut msgs: Vec<(Role, String)> = vec![];
+		for line in text.lines() {
+			match (parse_chat_log_line(line), msgs.last_mut()) {
+				(Some((role, content)), _) => msgs.push((role, content.to_string())),
+				(None, Some((_, content))) => {
+					content.push('\n');
+					content.push_str(line);
+				},
+				(None, None) => debug!("Skipping chat log line without role: {}", line),
+			}
+		}
+
+		let timestamp = chrono::Utc::now().to_rfc3339();
+		let mut tapestry_fragment = Self::new();
+		tapestry_fragment.extend_messages(
+			msgs.into_iter()
+				.map(|(role, content)| {
+					ContextMessage::new(
+						WrapperRole::Role(role),
+						content.trim_end().to_string(),
+						None,
+						timestamp.clone(),
+					)
+				})
+				.collect(),
+		)?;
+
+		Ok(tapestry_fragment)
+	}
+
+	/// Convert the `context_messages` into OpenAI request messages, e.g. to build custom prompts
+	/// on top of stored tapestry fragments.
+	///
+	/// Uses the `From<ContextMessage>` conversion, which cannot fail.
+	pub fn into_openai_messages(self) -> Vec<ChatCompletionRequestMessage> {
+		self.context_messages.into_iter().map(Into::into).collect()
+	}
+
+	/// Messages as a fine-tuning dataset in the JSONL format expected by OpenAI.
+	///
+	/// Emits one `{"messages": [...]}` line per user message directly followed by an assistant
+	/// message, each terminated by a newline. All other messages are skipped.
+	///
+	/// See [`training::export_training_dataset`] to export whole tapestries.
+	pub fn as_training_jsonl(&self) -> String {
+		self.context_messages
+			.windows(2)
+			.filter(|pair| {
+				matches!(
+					(&pair[0].role, &pair[1].role),
+					(WrapperRole::Role(Role::User), WrapperRole::Role(Role::Assistant))
+				)
+			})
+			.map(|pair| {
+				let line = serde_json::json!({
+					"messages": [
+						{ "role": USER_ROLE, "content": pair[0].content },
+						{ "role": ASSISTANT_ROLE, "content": pair[1].content },
+					]
+				});
+				format!("{line}\n")
+			})
+			.collect()
+	}
+
+	/// Approximate number of bytes taken by this tapestry fragment, for capacity planning.
+	///
+	/// Sums the lengths of the `content`, `account_id`, `role` and `timestamp` of all
+	/// `context_messages`, plus 8 bytes for `context_tokens`.
+	pub fn approximate_size_bytes(&self) -> usize {
+		let messages_size: usize = self
+			.context_messages
+			.iter()
+			.map(|msg| {
+				msg.content.len() +
+					msg.account_id.as_ref().map_or(0, String::len) +
+					msg.role.as_str().len() +
+					msg.timestamp.len()
+			})
+			.sum();
+
+		messages_size + 8
+	}
+
+	/// Remove the `context_messages` with the same [`ContextMessage::hash`] as an earlier message,
+	/// then recount the `context_tokens`.
+	///
+	/// Returns the number of messages removed.
+	pub fn dedup(&mut self) -> Result<usize> {
+		let len = self.context_messages.len();
+		let mut seen = HashSet::new();
+		self.context_messages.retain(|msg| seen.insert(msg.hash()));
+
+		let removed = len - self.context_messages.len();
+		if removed > 0 {
+			self.recount_tokens()?;
+		}
+
+		Ok(removed)
+	}
+
+	/// Recount the tokens of all `context_messages` and overwrite `context_tokens` with the
+	/// result.
+	///
+	/// Used to correct stale token counts, for example when a fragment was saved using a
+	/// different tokenizer.
+	///
+	/// Returns `true` if `context_tokens` was changed.
+	pub fn recount_tokens(&mut self) -> Result<bool> {
+		let mut tokens = PromptModelTokens::<T>::default();
+		for m in &self.context_messages {
+			tokens = tokens.checked_add(&T::PromptModel::count_tokens(&m.content)?).ok_or_else(
+				|| {
+					LoomError::from(WeaveError::BadConfig(
+						"Number of tokens exceeds max tokens for model".to_string(),
+					))
+				},
+			)?;
+		}
+
+		let changed = tokens != self.context_tokens;
+		self.context_tokens = tokens;
+
+		Ok(changed)
+	}
+
+	/// Estimate the cost of the `context_messages` given prices per 1000 tokens.
+	///
+	/// Assistant messages are counted as completion tokens, all other messages as prompt tokens.
+	pub fn estimate_cost(
+		&self,
+		prompt_token_price: f64,
+		completion_token_price: f64,
+	) -> Result<f64> {
+		let (mut prompt_tokens, mut completion_tokens) = (0u64, 0u64);
+		for m in &self.context_messages {
+			let tokens = T::PromptModel::count_tokens(&m.content)?.to_u64().unwrap_or_default();
+			match m.role {
+				WrapperRole::Role(Role::Assistant) =>
+					completion_tokens = completion_tokens.saturating_add(tokens),
+				_ => prompt_tokens = prompt_tokens.saturating_add(tokens),
+			}
+		}
+
+		Ok((prompt_tokens as f64 * prompt_token_price +
+			completion_tokens as f64 * completion_token_price) /
+			1000.0)
+	}
+
+	/// Estimate the cost of the `context_messages` using the [`Llm::default_pricing`] of
+	/// `model`.
+	///
+	/// Returns `None` if `model` has no default pricing. Logs a warning if the pricing is older
+	/// than [`PRICING_STALE_AFTER_SECS`], see [`Llm::PRICING_UPDATED_AT`].
+	pub fn estimate_default_cost(&self, model: &T::PromptModel) -> Result<Option<f64>> {
+		let Some((prompt_token_price, completion_token_price)) = model.default_pricing() else {
+			return Ok(None);
+		};
+
+		if let Some(updated_at) = <T::PromptModel as Llm<T>>::PRICING_UPDATED_AT {
+			let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+			if now.saturating_sub(updated_at) > PRICING_STALE_AFTER_SECS {
+				warn!(
+					"Default pricing of {} is deprecated: last updated at {}",
+					model.name(),
+					updated_at
+				);
+			}
+		}
+
+		self.estimate_cost(prompt_token_price, completion_token_price).map(Some)
+	}
+}
+
+/// The machine that drives all of the core methods that should be used across any service
+/// that needs to prompt LLM and receive a response.
+///
+/// This is implemented over the [`Config`] trait.
+#[async_trait]
+pub trait Loom<T: Config> {
+	/// Prompt LLM Weaver for a response for [`TapestryId`].
+	///
+	/// Prompts LLM with the current [`TapestryFragment`] instance and the new `msgs`.
+	///
+	/// A summary will be generated of the current [`TapestryFragment`] instance if the total number
+	/// of tokens in the `context_messages` exceeds the maximum number of tokens allowed for the
 	/// current [`Config::PromptModel`] or custom max tokens. This threshold is affected by the
 	/// [`Config::TOKEN_THRESHOLD_PERCENTILE`].
 	///
@@ -365,146 +1674,1320 @@ pub trait Loom<T: Config> {
 	/// - `instructions`: The instruction message to be used for the current [`TapestryFragment`]
 	///   instance.
 	/// - `msgs`: The messages to prompt the LLM with.
-	#[instrument]
+	/// - `extra_context`: Messages inserted between the instructions and the tapestry fragment
+	///   messages, such as retrieved documents. They count towards the token limit but are never
+	///   saved.
+	///
+	/// The content of the prompt and response is only logged if [`Config::LOG_PROMPTS`] and
+	/// [`Config::LOG_RESPONSES`] are enabled.
+	#[instrument(skip(instructions, msgs, extra_context))]
 	async fn weave<TID: TapestryId>(
 		prompt_llm_config: LlmConfig<T, T::PromptModel>,
 		summary_llm_config: LlmConfig<T, T::SummaryModel>,
 		tapestry_id: TID,
 		instructions: String,
 		mut msgs: Vec<ContextMessage<T>>,
+		extra_context: Option<Vec<ContextMessage<T>>>,
 	) -> Result<(<<T as Config>::PromptModel as Llm<T>>::Response, u64, bool)> {
-		let instructions_ctx_msg =
-			Self::build_context_message(SYSTEM_ROLE.into(), instructions, None);
-		let instructions_req_msg: PromptModelRequest<T> = instructions_ctx_msg.clone().into();
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let instructions_ctx_msg =
+				Self::build_context_message(SYSTEM_ROLE.into(), instructions, None);
+
+			let prepared = prepare_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				summary_llm_config,
+				&tapestry_id,
+				&instructions_ctx_msg,
+				&msgs,
+				extra_context.unwrap_or_default(),
+				None,
+			)
+			.await?;
+			let was_summary_generated = prepared.was_summary_generated;
+
+			let response = prompt_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				&tapestry_id,
+				&prepared,
+				&instructions_ctx_msg,
+				&msgs,
+			)
+			.await?;
+
+			// Add LLM response to the tapestry fragment messages to save
+			let response_content: String = response.clone().into().unwrap_or_default();
+			msgs.push(Self::build_context_message(ASSISTANT_ROLE.into(), response_content, None));
+
+			let tapestry_fragment_id =
+				save_weave::<T, Self, TID>(&tapestry_id, prepared, msgs).await?;
+
+			Ok((response, tapestry_fragment_id, was_summary_generated))
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Same as [`Loom::weave`] but fails with [`WeaveError::Timeout`] if it does not complete
+	/// within `timeout`.
+	///
+	/// Only the prompt is timed out: once the tapestry fragment is being saved, [`Loom::weave`]
+	/// runs to completion and returns the saved response even after `timeout`. Saving the tapestry
+	/// fragment, counting the call and releasing the lock are each bounded by
+	/// [`Config::STORAGE_TIMEOUT_MS`], so the call can take up to `timeout` plus three times
+	/// [`Config::STORAGE_TIMEOUT_MS`].
+	async fn weave_with_timeout<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+		extra_context: Option<Vec<ContextMessage<T>>>,
+		timeout: Duration,
+	) -> Result<(<<T as Config>::PromptModel as Llm<T>>::Response, u64, bool)> {
+		interruptible_weave(
+			Self::weave(
+				prompt_llm_config,
+				summary_llm_config,
+				tapestry_id,
+				instructions,
+				msgs,
+				extra_context,
+			),
+			async move {
+				tokio::time::sleep(timeout).await;
+				error!("Weave timed out after {:?}", timeout);
+				LoomError::from(WeaveError::Timeout(timeout))
+			},
+		)
+		.await
+	}
+
+	/// Same as [`Loom::weave`] but fails with [`WeaveError::Cancelled`] once `cancel` is
+	/// cancelled, e.g. when a user stops the generation.
+	///
+	/// Like [`Loom::weave_with_timeout`], cancelling once the tapestry fragment is being saved
+	/// waits for [`Loom::weave`] to complete and returns the saved response.
+	async fn weave_with_cancellation<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+		extra_context: Option<Vec<ContextMessage<T>>>,
+		cancel: CancellationToken,
+	) -> Result<(<<T as Config>::PromptModel as Llm<T>>::Response, u64, bool)> {
+		interruptible_weave(
+			Self::weave(
+				prompt_llm_config,
+				summary_llm_config,
+				tapestry_id,
+				instructions,
+				msgs,
+				extra_context,
+			),
+			async move {
+				cancel.cancelled().await;
+				warn!("Weave was cancelled");
+				LoomError::from(WeaveError::Cancelled)
+			},
+		)
+		.await
+	}
+
+	/// Same as [`Loom::weave`] but recalls the past user messages most similar to the user
+	/// messages in `msgs` from `memory` when [`Config::ENABLE_LONG_TERM_MEMORY`] is enabled.
+	///
+	/// At most [`Config::MEMORY_TOP_K`] messages are recalled and sent to the LLM as extra
+	/// context, without being stored again. The user messages in `msgs` are remembered once the
+	/// response is saved.
+	///
+	/// # Parameters
+	///
+	/// Same as [`Loom::weave`] with the addition of:
+	///
+	/// - `memory`: The [`memory::EmbeddingMemory`] to recall from and remember in.
+	/// - `embedder`: Computes the embeddings of the user messages.
+	#[cfg(feature = "embeddings")]
+	async fn weave_with_memory<TID: TapestryId, E: memory::Embedder>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+		memory: &memory::EmbeddingMemory<T>,
+		embedder: &E,
+	) -> Result<(<<T as Config>::PromptModel as Llm<T>>::Response, u64, bool)> {
+		if !T::ENABLE_LONG_TERM_MEMORY {
+			return Self::weave(
+				prompt_llm_config,
+				summary_llm_config,
+				tapestry_id,
+				instructions,
+				msgs,
+				None,
+			)
+			.await;
+		}
+
+		let mut embedded_msgs = vec![];
+		for msg in msgs.iter().filter(|msg| matches!(msg.role, WrapperRole::Role(Role::User))) {
+			embedded_msgs.push((embedder.embed(&msg.content).await?, msg.clone()));
+		}
+
+		// Recall using the latest user message before remembering the new ones
+		let recalled = match embedded_msgs.last() {
+			Some((embedding, _)) => memory.recall(&tapestry_id, embedding, T::MEMORY_TOP_K),
+			None => vec![],
+		};
+		debug!("Recalled {} messages for {}", recalled.len(), tapestry_id.base_key());
+
+		let extra_context = (!recalled.is_empty()).then(|| {
+			let content = recalled
+				.iter()
+				.map(|msg| format!("{}: {}", msg.role.as_str(), msg.content))
+				.collect::<Vec<_>>()
+				.join("\n");
+
+			vec![Self::build_context_message(
+				SYSTEM_ROLE.into(),
+				format!("Relevant messages from earlier in the conversation:\n{}", content),
+				None,
+			)]
+		});
+
+		let output = Self::weave(
+			prompt_llm_config,
+			summary_llm_config,
+			tapestry_id.clone(),
+			instructions,
+			msgs,
+			extra_context,
+		)
+		.await?;
+
+		for (embedding, msg) in embedded_msgs {
+			memory.remember(&tapestry_id, embedding, msg);
+		}
+
+		Ok(output)
+	}
+
+	/// Same as [`Loom::weave`] but sends `retrieved_context`, such as document chunks retrieved
+	/// for Retrieval-Augmented Generation, to the LLM.
+	///
+	/// Each chunk is sent as a `Context:\n{chunk}` system message of the extra context. The chunks
+	/// count against the token budget but are not stored. Only the first
+	/// [`Config::MAX_RETRIEVED_CHUNKS`] chunks are sent.
+	///
+	/// # Parameters
+	///
+	/// Same as [`Loom::weave`] with the addition of:
+	///
+	/// - `retrieved_context`: The retrieved chunks, most relevant first.
+	async fn weave_with_retrieved_context<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+		extra_context: Option<Vec<ContextMessage<T>>>,
+		retrieved_context: Vec<String>,
+	) -> Result<(<<T as Config>::PromptModel as Llm<T>>::Response, u64, bool)> {
+		if retrieved_context.len() > T::MAX_RETRIEVED_CHUNKS {
+			warn!(
+				"Dropping {} of {} retrieved chunks",
+				retrieved_context.len() - T::MAX_RETRIEVED_CHUNKS,
+				retrieved_context.len()
+			);
+		}
+
+		let mut extra_context = extra_context.unwrap_or_default();
+		extra_context.extend(retrieved_context.into_iter().take(T::MAX_RETRIEVED_CHUNKS).map(
+			|chunk| {
+				Self::build_context_message(SYSTEM_ROLE.into(), format!("Context:\n{chunk}"), None)
+			},
+		));
+
+		Self::weave(
+			prompt_llm_config,
+			summary_llm_config,
+			tapestry_id,
+			instructions,
+			msgs,
+			(!extra_context.is_empty()).then_some(extra_context),
+		)
+		.await
+	}
+
+	/// Prompt LLM Weaver for a response for [`TapestryId`], letting the LLM call `functions`.
+	///
+	/// Executes [`Loom::weave`] in a loop. Whenever the response contains a function call (see
+	/// [`Llm::function_call`]), `executor` is called with the function name and its JSON encoded
+	/// arguments and its result is sent back to the LLM as a function message. This repeats until
+	/// the LLM responds without a function call, or fails with [`WeaveError::MaxToolIterations`]
+	/// after [`Config::MAX_TOOL_ITERATIONS`] function calls.
+	///
+	/// The function messages and responses are stored like any other message.
+	///
+	/// # Parameters
+	///
+	/// Same as [`Loom::weave`] with the addition of:
+	///
+	/// - `functions`: The functions the LLM may call. See [`Llm::params_with_functions`].
+	/// - `executor`: Executes a function call, mapping `(function_name, arguments_json)` to the
+	///   function result.
+	async fn weave_agent<TID, F, Fut>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+		functions: Vec<FunctionSpec>,
+		executor: F,
+	) -> Result<(<<T as Config>::PromptModel as Llm<T>>::Response, u64, bool)>
+	where
+		TID: TapestryId,
+		F: Fn(String, String) -> Fut + Send + Sync,
+		Fut: Future<Output = Result<String>> + Send,
+	{
+		if !functions.is_empty() && !prompt_llm_config.model.supports_function_calling() {
+			error!("{} does not support function calling", prompt_llm_config.model.name());
+			return Err(LoomError::from(WeaveError::UnsupportedModelCapability(
+				"function calling".to_string(),
+			))
+			.into());
+		}
+
+		let prompt_params = prompt_llm_config
+			.model
+			.params_with_functions(&prompt_llm_config.params, &functions);
+
+		let mut msgs = msgs;
+		let mut was_summary_generated = false;
+		let mut tool_iterations = 0;
+
+		loop {
+			let (response, tapestry_fragment_id, summarized) = Self::weave(
+				LlmConfig { model: prompt_llm_config.model, params: prompt_params.clone() },
+				LlmConfig {
+					model: summary_llm_config.model,
+					params: summary_llm_config.params.clone(),
+				},
+				tapestry_id.clone(),
+				instructions.clone(),
+				msgs,
+				None,
+			)
+			.await?;
+			was_summary_generated |= summarized;
+
+			let function_call = match T::PromptModel::function_call(&response) {
+				Some(function_call) => function_call,
+				None => return Ok((response, tapestry_fragment_id, was_summary_generated)),
+			};
+
+			if tool_iterations >= T::MAX_TOOL_ITERATIONS {
+				error!("Exceeded {} tool iterations", T::MAX_TOOL_ITERATIONS);
+				return Err(
+					LoomError::from(WeaveError::MaxToolIterations(T::MAX_TOOL_ITERATIONS)).into()
+				);
+			}
+			tool_iterations += 1;
+
+			debug!("Executing function call {}", function_call.name);
+
+			let result = executor(function_call.name.clone(), function_call.arguments).await?;
+
+			msgs = vec![Self::build_context_message(
+				FUNCTION_ROLE.into(),
+				result,
+				Some(function_call.name),
+			)];
+		}
+	}
+
+	/// Run a scripted conversation, e.g. for automated conversation quality benchmarks.
+	///
+	/// Calls [`Loom::weave`] once per user message of `turns`, in order, so that every turn sees
+	/// the messages and responses of the previous turns as context. Stops at the first failing
+	/// turn, the previous turns remain saved.
+	///
+	/// Returns the responses in the order of `turns`.
+	///
+	/// # Parameters
+	///
+	/// Same as [`Loom::weave`] with the addition of:
+	///
+	/// - `system`: The instructions used for every turn.
+	/// - `turns`: The content of the user message of every turn.
+	async fn multi_turn_eval<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		system: String,
+		turns: Vec<String>,
+	) -> Result<Vec<String>> {
+		let mut responses = Vec::with_capacity(turns.len());
+
+		for (turn, content) in turns.into_iter().enumerate() {
+			debug!("Running evaluation turn {}", turn + 1);
+
+			let (response, _, _) = Self::weave(
+				LlmConfig {
+					model: prompt_llm_config.model,
+					params: prompt_llm_config.params.clone(),
+				},
+				LlmConfig {
+					model: summary_llm_config.model,
+					params: summary_llm_config.params.clone(),
+				},
+				tapestry_id.clone(),
+				system.clone(),
+				vec![Self::build_context_message(USER_ROLE.into(), content, None)],
+				None,
+			)
+			.await?;
+
+			responses.push(response.into().unwrap_or_default());
+		}
+
+		Ok(responses)
+	}
+
+	/// Prompt the LLM using the current [`TapestryFragment`] instance of `tapestry_id` as context
+	/// without saving the new messages or the response.
+	///
+	/// Useful for one-off tasks, such as translations, which should not pollute the stored
+	/// message history. Since nothing is saved, no summary is generated. Instead, the oldest
+	/// messages of the tapestry fragment are left out if the prompt would exceed the maximum
+	/// prompt token limit.
+	///
+	/// # Parameters
+	///
+	/// - `prompt_llm_config`: The [`Config::PromptModel`] to use for prompting LLM.
+	/// - `tapestry_id`: The [`TapestryId`] of the [`TapestryFragment`] to use as context.
+	/// - `instructions`: The instruction message.
+	/// - `msgs`: The messages to prompt the LLM with.
+	async fn weave_ephemeral<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+	) -> Result<<<T as Config>::PromptModel as Llm<T>>::Response> {
+		let messages =
+			Self::build_ephemeral_messages(&tapestry_id, &prompt_llm_config, instructions, msgs)
+				.await?;
+
+		Self::prompt_ephemeral(prompt_llm_config, &tapestry_id, messages).await
+	}
+
+	/// Same as [`Loom::weave_ephemeral`] but returns the cached response content if the same
+	/// model was prompted with the same parameters and messages within [`Config::CACHE_TTL_SECS`].
+	///
+	/// Messages are compared as sent to the LLM by their [`ContextMessage::hash`] and content
+	/// parts, ignoring their timestamps. Responses without content are not cached. Useful for
+	/// retries and idempotent calls which would otherwise pay for the same prompt again.
+	///
+	/// Returns the response content along with whether it was a cache hit.
+	///
+	/// # Parameters
+	///
+	/// Same as [`Loom::weave_ephemeral`] with the addition of:
+	///
+	/// - `cache`: The [`cache::ResponseCache`] to read from and write to.
+	async fn weave_ephemeral_cached<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+		cache: &cache::ResponseCache<T>,
+	) -> Result<(String, bool)> {
+		let model = prompt_llm_config.model.name();
+
+		let messages =
+			Self::build_ephemeral_messages(&tapestry_id, &prompt_llm_config, instructions, msgs)
+				.await?;
+
+		if let Some(content) = cache.get(model, &prompt_llm_config.params, &messages) {
+			debug!("Cache hit for {} prompt", model);
+			return Ok((content, true));
+		}
+
+		let params = prompt_llm_config.params.clone();
+		let Some(content) =
+			Self::prompt_ephemeral(prompt_llm_config, &tapestry_id, messages.clone())
+				.await?
+				.into()
+		else {
+			debug!("Not caching {} response without content", model);
+			return Ok((String::new(), false));
+		};
+		cache.insert(model, &params, &messages, content.clone());
+
+		Ok((content, false))
+	}
+
+	/// Send a minimal prompt to the LLM to establish its connection ahead of time.
+	///
+	/// The first prompt usually suffers from extra latency due to the TLS handshake and connection
+	/// establishment. Calling this during application startup moves that latency out of the first
+	/// [`Loom::weave`], provided the [`Llm`] implementation reuses its connections. Nothing is
+	/// saved.
+	async fn warm_up(prompt_llm_config: LlmConfig<T, T::PromptModel>) -> Result<()> {
+		let warm_up_msg = Self::build_context_message(USER_ROLE.into(), " ".to_string(), None);
+
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::PromptModel>::new();
+		req_msgs.extend(prompt_llm_config.model.ctx_msgs_to_prompt_requests(&[warm_up_msg]));
+
+		prompt_llm_config
+			.model
+			.prompt(
+				false,
+				req_msgs.tokens,
+				req_msgs.into_vec(),
+				&prompt_llm_config.params,
+				PromptModelTokens::<T>::from_u8(1).unwrap(),
+			)
+			.await
+			.map_err(|e| {
+				error!("Failed to warm up {}: {}", prompt_llm_config.model.name(), e);
+				e
+			})?;
+
+		debug!("Warmed up {}", prompt_llm_config.model.name());
+
+		Ok(())
+	}
+
+	/// Seed a [`TapestryId`] with existing message history without prompting the LLM.
+	///
+	/// The `msgs` are prepended to the messages of the current [`TapestryFragment`] instance which
+	/// is then saved. If the resulting tapestry fragment would exceed the maximum prompt token
+	/// limit of `prompt_model` once [`Config::MINIMUM_RESPONSE_LENGTH`] is reserved, messages of
+	/// the current instance are dropped in the order of [`TapestryFragment::truncate_to_tokens`]
+	/// until it fits. The injected `msgs` are never dropped, fails with
+	/// [`WeaveError::ContextExhausted`] if they do not fit.
+	///
+	/// # Parameters
+	///
+	/// - `prompt_model`: The [`Config::PromptModel`] whose token limit the tapestry fragment must
+	///   respect.
+	/// - `tapestry_id`: The [`TapestryId`] to inject the messages into.
+	/// - `msgs`: The historical messages to inject, oldest first.
+	async fn inject_context<TID: TapestryId>(
+		prompt_model: T::PromptModel,
+		tapestry_id: TID,
+		msgs: Vec<ContextMessage<T>>,
+	) -> Result<()> {
+		Self::validate_messages(&prompt_model, &msgs).map_err(|errors| {
+			error!("Refusing to inject invalid messages: {:?}", errors);
+			LoomError::from(WeaveError::InvalidMessages(errors))
+		})?;
+
+		let tapestry_lock = TapestryLock::<T, TID>::acquire(
+			tapestry_id.clone(),
+			Duration::from_millis(T::LOCK_TIMEOUT_MS),
+		)
+		.await?;
+
+		let current_tapestry_fragment = with_storage_timeout::<T, _>(
+			T::Chest::get_tapestry_fragment(tapestry_id.clone(), None),
+		)
+		.await?
+		.unwrap_or_default();
+
+		let mut tapestry_fragment = TapestryFragment::new();
+		tapestry_fragment.extend_messages(Self::redact_messages(Self::analyze_sentiment(msgs)))?;
+
+		let max_tokens = prompt_model
+			.get_max_prompt_token_limit()
+			.saturating_sub(&PromptModelTokens::<T>::from_u64(T::MINIMUM_RESPONSE_LENGTH).unwrap());
+
+		// Drop messages of the current instance to make room for the injected messages
+		let msgs_len = current_tapestry_fragment.context_messages.len();
+		let current_tapestry_fragment = current_tapestry_fragment
+			.truncate_to_tokens(max_tokens.saturating_sub(&tapestry_fragment.context_tokens));
+		let truncated = msgs_len - current_tapestry_fragment.context_messages.len();
+
+		// The injected messages, or the messages preserved by the truncation, do not fit
+		let tokens_available = max_tokens.saturating_sub(&current_tapestry_fragment.context_tokens);
+		if tapestry_fragment.context_tokens > tokens_available {
+			error!(
+				"Injected messages have {} tokens, only {} tokens are available",
+				tapestry_fragment.context_tokens, tokens_available
+			);
+			return Err(LoomError::from(WeaveError::ContextExhausted {
+				message_tokens: tapestry_fragment.context_tokens.to_u64().unwrap_or(u64::MAX),
+				available: tokens_available.to_u64().unwrap_or_default(),
+			})
+			.into());
+		}
+
+		if truncated > 0 {
+			info!(
+				"Truncated {} messages to fit injected context into {}",
+				truncated,
+				tapestry_id.base_key()
+			);
+		}
+
+		tapestry_fragment.extend_messages(current_tapestry_fragment.context_messages)?;
+
+		with_storage_timeout::<T, _>(T::Chest::save_tapestry_fragment(
+			&tapestry_id,
+			tapestry_fragment,
+			false,
+		))
+		.await
+		.map_err(|e| {
+			error!("Failed to save tapestry fragment: {}", e);
+			e
+		})?;
+
+		tapestry_lock.release_or_log().await;
+
+		Ok(())
+	}
+
+	/// Check that `msgs` can be stored, for example before [`Loom::inject_context`].
+	///
+	/// A message is invalid if its content is empty, its role is unsupported, its timestamp is
+	/// not RFC 3339 formatted or in the future, or its content alone exceeds the maximum prompt
+	/// token limit of `prompt_model`.
+	///
+	/// Returns every problem found.
+	fn validate_messages(
+		prompt_model: &T::PromptModel,
+		msgs: &[ContextMessage<T>],
+	) -> std::result::Result<(), Vec<MessageValidationError>> {
+		let limit = prompt_model.get_max_prompt_token_limit();
+		let mut errors = vec![];
+
+		for (index, msg) in msgs.iter().enumerate() {
+			if msg.content.trim().is_empty() {
+				errors.push(MessageValidationError::EmptyContent);
+			}
+
+			let WrapperRole::Role(role) = &msg.role;
+			if matches!(role, Role::Tool) {
+				errors.push(MessageValidationError::InvalidRole(format!("{:?}", role)));
+			}
+
+			match chrono::DateTime::parse_from_rfc3339(&msg.timestamp) {
+				Ok(timestamp) if timestamp <= chrono::Utc::now() => {},
+				_ => errors.push(MessageValidationError::InvalidTimestamp(msg.timestamp.clone())),
+			}
+
+			match T::PromptModel::count_tokens(&msg.content) {
+				Ok(tokens) if tokens <= limit => {},
+				Ok(tokens) => errors.push(MessageValidationError::ContentTooLong {
+					index,
+					tokens: tokens.to_u64().unwrap_or(u64::MAX),
+					limit: limit.to_u64().unwrap_or(u64::MAX),
+				}),
+				// Too many tokens to be counted
+				Err(_) => errors.push(MessageValidationError::ContentTooLong {
+					index,
+					tokens: u64::MAX,
+					limit: limit.to_u64().unwrap_or(u64::MAX),
+				}),
+			}
+		}
+
+		match errors.is_empty() {
+			true => Ok(()),
+			false => Err(errors),
+		}
+	}
+
+	/// Delete all [`TapestryFragment`] instances of `tapestry_id` to restart the conversation.
+	///
+	/// If `keep_system` is set, the system messages of the first tapestry fragment instance are
+	/// saved as a new tapestry fragment instance. Since [`Loom::weave`] does not save its
+	/// `instructions`, these are only the system messages injected with [`Loom::inject_context`]
+	/// or passed in the `msgs` of [`Loom::weave`]. Summaries are saved to later instances and are
+	/// not kept.
+	///
+	/// The tapestry is locked for the whole reset.
+	///
+	/// Returns the number of tapestry fragment instances deleted.
+	async fn reset<TID: TapestryId>(tapestry_id: TID, keep_system: bool) -> Result<usize> {
+		let tapestry_lock = TapestryLock::<T, TID>::acquire(
+			tapestry_id.clone(),
+			Duration::from_millis(T::LOCK_TIMEOUT_MS),
+		)
+		.await?;
+
+		let Some(instance_count) =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry(tapestry_id.clone())).await?
+		else {
+			tapestry_lock.release_or_log().await;
+			return Ok(0);
+		};
+
+		let system_msgs = match keep_system {
+			true => with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+				tapestry_id.clone(),
+				Some(1),
+			))
+			.await?
+			.map(|tapestry_fragment| {
+				tapestry_fragment
+					.context_messages
+					.into_iter()
+					.filter(|msg| matches!(msg.role, WrapperRole::Role(Role::System)))
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or_default(),
+			false => vec![],
+		};
+
+		with_storage_timeout::<T, _>(T::Chest::delete_tapestry(tapestry_id.clone()))
+			.await
+			.map_err(|e| {
+				error!("Failed to delete tapestry: {}", e);
+				e
+			})?;
+
+		if !system_msgs.is_empty() {
+			let mut tapestry_fragment = TapestryFragment::new();
+			tapestry_fragment.extend_messages(system_msgs)?;
+			with_storage_timeout::<T, _>(T::Chest::save_tapestry_fragment(
+				&tapestry_id,
+				tapestry_fragment,
+				false,
+			))
+			.await?;
+		}
+
+		tapestry_lock.release_or_log().await;
+
+		info!("Reset {} by deleting {} instances", tapestry_id.base_key(), instance_count);
+
+		Ok(instance_count as usize)
+	}
+
+	/// Continue the conversation from the historical [`TapestryFragment`] `instance` of
+	/// `tapestry_id`, branching off from it.
+	///
+	/// The LLM is prompted like [`Loom::weave`] with the `instructions`, the messages of `instance`
+	/// and `msg`. The messages of `instance`, `msg` and the response are then saved as a new
+	/// tapestry fragment instance whose `parent_instance` is `instance`, leaving `instance`
+	/// untouched. Subsequent calls to [`Loom::weave`] continue from the new branch.
+	///
+	/// If the messages of `instance` leave no room for a response, the new instance holds their
+	/// summary instead.
+	///
+	/// Returns the response of the LLM.
+	async fn continue_from<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instance: u64,
+		instructions: String,
+		msg: String,
+	) -> Result<String> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let instructions_ctx_msg =
+				Self::build_context_message(SYSTEM_ROLE.into(), instructions, None);
+			let mut msgs = vec![Self::build_context_message(USER_ROLE.into(), msg, None)];
+
+			let prepared = prepare_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				summary_llm_config,
+				&tapestry_id,
+				&instructions_ctx_msg,
+				&msgs,
+				vec![],
+				Some(instance),
+			)
+			.await?;
+
+			let response_content: String = prompt_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				&tapestry_id,
+				&prepared,
+				&instructions_ctx_msg,
+				&msgs,
+			)
+			.await?
+			.into()
+			.unwrap_or_default();
+
+			msgs.push(Self::build_context_message(
+				ASSISTANT_ROLE.into(),
+				response_content.clone(),
+				None,
+			));
+			let tapestry_fragment_id =
+				save_weave::<T, Self, TID>(&tapestry_id, prepared, msgs).await?;
+			info!("Branched instance {} off instance {}", tapestry_fragment_id, instance);
+
+			Ok(response_content)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Prompt the LLM to reason step by step between `<thinking>` tags before answering `msg`.
+	///
+	/// The LLM is prompted like [`Loom::weave`] with the `instructions`, the messages of the
+	/// current [`TapestryFragment`] of `tapestry_id`, `msg` and a final system message asking for
+	/// the reasoning. The response is split with [`CoTResponse::parse`], and only the `answer` is
+	/// saved along with `msg` so that the reasoning never pollutes the context of later prompts.
+	async fn weave_with_cot<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		instructions: String,
+		msg: String,
+	) -> Result<CoTResponse> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let instructions_ctx_msg =
+				Self::build_context_message(SYSTEM_ROLE.into(), instructions, None);
+			let user_ctx_msg = Self::build_context_message(USER_ROLE.into(), msg, None);
+			let cot_ctx_msg = Self::build_context_message(
+				SYSTEM_ROLE.into(),
+				"First reason step by step between <thinking> and </thinking> tags, then give \
+				 the final answer after the closing tag."
+					.to_string(),
+				None,
+			);
+			let prompt_msgs = [user_ctx_msg.clone(), cot_ctx_msg];
+
+			let prepared = prepare_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				summary_llm_config,
+				&tapestry_id,
+				&instructions_ctx_msg,
+				&prompt_msgs,
+				vec![],
+				None,
+			)
+			.await?;
+
+			let response_content: String = prompt_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				&tapestry_id,
+				&prepared,
+				&instructions_ctx_msg,
+				&prompt_msgs,
+			)
+			.await?
+			.into()
+			.unwrap_or_default();
+
+			let cot_response = CoTResponse::parse(&response_content);
+			debug!("LLM reasoned with {} characters", cot_response.reasoning.len());
+
+			let msgs = vec![
+				user_ctx_msg,
+				Self::build_context_message(
+					ASSISTANT_ROLE.into(),
+					cot_response.answer.clone(),
+					None,
+				),
+			];
+			save_weave::<T, Self, TID>(&tapestry_id, prepared, msgs).await?;
+
+			Ok(cot_response)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Prompt every persona of `personas` concurrently with the current [`TapestryFragment`] of
+	/// `tapestry_id` and `msg`, e.g. for roleplay with multiple AI characters.
+	///
+	/// Each persona is a `(persona_name, system_prompt)` pair whose `system_prompt` is used as
+	/// instructions. The user message `msg` and the response of every persona, as an assistant
+	/// message whose `account_id` is the persona name, are then saved in a single update of the
+	/// current tapestry fragment, counting as a single call towards [`Config::MAX_WEAVE_CALLS`].
+	///
+	/// Like [`Loom::weave`], the current tapestry fragment is summarized first if it would leave
+	/// no room for the response to the longest `system_prompt`. Nothing is saved if any persona
+	/// fails.
+	///
+	/// Returns the `(persona_name, response)` pairs in the order of `personas`.
+	async fn multi_persona_weave<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		summary_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		personas: Vec<(String, String)>,
+		msg: String,
+	) -> Result<Vec<(String, String)>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let user_ctx_msg = Self::build_context_message(USER_ROLE.into(), msg, None);
+			let personas = personas
+				.into_iter()
+				.map(|(persona_name, system_prompt)| {
+					(
+						persona_name,
+						Self::build_context_message(SYSTEM_ROLE.into(), system_prompt, None),
+					)
+				})
+				.collect::<Vec<_>>();
+
+			// The longest instructions decide whether the tapestry fragment must be summarized
+			let longest_instructions = personas
+				.iter()
+				.map(|(_, instructions_ctx_msg)| instructions_ctx_msg)
+				.max_by_key(|instructions_ctx_msg| {
+					Self::count_tokens_in_messages(std::iter::once(*instructions_ctx_msg))
+				})
+				.cloned()
+				.unwrap_or_else(|| {
+					Self::build_context_message(SYSTEM_ROLE.into(), String::new(), None)
+				});
+
+			let prepared = prepare_weave::<T, Self, TID>(
+				&prompt_llm_config,
+				summary_llm_config,
+				&tapestry_id,
+				&longest_instructions,
+				std::slice::from_ref(&user_ctx_msg),
+				vec![],
+				None,
+			)
+			.await?;
+
+			let prompts = personas.iter().map(|(persona_name, instructions_ctx_msg)| {
+				let (prompt_llm_config, tapestry_id, prepared, user_ctx_msg) =
+					(&prompt_llm_config, &tapestry_id, &prepared, &user_ctx_msg);
+				async move {
+					debug!("Prompting persona {}", persona_name);
+
+					let response_content: String = prompt_weave::<T, Self, TID>(
+						prompt_llm_config,
+						tapestry_id,
+						prepared,
+						instructions_ctx_msg,
+						std::slice::from_ref(user_ctx_msg),
+					)
+					.await
+					.map_err(|e| {
+						error!("Failed to prompt LLM as {}: {}", persona_name, e);
+						e
+					})?
+					.into()
+					.unwrap_or_default();
+
+					Ok::<_, Box<dyn std::error::Error + Send + Sync>>((
+						persona_name.clone(),
+						response_content,
+					))
+				}
+			});
+			let responses = futures_util::future::try_join_all(prompts).await?;
+
+			let mut msgs = vec![user_ctx_msg];
+			msgs.extend(responses.iter().map(|(persona_name, response_content)| {
+				Self::build_context_message(
+					ASSISTANT_ROLE.into(),
+					response_content.clone(),
+					Some(persona_name.clone()),
+				)
+			}));
+			save_weave::<T, Self, TID>(&tapestry_id, prepared, msgs).await?;
 
-		// Get current tapestry fragment to work with
-		let current_tapestry_fragment = T::Chest::get_tapestry_fragment(tapestry_id.clone(), None)
-			.await?
-			.unwrap_or_default();
+			Ok(responses)
+		}
+		.instrument(span)
+		.await
+	}
 
-		// Get max token limit which cannot be exceeded in a tapestry fragment
-		let max_prompt_tokens_limit = prompt_llm_config.model.get_max_prompt_token_limit();
+	/// Analyze the conversation of `tapestry_id` following `analysis_prompt`, e.g. "Identify the
+	/// top 3 user frustrations".
+	///
+	/// The messages of all tapestry fragment instances of `tapestry_id` are concatenated into a
+	/// single transcript message, followed by `analysis_prompt` as the final system instruction.
+	/// Deleted instances are skipped. The analysis is returned without being stored, leaving the
+	/// tapestry untouched.
+	///
+	/// Fails with [`WeaveError::MaxCompletionTokensIsZero`] if the transcript leaves no room for a
+	/// response.
+	async fn analyze_conversation<TID: TapestryId>(
+		analysis_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		analysis_prompt: &str,
+	) -> Result<String> {
+		let instance_count =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry(tapestry_id.clone()))
+				.await?
+				.unwrap_or_default();
+
+		let mut transcript = vec![];
+		for instance in 1..=instance_count as u64 {
+			match with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+				tapestry_id.clone(),
+				Some(instance),
+			))
+			.await
+			{
+				Ok(Some(tapestry_fragment)) => transcript.extend(
+					tapestry_fragment
+						.context_messages
+						.into_iter()
+						.map(|msg| format!("{}: {}", msg.role.as_str(), msg.content)),
+				),
+				Ok(None) => {},
+				Err(e) => match LoomError::from(e) {
+					LoomError::Storage(StorageError::NotFound) => {},
+					e => return Err(e.into()),
+				},
+			}
+		}
 
-		// Request messages which will be sent as a whole to the LLM
-		let mut req_msgs = VecPromptMsgsDeque::<T, T::PromptModel>::with_capacity(
-			current_tapestry_fragment.context_messages.len() + 1, /* +1 for the instruction
-			                                                       * message to add */
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::SummaryModel>::with_capacity(2);
+		req_msgs.push_back(
+			Self::build_context_message(USER_ROLE.into(), transcript.join("\n"), None).into(),
+		);
+		req_msgs.push_back(
+			Self::build_context_message(SYSTEM_ROLE.into(), analysis_prompt.to_string(), None)
+				.into(),
 		);
 
-		// Add instructions as the first message
-		req_msgs.push_front(instructions_req_msg);
+		let max_completion_tokens = analysis_llm_config
+			.model
+			.get_max_prompt_token_limit()
+			.saturating_sub(&req_msgs.tokens);
+		if max_completion_tokens.is_zero() {
+			return Err(LoomError::from(WeaveError::MaxCompletionTokensIsZero).into());
+		}
 
-		// Convert and append all tapestry fragment messages to the request messages.
-		let mut ctx_msgs = VecDeque::from(
-			prompt_llm_config
-				.model
-				.ctx_msgs_to_prompt_requests(&current_tapestry_fragment.context_messages),
+		debug!(
+			"Analyzing {} messages of {} with {} tokens",
+			transcript.len(),
+			tapestry_id.base_key(),
+			req_msgs.tokens
 		);
-		req_msgs.append(&mut ctx_msgs);
-
-		// New messages are not added here yet since we first calculate if the new `msgs` would
-		// have the tapestry fragment exceed the maximum token limit and require a summary
-		// generation resulting in a new tapestry fragment.
-		//
-		// Either we are starting a new tapestry fragment with the instruction and summary messages
-		// or we are continuing the current tapestry fragment.
-		let msgs_tokens = Self::count_tokens_in_messages(msgs.iter());
-
-		// Check if the total number of tokens in the tapestry fragment exceeds the maximum number
-		// of tokens allowed after adding the new messages and the minimum response length.
-		let does_exceeding_max_token_limit = max_prompt_tokens_limit <=
-			req_msgs.tokens.saturating_add(&msgs_tokens).saturating_add(
-				&PromptModelTokens::<T>::from_u64(T::MINIMUM_RESPONSE_LENGTH).unwrap(),
-			);
 
-		let (mut tapestry_fragment_to_persist, was_summary_generated) =
-			if does_exceeding_max_token_limit {
-				// Summary generation should not exceed the maximum token limit of the prompt model
-				// since it will be added to the tapestry fragment
-				let summary_max_tokens: PromptModelTokens<T> =
-					prompt_llm_config.model.max_context_length() - max_prompt_tokens_limit;
-
-				// Generate summary
-				let summary = Self::generate_summary(
-					summary_llm_config,
-					&current_tapestry_fragment,
-					T::convert_prompt_tokens_to_summary_model_tokens(summary_max_tokens),
-				)
-				.await?;
+		let response = analysis_llm_config
+			.model
+			.prompt(
+				false,
+				req_msgs.tokens,
+				req_msgs.into_vec(),
+				&analysis_llm_config.params,
+				max_completion_tokens,
+			)
+			.await
+			.map_err(|e| {
+				error!("Failed to prompt LLM: {}", e);
+				T::map_prompt_error(e)
+			})?;
 
-				let summary_ctx_msg = Self::build_context_message(
-					SYSTEM_ROLE.into(),
-					format!("\n\"\"\"\nSummary\n {}", summary),
-					None,
-				);
+		Ok(response.into().unwrap_or_default())
+	}
 
-				// Truncate all tapestry fragment messages except for the instructions and add the
-				// summary
-				req_msgs.truncate(1);
-				req_msgs.push_back(summary_ctx_msg.clone().into());
+	/// Score `response` following `rubric`, e.g. "Is this response accurate and helpful? Rate
+	/// 0-10.", using the LLM of `judge_llm_config` as a judge.
+	///
+	/// The judge is prompted with the messages of the current [`TapestryFragment`] of
+	/// `tapestry_id` and `response`, followed by `rubric` and instructions to reply with a score
+	/// and its reasoning. Nothing is stored.
+	///
+	/// Fails with [`ParseError::MissingScore`] if the reply contains no score.
+	async fn score_response<TID: TapestryId>(
+		judge_llm_config: LlmConfig<T, T::SummaryModel>,
+		tapestry_id: TID,
+		response: &str,
+		rubric: &str,
+	) -> Result<ResponseScore> {
+		let tapestry_fragment =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(tapestry_id, None))
+				.await?
+				.unwrap_or_default();
+
+		let transcript = tapestry_fragment
+			.context_messages
+			.into_iter()
+			.map(|msg| format!("{}: {}", msg.role.as_str(), msg.content))
+			.collect::<Vec<_>>()
+			.join("\n");
+
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::SummaryModel>::with_capacity(2);
+		req_msgs.push_back(
+			Self::build_context_message(
+				USER_ROLE.into(),
+				format!("Conversation:\n{transcript}\n\nResponse:\n{response}"),
+				None,
+			)
+			.into(),
+		);
+		req_msgs.push_back(
+			Self::build_context_message(
+				SYSTEM_ROLE.into(),
+				format!(
+					"{rubric}\n\nReply with \"Score: <number>\" on the first line, followed by \
+					 \"Reasoning: <explanation>\"."
+				),
+				None,
+			)
+			.into(),
+		);
 
-				// Create new tapestry fragment
-				let mut new_tapestry_fragment = TapestryFragment::new();
-				new_tapestry_fragment.push_message(summary_ctx_msg)?;
+		let max_completion_tokens = judge_llm_config
+			.model
+			.get_max_prompt_token_limit()
+			.saturating_sub(&req_msgs.tokens);
+		if max_completion_tokens.is_zero() {
+			return Err(LoomError::from(WeaveError::MaxCompletionTokensIsZero).into());
+		}
 
-				(new_tapestry_fragment, true)
-			} else {
-				(current_tapestry_fragment, false)
-			};
+		let reply: String = judge_llm_config
+			.model
+			.prompt(
+				false,
+				req_msgs.tokens,
+				req_msgs.into_vec(),
+				&judge_llm_config.params,
+				max_completion_tokens,
+			)
+			.await
+			.map_err(|e| {
+				error!("Failed to prompt LLM: {}", e);
+				T::map_prompt_error(e)
+			})?
+			.into()
+			.unwrap_or_default();
 
-		// Add new messages to the request messages
-		req_msgs.extend(msgs.iter().map(|m| m.clone().into()).collect::<Vec<_>>());
+		Ok(reply.parse::<ResponseScore>().map_err(|e| {
+			error!("Failed to parse judge reply: {}", reply);
+			LoomError::from(e)
+		})?)
+	}
 
-		// Tokens available for LLM response which would not exceed maximum token limit
-		let max_completion_tokens = max_prompt_tokens_limit.saturating_sub(&req_msgs.tokens);
+	/// Classify `msg` into one of `categories`, e.g. `["question", "complaint", "feedback"]`,
+	/// using the LLM of `classifier_llm_config`.
+	///
+	/// Intended as a cheap pre-processing step, so pass the cheapest variant of
+	/// [`Config::SummaryModel`]. The LLM is prompted once with `msg` only, no tapestry is read or
+	/// stored.
+	///
+	/// Returns the category of `categories` matching the reply, ignoring case. Fails with
+	/// [`ParseError::UnknownCategory`] if the reply matches none of them.
+	async fn classify_intent(
+		classifier_llm_config: LlmConfig<T, T::SummaryModel>,
+		msg: &str,
+		categories: &[&str],
+	) -> Result<String> {
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::SummaryModel>::with_capacity(2);
+		req_msgs.push_back(
+			Self::build_context_message(
+				SYSTEM_ROLE.into(),
+				format!(
+					"Classify the message of the user into exactly one of the following \
+					 categories: {}.\n\nReply with the category only.",
+					categories.join(", ")
+				),
+				None,
+			)
+			.into(),
+		);
+		req_msgs
+			.push_back(Self::build_context_message(USER_ROLE.into(), msg.to_string(), None).into());
 
+		let max_completion_tokens = classifier_llm_config
+			.model
+			.get_max_prompt_token_limit()
+			.saturating_sub(&req_msgs.tokens);
 		if max_completion_tokens.is_zero() {
 			return Err(LoomError::from(WeaveError::MaxCompletionTokensIsZero).into());
 		}
 
-		// Execute prompt to LLM
-		let response = prompt_llm_config
+		let reply: String = classifier_llm_config
 			.model
 			.prompt(
 				false,
 				req_msgs.tokens,
 				req_msgs.into_vec(),
-				&prompt_llm_config.params,
+				&classifier_llm_config.params,
 				max_completion_tokens,
 			)
 			.await
 			.map_err(|e| {
 				error!("Failed to prompt LLM: {}", e);
-				e
-			})?;
+				T::map_prompt_error(e)
+			})?
+			.into()
+			.unwrap_or_default();
 
-		// Add LLM response to the tapestry fragment messages to save
-		msgs.push(Self::build_context_message(
-			ASSISTANT_ROLE.into(),
-			response.clone().into().unwrap_or_default(),
-			None,
-		));
+		match match_category(&reply, categories) {
+			Some(category) => Ok(category.to_string()),
+			None => {
+				error!("Failed to match classifier reply to a category: {}", reply);
+				Err(LoomError::from(ParseError::UnknownCategory(reply)).into())
+			},
+		}
+	}
 
-		// Add new messages and response to the tapestry fragment which will be persisted in the
-		// database
-		tapestry_fragment_to_persist.extend_messages(msgs)?;
+	/// Get the system messages of the current [`TapestryFragment`] instance of `tapestry_id`.
+	///
+	/// The instructions passed to [`Loom::weave`] are not stored, so these are the system
+	/// messages saved in the tapestry fragment, such as generated summaries.
+	///
+	/// Returns an empty list if the tapestry does not exist.
+	async fn get_system_messages<TID: TapestryId>(
+		tapestry_id: TID,
+	) -> Result<Vec<ContextMessage<T>>> {
+		let tapestry_fragment =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(tapestry_id, None))
+				.await?
+				.unwrap_or_default();
+
+		Ok(tapestry_fragment
+			.context_messages
+			.into_iter()
+			.filter(|msg| matches!(msg.role, WrapperRole::Role(Role::System)))
+			.collect())
+	}
 
-		debug!("Saving tapestry fragment: {:?}", tapestry_fragment_to_persist);
+	/// Get the `n` most recent user/assistant exchanges of the current [`TapestryFragment`]
+	/// instance of `tapestry_id`, oldest first.
+	///
+	/// An exchange is a user message directly followed by an assistant message. Returns fewer
+	/// than `n` exchanges if the tapestry fragment does not contain as many, and none if the
+	/// tapestry does not exist.
+	async fn get_last_n_exchanges<TID: TapestryId>(
+		tapestry_id: TID,
+		n: usize,
+	) -> Result<Vec<(ContextMessage<T>, ContextMessage<T>)>> {
+		let tapestry_fragment =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(tapestry_id, None))
+				.await?
+				.unwrap_or_default();
+
+		let mut exchanges = vec![];
+		let mut msgs = tapestry_fragment.context_messages.into_iter().rev().peekable();
+		while exchanges.len() < n {
+			let Some(msg) = msgs.next() else {
+				break;
+			};
 
-		// Save tapestry fragment to database
-		// When summarized, the tapestry_fragment will be saved under a new instance
-		let tapestry_fragment_id = T::Chest::save_tapestry_fragment(
-			&tapestry_id,
-			tapestry_fragment_to_persist,
-			was_summary_generated,
-		)
-		.await
-		.map_err(|e| {
-			error!("Failed to save tapestry fragment: {}", e);
-			e
-		})?;
+			if matches!(msg.role, WrapperRole::Role(Role::Assistant)) &&
+				msgs.peek().is_some_and(|m| matches!(m.role, WrapperRole::Role(Role::User)))
+			{
+				let user_msg = msgs.next().unwrap();
+				exchanges.push((user_msg, msg));
+			}
+		}
+		exchanges.reverse();
+
+		Ok(exchanges)
+	}
+
+	/// Percentage of the context window of `prompt_model` used by the current
+	/// [`TapestryFragment`] instance of `tapestry_id`, between `0.0` and `100.0`.
+	///
+	/// Returns `0.0` if the tapestry does not exist.
+	async fn get_context_window_utilization<TID: TapestryId>(
+		prompt_model: T::PromptModel,
+		tapestry_id: TID,
+	) -> Result<f32> {
+		let Some(tapestry_fragment) =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(tapestry_id, None))
+				.await?
+		else {
+			return Ok(0.0);
+		};
+
+		let context_tokens = tapestry_fragment.context_tokens.to_f32().unwrap_or_default();
+		let max_context_length = prompt_model.max_context_length().to_f32().unwrap_or_default();
+		if max_context_length == 0.0 {
+			return Ok(100.0);
+		}
+
+		Ok((context_tokens / max_context_length * 100.0).clamp(0.0, 100.0))
+	}
+
+	/// Build the messages [`Loom::weave`] would send to the LLM without prompting it or saving
+	/// anything.
+	///
+	/// Useful for inspecting prompt construction, for example in CI, without spending API
+	/// credits.
+	///
+	/// # Parameters
+	///
+	/// - `prompt_model`: The [`Config::PromptModel`] that would be prompted.
+	/// - `tapestry_id`: The [`TapestryId`] of the [`TapestryFragment`] to build upon.
+	/// - `instructions`: The instruction message.
+	/// - `msgs`: The new messages.
+	async fn dry_run<TID: TapestryId>(
+		prompt_model: T::PromptModel,
+		tapestry_id: TID,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+	) -> Result<DryRunOutput<T>> {
+		let instructions_ctx_msg =
+			Self::build_context_message(SYSTEM_ROLE.into(), instructions, None);
+
+		let current_tapestry_fragment =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(tapestry_id, None))
+				.await?
+				.unwrap_or_default();
+
+		let max_prompt_tokens_limit = prompt_model.get_max_prompt_token_limit();
+
+		// Same calculation as `weave` to determine whether a summary would be generated
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::PromptModel>::new();
+		req_msgs.push_front(instructions_ctx_msg.clone().into());
+		req_msgs.extend(
+			prompt_model.ctx_msgs_to_prompt_requests(&current_tapestry_fragment.context_messages),
+		);
+		let msgs_tokens = Self::count_tokens_in_messages(msgs.iter());
+		let would_summarize = max_prompt_tokens_limit <=
+			req_msgs.tokens.saturating_add(&msgs_tokens).saturating_add(
+				&PromptModelTokens::<T>::from_u64(T::MINIMUM_RESPONSE_LENGTH).unwrap(),
+			);
+
+		// Pinned messages are sent even when summarizing
+		let (pinned_msgs, unpinned_msgs) = current_tapestry_fragment.partition_pinned();
+		let mut messages = vec![instructions_ctx_msg];
+		messages.extend(pinned_msgs);
+		if !would_summarize {
+			messages.extend(unpinned_msgs);
+		}
+		messages.extend(msgs);
+
+		if T::PROMPT_FORMAT == PromptFormat::ChatML {
+			messages = vec![Self::build_chatml_message(messages.iter())];
+		}
+
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::PromptModel>::new();
+		req_msgs.extend(prompt_model.ctx_msgs_to_prompt_requests(&messages));
+
+		Ok(DryRunOutput { messages, estimated_prompt_tokens: req_msgs.tokens, would_summarize })
+	}
+
+	/// Estimate the quality of a summary of `summary_tokens` generated from `source_tokens`.
+	///
+	/// The estimate is based solely on the compression ratio, see [`SummaryQuality`]. A summary
+	/// that compresses the source too much likely lost important information.
+	fn estimate_summary_quality(
+		source_tokens: PromptModelTokens<T>,
+		summary_tokens: PromptModelTokens<T>,
+	) -> SummaryQuality {
+		let source_tokens = source_tokens.to_u64().unwrap_or_default();
+		let summary_tokens = summary_tokens.to_u64().unwrap_or_default();
+
+		if summary_tokens == 0 {
+			return SummaryQuality::TooShort;
+		}
+		if source_tokens == 0 {
+			return SummaryQuality::Excellent;
+		}
 
-		Ok((response, tapestry_fragment_id, was_summary_generated))
+		match summary_tokens.saturating_mul(100) / source_tokens {
+			0..=1 => SummaryQuality::TooShort,
+			2..=4 => SummaryQuality::Poor,
+			5..=9 => SummaryQuality::Good,
+			_ => SummaryQuality::Excellent,
+		}
 	}
 
 	/// Generates the summary of the current [`TapestryFragment`] instance.
@@ -517,11 +3000,20 @@ pub trait Loom<T: Config> {
 	) -> Result<String> {
 		let mut summary_generation_prompt = VecPromptMsgsDeque::<T, T::SummaryModel>::new();
 
-		summary_generation_prompt.extend(
-			summary_model_config
-				.model
-				.ctx_msgs_to_prompt_requests(tapestry_fragment.context_messages.as_slice()),
-		);
+		// Pinned messages are carried over as is and would bias the summary
+		let (_, unpinned_msgs) = tapestry_fragment.partition_pinned();
+		summary_generation_prompt
+			.extend(summary_model_config.model.ctx_msgs_to_prompt_requests(&unpinned_msgs));
+
+		if !T::SUMMARIZATION_PROMPT.is_empty() {
+			let words = summary_model_config.model.convert_tokens_to_words(summary_max_tokens);
+			let instruction = Self::build_context_message(
+				SYSTEM_ROLE.into(),
+				T::SUMMARIZATION_PROMPT.replace("{words}", &words.to_string()),
+				None,
+			);
+			summary_generation_prompt.push_back(instruction.into());
+		}
 
 		let res = summary_model_config
 			.model
@@ -535,7 +3027,7 @@ pub trait Loom<T: Config> {
 			.await
 			.map_err(|e| {
 				error!("Failed to prompt LLM: {}", e);
-				e
+				T::map_prompt_error(e)
 			})?;
 
 		let summary_response_content = res.into();
@@ -543,19 +3035,126 @@ pub trait Loom<T: Config> {
 		Ok(summary_response_content.unwrap_or_default())
 	}
 
+	/// Apply [`Config::SentimentAnalyzer`] to `msgs` without a sentiment.
+	fn analyze_sentiment(mut msgs: Vec<ContextMessage<T>>) -> Vec<ContextMessage<T>> {
+		let sentiment_analyzer = T::SentimentAnalyzer::default();
+		for msg in msgs.iter_mut().filter(|msg| msg.sentiment.is_none()) {
+			msg.sentiment = sentiment_analyzer.sentiment(&msg.content);
+		}
+		msgs
+	}
+
+	/// Apply [`Config::RedactionFilter`] to the content of `msgs`.
+	fn redact_messages(mut msgs: Vec<ContextMessage<T>>) -> Vec<ContextMessage<T>> {
+		let redaction_filter = T::RedactionFilter::default();
+		for msg in msgs.iter_mut() {
+			msg.content = redaction_filter.redact(&msg.content);
+		}
+		msgs
+	}
+
+	/// Helper method to build the messages [`Loom::weave_ephemeral`] prompts the LLM with: the
+	/// `instructions`, as many of the latest messages of the current [`TapestryFragment`] instance
+	/// of `tapestry_id` as fit, and `msgs`.
+	async fn build_ephemeral_messages<TID: TapestryId>(
+		tapestry_id: &TID,
+		prompt_llm_config: &LlmConfig<T, T::PromptModel>,
+		instructions: String,
+		msgs: Vec<ContextMessage<T>>,
+	) -> Result<Vec<ContextMessage<T>>> {
+		let instructions_ctx_msg =
+			Self::build_context_message(SYSTEM_ROLE.into(), instructions, None);
+
+		let current_tapestry_fragment = with_storage_timeout::<T, _>(
+			T::Chest::get_tapestry_fragment(tapestry_id.clone(), None),
+		)
+		.await?
+		.unwrap_or_default();
+
+		let max_prompt_tokens_limit = prompt_llm_config.model.get_max_prompt_token_limit();
+
+		// Leave out the oldest messages which do not fit next to the instructions, the new
+		// messages and the minimum response length
+		let required_tokens = Self::count_tokens_in_messages(
+			std::iter::once(&instructions_ctx_msg).chain(msgs.iter()),
+		)
+		.saturating_add(&PromptModelTokens::<T>::from_u64(T::MINIMUM_RESPONSE_LENGTH).unwrap());
+		let context_tapestry_fragment = current_tapestry_fragment
+			.truncate_to_tokens(max_prompt_tokens_limit.saturating_sub(&required_tokens));
+
+		let mut messages = vec![instructions_ctx_msg];
+		messages.extend(context_tapestry_fragment.context_messages);
+		messages.extend(msgs);
+
+		if T::PROMPT_FORMAT == PromptFormat::ChatML {
+			messages = vec![Self::build_chatml_message(messages.iter())];
+		}
+
+		Ok(messages)
+	}
+
+	/// Helper method to prompt the LLM with `messages` built by
+	/// [`Loom::build_ephemeral_messages`] without saving anything.
+	async fn prompt_ephemeral<TID: TapestryId>(
+		prompt_llm_config: LlmConfig<T, T::PromptModel>,
+		tapestry_id: &TID,
+		messages: Vec<ContextMessage<T>>,
+	) -> Result<<<T as Config>::PromptModel as Llm<T>>::Response> {
+		let max_prompt_tokens_limit = prompt_llm_config.model.get_max_prompt_token_limit();
+
+		let mut req_msgs = VecPromptMsgsDeque::<T, T::PromptModel>::new();
+		req_msgs.extend(prompt_llm_config.model.ctx_msgs_to_prompt_requests(&messages));
+
+		// Tokens reserved through `TokenBudgetGuard`s are not available for the LLM response
+		let reserved_tokens =
+			with_storage_timeout::<T, _>(T::Chest::get_reserved_tokens(tapestry_id)).await?;
+		let reserved_tokens =
+			PromptModelTokens::<T>::from_u64(reserved_tokens).unwrap_or(max_prompt_tokens_limit);
+
+		let max_completion_tokens = max_prompt_tokens_limit
+			.saturating_sub(&req_msgs.tokens)
+			.saturating_sub(&reserved_tokens);
+
+		if max_completion_tokens.is_zero() {
+			return Err(LoomError::from(WeaveError::MaxCompletionTokensIsZero).into());
+		}
+
+		prompt_llm_config
+			.model
+			.prompt(
+				false,
+				req_msgs.tokens,
+				req_msgs.into_vec(),
+				&prompt_llm_config.params,
+				max_completion_tokens,
+			)
+			.await
+			.map_err(|e| {
+				error!("Failed to prompt LLM: {}", e);
+				T::map_prompt_error(e)
+			})
+	}
+
 	/// Helper method to build a [`ContextMessage`]
 	fn build_context_message(
 		role: WrapperRole,
 		content: String,
 		account_id: Option<String>,
 	) -> ContextMessage<T> {
-		ContextMessage {
-			role,
-			content,
-			account_id,
-			timestamp: chrono::Utc::now().to_rfc3339(),
-			_phantom: PhantomData,
-		}
+		ContextMessage::new(role, content, account_id, chrono::Utc::now().to_rfc3339())
+	}
+
+	/// Helper method to encode [`ContextMessage`]s into a single ChatML formatted user
+	/// [`ContextMessage`].
+	///
+	/// The encoded content ends with an open assistant turn for the LLM to complete.
+	fn build_chatml_message(msgs: impl Iterator<Item = &ContextMessage<T>>) -> ContextMessage<T> {
+		let mut content = msgs
+			.map(|m| format!("<|im_start|>{}\n{}<|im_end|>\n", m.role.as_str(), m.content))
+			.collect::<String>();
+		content.push_str("<|im_start|>assistant\n");
+
+		Self::build_context_message(USER_ROLE.into(), content, None)
 	}
 
 	fn count_tokens_in_messages(
@@ -574,6 +3173,377 @@ pub trait Loom<T: Config> {
 	}
 }
 
+/// A [`Loom::weave`] holding the lock on its tapestry, ready to prompt the LLM, see
+/// [`prepare_weave`].
+struct PreparedWeave<T: Config, TID: TapestryId> {
+	/// Held until the tapestry fragment is saved. Released in the background if an error occurs
+	/// before that.
+	tapestry_lock: TapestryLock<T, TID>,
+	/// Tapestry fragment to prompt with and to add the new messages to. A new tapestry fragment
+	/// holding the summary of the current one if `was_summary_generated`.
+	tapestry_fragment: TapestryFragment<T>,
+	/// Messages sent right after the instructions which are never saved.
+	extra_context: Vec<ContextMessage<T>>,
+	was_summary_generated: bool,
+	/// Whether the tapestry fragment is saved as a new instance, after summarizing or branching.
+	increment: bool,
+}
+
+/// Lock `tapestry_id` and load its current tapestry fragment instance, which is summarized if the
+/// `instructions`, the `extra_context`, its messages, `msgs` and
+/// [`Config::MINIMUM_RESPONSE_LENGTH`] would exceed the maximum prompt token limit.
+///
+/// Branches off from the tapestry fragment instance `branch_from` instead of continuing the
+/// current one if set, see [`Loom::continue_from`].
+///
+/// Shared by [`Loom::weave`] and all of its variants which save a response, followed by
+/// [`prompt_weave`] and [`save_weave`]. Fails with [`WeaveError::QuotaExceeded`] once
+/// [`Config::MAX_WEAVE_CALLS`] is reached, and with [`WeaveError::ContextExhausted`] if `msgs` do
+/// not fit even after summarizing.
+async fn prepare_weave<T: Config, L: Loom<T> + Send + ?Sized, TID: TapestryId>(
+	prompt_llm_config: &LlmConfig<T, T::PromptModel>,
+	summary_llm_config: LlmConfig<T, T::SummaryModel>,
+	tapestry_id: &TID,
+	instructions: &ContextMessage<T>,
+	msgs: &[ContextMessage<T>],
+	extra_context: Vec<ContextMessage<T>>,
+	branch_from: Option<u64>,
+) -> Result<PreparedWeave<T, TID>> {
+	validate_config::<T>()?;
+
+	#[cfg(feature = "multimodal")]
+	if !prompt_llm_config.model.supports_vision() &&
+		msgs.iter().chain(extra_context.iter()).any(|msg| {
+			msg.content_parts
+				.as_ref()
+				.is_some_and(|content_parts| !content_parts.is_empty())
+		}) {
+		error!("{} does not support images", prompt_llm_config.model.name());
+		return Err(
+			LoomError::from(WeaveError::UnsupportedModelCapability("vision".to_string())).into()
+		);
+	}
+
+	let tapestry_lock = TapestryLock::<T, TID>::acquire(
+		tapestry_id.clone(),
+		Duration::from_millis(T::LOCK_TIMEOUT_MS),
+	)
+	.await?;
+
+	// Checked while holding the lock so that concurrent calls cannot both pass the limit
+	if let Some(limit) = T::MAX_WEAVE_CALLS {
+		let weave_count =
+			with_storage_timeout::<T, _>(T::Chest::get_weave_count(tapestry_id)).await?;
+		if weave_count >= limit {
+			error!("Weave call {} exceeds the limit of {} calls", weave_count + 1, limit);
+			return Err(LoomError::from(WeaveError::QuotaExceeded {
+				limit,
+				current: weave_count + 1,
+			})
+			.into());
+		}
+	}
+
+	// Get current tapestry fragment to work with
+	let current_tapestry_fragment = match branch_from {
+		Some(instance) => {
+			let mut tapestry_fragment = with_storage_timeout::<T, _>(
+				T::Chest::get_tapestry_fragment(tapestry_id.clone(), Some(instance)),
+			)
+			.await?
+			.ok_or_else(|| {
+				error!("Tapestry fragment instance {} not found", instance);
+				LoomError::from(StorageError::NotFound)
+			})?;
+			tapestry_fragment.parent_instance = Some(instance);
+			tapestry_fragment
+		},
+		None =>
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(tapestry_id.clone(), None))
+				.await?
+				.unwrap_or_default(),
+	};
+
+	// Get max token limit which cannot be exceeded in a tapestry fragment
+	let max_prompt_tokens_limit = prompt_llm_config.model.get_max_prompt_token_limit();
+
+	// Tokens of everything but the new messages
+	let context_tokens = |tapestry_fragment: &TapestryFragment<T>| {
+		build_request_messages::<T, L>(
+			prompt_llm_config,
+			instructions,
+			&extra_context,
+			tapestry_fragment,
+			&[],
+		)
+		.tokens
+	};
+
+	// Check if the total number of tokens in the tapestry fragment exceeds the maximum number of
+	// tokens allowed after adding the new messages and the minimum response length, requiring a
+	// summary generation resulting in a new tapestry fragment.
+	let msgs_tokens = L::count_tokens_in_messages(msgs.iter());
+	let does_exceeding_max_token_limit = max_prompt_tokens_limit <=
+		context_tokens(&current_tapestry_fragment)
+			.saturating_add(&msgs_tokens)
+			.saturating_add(
+				&PromptModelTokens::<T>::from_u64(T::MINIMUM_RESPONSE_LENGTH).unwrap(),
+			);
+
+	let (tapestry_fragment, was_summary_generated) = if does_exceeding_max_token_limit {
+		// Summary generation should not exceed the maximum token limit of the prompt model since
+		// it will be added to the tapestry fragment
+		let summary_max_tokens: PromptModelTokens<T> =
+			prompt_llm_config.model.max_context_length() - max_prompt_tokens_limit;
+
+		// Generate summary
+		let summary = L::generate_summary(
+			summary_llm_config,
+			&current_tapestry_fragment,
+			T::convert_prompt_tokens_to_summary_model_tokens(summary_max_tokens),
+		)
+		.await?;
+
+		let summary_tokens = T::PromptModel::count_tokens(&summary).unwrap_or_default();
+		info!(
+			"Summarized {} tokens into {} tokens, summary quality: {:?}",
+			current_tapestry_fragment.context_tokens,
+			summary_tokens,
+			L::estimate_summary_quality(current_tapestry_fragment.context_tokens, summary_tokens)
+		);
+
+		let summary_ctx_msg = L::build_context_message(
+			SYSTEM_ROLE.into(),
+			format!("\n\"\"\"\nSummary\n {}", summary),
+			None,
+		);
+
+		// Create new tapestry fragment, keeping the pinned messages
+		let (pinned_msgs, _) = current_tapestry_fragment.partition_pinned();
+		let mut new_tapestry_fragment = TapestryFragment::new();
+		new_tapestry_fragment.parent_instance = branch_from;
+		new_tapestry_fragment.extend_messages(pinned_msgs)?;
+		new_tapestry_fragment.push_message(summary_ctx_msg)?;
+		info!("{:?}", new_tapestry_fragment.compression_report(&current_tapestry_fragment));
+
+		(new_tapestry_fragment, true)
+	} else {
+		(current_tapestry_fragment, false)
+	};
+
+	// The new messages must leave room for a response, summarizing cannot make them fit
+	let tokens_available =
+		max_prompt_tokens_limit.saturating_sub(&context_tokens(&tapestry_fragment));
+	if msgs_tokens >= tokens_available {
+		error!(
+			"New messages have {} tokens, only {} tokens are available",
+			msgs_tokens, tokens_available
+		);
+		return Err(LoomError::from(WeaveError::ContextExhausted {
+			message_tokens: msgs_tokens.to_u64().unwrap_or(u64::MAX),
+			available: tokens_available.to_u64().unwrap_or_default(),
+		})
+		.into());
+	}
+
+	Ok(PreparedWeave {
+		tapestry_lock,
+		tapestry_fragment,
+		extra_context,
+		was_summary_generated,
+		increment: was_summary_generated || branch_from.is_some(),
+	})
+}
+
+/// Prompt the LLM of `prompt_llm_config` with the `instructions`, the `prepared` tapestry
+/// fragment and `msgs`, falling back to [`Config::fallback_prompt_model`] on quota errors.
+///
+/// Tokens reserved through [`TokenBudgetGuard`](storage::TokenBudgetGuard)s are not available
+/// for the response.
+async fn prompt_weave<T: Config, L: Loom<T> + Send + ?Sized, TID: TapestryId>(
+	prompt_llm_config: &LlmConfig<T, T::PromptModel>,
+	tapestry_id: &TID,
+	prepared: &PreparedWeave<T, TID>,
+	instructions: &ContextMessage<T>,
+	msgs: &[ContextMessage<T>],
+) -> Result<<<T as Config>::PromptModel as Llm<T>>::Response> {
+	let max_prompt_tokens_limit = prompt_llm_config.model.get_max_prompt_token_limit();
+
+	// Request messages which will be sent as a whole to the LLM
+	let req_msgs = build_request_messages::<T, L>(
+		prompt_llm_config,
+		instructions,
+		&prepared.extra_context,
+		&prepared.tapestry_fragment,
+		msgs,
+	);
+
+	// Tokens reserved through `TokenBudgetGuard`s are not available for the LLM response
+	let reserved_tokens =
+		with_storage_timeout::<T, _>(T::Chest::get_reserved_tokens(tapestry_id)).await?;
+	let reserved_tokens =
+		PromptModelTokens::<T>::from_u64(reserved_tokens).unwrap_or(max_prompt_tokens_limit);
+
+	// Tokens available for LLM response which would not exceed maximum token limit
+	let max_completion_tokens = max_prompt_tokens_limit
+		.saturating_sub(&req_msgs.tokens)
+		.saturating_sub(&reserved_tokens);
+
+	if max_completion_tokens.is_zero() {
+		return Err(LoomError::from(WeaveError::MaxCompletionTokensIsZero).into());
+	}
+
+	if T::LOG_PROMPTS {
+		debug!(
+			"Prompting LLM with {} tokens: {}",
+			req_msgs.tokens,
+			req_msgs.inner.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+		);
+	} else {
+		debug!(
+			"Prompting LLM with {} messages and {} tokens",
+			req_msgs.inner.len(),
+			req_msgs.tokens
+		);
+	}
+
+	// Execute prompt to LLM
+	let prompt_tokens = req_msgs.tokens;
+	let req_msgs = req_msgs.into_vec();
+	let fallback = T::fallback_prompt_model().map(|model| (model, req_msgs.clone()));
+	tracing::Span::current().record("llm.model", prompt_llm_config.model.name());
+	let response = match prompt_llm_config
+		.model
+		.prompt(false, prompt_tokens, req_msgs, &prompt_llm_config.params, max_completion_tokens)
+		.await
+	{
+		Ok(response) => response,
+		Err(e) => match fallback {
+			Some((fallback_model, req_msgs))
+				if prompt_llm_config.model.is_quota_error(e.as_ref()) =>
+			{
+				warn!(
+					"{} quota exceeded, falling back to {}: {}",
+					prompt_llm_config.model.name(),
+					fallback_model.name(),
+					e
+				);
+				tracing::Span::current().record("llm.model", fallback_model.name());
+
+				fallback_model
+					.prompt(
+						false,
+						prompt_tokens,
+						req_msgs,
+						&prompt_llm_config.params,
+						max_completion_tokens,
+					)
+					.await
+					.map_err(|e| {
+						error!("Failed to prompt fallback LLM: {}", e);
+						T::map_prompt_error(e)
+					})?
+			},
+			_ => {
+				error!("Failed to prompt LLM: {}", e);
+				return Err(T::map_prompt_error(e));
+			},
+		},
+	};
+
+	if T::LOG_RESPONSES {
+		debug!("LLM responded: {}", response.clone().into().unwrap_or_default());
+	} else {
+		debug!(
+			"LLM responded with {} characters",
+			response.clone().into().map_or(0, |content| content.len())
+		);
+	}
+
+	Ok(response)
+}
+
+/// Add `msgs` to the `prepared` tapestry fragment, save it and count the weave call.
+///
+/// The tapestry fragment is saved under a new instance if it was summarized or branched off.
+/// Returns the saved tapestry fragment instance.
+async fn save_weave<T: Config, L: Loom<T> + Send + ?Sized, TID: TapestryId>(
+	tapestry_id: &TID,
+	prepared: PreparedWeave<T, TID>,
+	msgs: Vec<ContextMessage<T>>,
+) -> Result<u64> {
+	let PreparedWeave { tapestry_lock, mut tapestry_fragment, increment, .. } = prepared;
+
+	// Add new messages and response to the tapestry fragment which will be persisted in the
+	// database
+	tapestry_fragment.extend_messages(L::redact_messages(L::analyze_sentiment(msgs)))?;
+
+	debug!(
+		"Saving tapestry fragment with {} messages and {} tokens",
+		tapestry_fragment.context_messages.len(),
+		tapestry_fragment.context_tokens
+	);
+
+	// Save tapestry fragment to database
+	// When summarized or branched off, the tapestry_fragment will be saved under a new instance
+	mark_weave_saving();
+	let tapestry_fragment_id = with_storage_timeout::<T, _>(T::Chest::save_tapestry_fragment(
+		tapestry_id,
+		tapestry_fragment,
+		increment,
+	))
+	.await
+	.map_err(|e| {
+		error!("Failed to save tapestry fragment: {}", e);
+		e
+	})?;
+
+	tracing::Span::current().record("tapestry.instance", tapestry_fragment_id);
+
+	// Only successful calls are counted. The response is already saved, so failing to count it
+	// is not worth failing the call for.
+	match with_storage_timeout::<T, _>(T::Chest::increment_weave_count(tapestry_id)).await {
+		Ok(_) => {},
+		Err(e) => match LoomError::from(e) {
+			LoomError::Storage(StorageError::Unsupported(_)) if T::MAX_WEAVE_CALLS.is_none() => {},
+			e => error!("Failed to count weave call on {}: {}", tapestry_id.base_key(), e),
+		},
+	}
+
+	tapestry_lock.release_or_log().await;
+
+	Ok(tapestry_fragment_id)
+}
+
+/// Request messages of the `instructions`, the `extra_context`, the pinned and then all other
+/// messages of the `tapestry_fragment`, and `msgs`, encoded into a single user message if
+/// [`Config::PROMPT_FORMAT`] is [`PromptFormat::ChatML`].
+fn build_request_messages<T: Config, L: Loom<T> + Send + ?Sized>(
+	prompt_llm_config: &LlmConfig<T, T::PromptModel>,
+	instructions: &ContextMessage<T>,
+	extra_context: &[ContextMessage<T>],
+	tapestry_fragment: &TapestryFragment<T>,
+	msgs: &[ContextMessage<T>],
+) -> VecPromptMsgsDeque<T, T::PromptModel> {
+	// Pinned messages are sent first so that they survive summarization
+	let ctx_msgs = std::iter::once(instructions)
+		.chain(extra_context)
+		.chain(tapestry_fragment.context_messages.iter().filter(|m| m.pinned))
+		.chain(tapestry_fragment.context_messages.iter().filter(|m| !m.pinned))
+		.chain(msgs);
+
+	let mut req_msgs = VecPromptMsgsDeque::<T, T::PromptModel>::new();
+	match T::PROMPT_FORMAT {
+		PromptFormat::ChatML => req_msgs.push_back(L::build_chatml_message(ctx_msgs).into()),
+		_ => req_msgs.append(&mut VecDeque::from(
+			prompt_llm_config
+				.model
+				.ctx_msgs_to_prompt_requests(&ctx_msgs.cloned().collect::<Vec<_>>()),
+		)),
+	}
+	req_msgs
+}
+
 /// A helper struct to manage the prompt messages in a deque while keeping track of the tokens
 /// added or removed.
 struct VecPromptMsgsDeque<T: Config, L: Llm<T>> {
@@ -610,6 +3580,7 @@ impl<T: Config, L: Llm<T>> VecPromptMsgsDeque<T, L> {
 		self.inner.append(msg_reqs);
 	}
 
+	#[cfg(test)]
 	fn truncate(&mut self, len: usize) {
 		let mut tokens = L::Tokens::from_u8(0).unwrap();
 		for msg_req in self.inner.iter().take(len) {
@@ -0,0 +1,112 @@
+//! Long-term conversational memory recalled by semantic similarity.
+//!
+//! Summarization discards the details of older messages. [`EmbeddingMemory`] keeps the embedding
+//! of every remembered message so that the messages most similar to a new prompt can be recalled
+//! and passed to the LLM as extra context, see
+//! [`Loom::weave_with_memory`](crate::Loom::weave_with_memory).
+//!
+//! Only available with the `embeddings` feature.
+use std::{
+	collections::HashMap,
+	sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+
+use crate::{Config, ContextMessage, Result, TapestryId};
+
+/// Computes embedding vectors, e.g. using the OpenAI embeddings API.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+	/// Embedding vector of `content`.
+	///
+	/// All embeddings of an [`EmbeddingMemory`] must be computed by the same model.
+	async fn embed(&self, content: &str) -> Result<Vec<f32>>;
+}
+
+/// A remembered message along with its embedding.
+struct Memory<T: Config> {
+	embedding: Vec<f32>,
+	msg: ContextMessage<T>,
+}
+
+/// In-memory vector store of messages, searched by cosine similarity.
+///
+/// Memories are kept per [`TapestryId`] and are lost when the process exits.
+pub struct EmbeddingMemory<T: Config> {
+	memories: Mutex<HashMap<String, Vec<Memory<T>>>>,
+}
+
+impl<T: Config> Default for EmbeddingMemory<T> {
+	fn default() -> Self {
+		Self { memories: Mutex::new(HashMap::new()) }
+	}
+}
+
+impl<T: Config> EmbeddingMemory<T> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn memories(&self) -> MutexGuard<'_, HashMap<String, Vec<Memory<T>>>> {
+		self.memories.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Remember `msg` of `tapestry_id` along with its `embedding`.
+	pub fn remember<TID: TapestryId>(
+		&self,
+		tapestry_id: &TID,
+		embedding: Vec<f32>,
+		msg: ContextMessage<T>,
+	) {
+		self.memories()
+			.entry(tapestry_id.base_key())
+			.or_default()
+			.push(Memory { embedding, msg });
+	}
+
+	/// Recall the `top_k` messages of `tapestry_id` most similar to `embedding`, most similar
+	/// first.
+	pub fn recall<TID: TapestryId>(
+		&self,
+		tapestry_id: &TID,
+		embedding: &[f32],
+		top_k: usize,
+	) -> Vec<ContextMessage<T>> {
+		let memories = self.memories();
+		let Some(memories) = memories.get(&tapestry_id.base_key()) else {
+			return vec![];
+		};
+
+		let mut scored = memories
+			.iter()
+			.map(|memory| (cosine_similarity(&memory.embedding, embedding), &memory.msg))
+			.collect::<Vec<_>>();
+		scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+		scored.into_iter().take(top_k).map(|(_, msg)| msg.clone()).collect()
+	}
+
+	/// Number of messages remembered for `tapestry_id`.
+	pub fn len<TID: TapestryId>(&self, tapestry_id: &TID) -> usize {
+		self.memories().get(&tapestry_id.base_key()).map_or(0, Vec::len)
+	}
+
+	/// Forget all messages of `tapestry_id`, e.g. after deleting the tapestry.
+	pub fn forget<TID: TapestryId>(&self, tapestry_id: &TID) {
+		self.memories().remove(&tapestry_id.base_key());
+	}
+}
+
+/// Cosine similarity between `a` and `b`, `0.0` if either is a zero vector.
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+	let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+	let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+	let norms = norm(a) * norm(b);
+	if norms == 0.0 {
+		return 0.0;
+	}
+
+	dot / norms
+}
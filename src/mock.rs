@@ -1,22 +1,23 @@
-use std::fmt::Formatter;
+use std::{
+	fmt::Formatter,
+	sync::atomic::{AtomicUsize, Ordering},
+};
 
+use crate::*;
 use async_trait::async_trait;
 use serde::de::DeserializeOwned;
-use tiktoken_rs::p50k_base;
-
-use crate::*;
 
 use self::types::StorageError;
 
 pub struct TestChest;
 
 #[async_trait]
-impl TapestryChestHandler<TestApp> for TestChest {
+impl<T: Config> TapestryChestHandler<T> for TestChest {
 	type Error = StorageError;
 
 	async fn save_tapestry_fragment<TID: TapestryId>(
 		_tapestry_id: &TID,
-		_tapestry_fragment: TapestryFragment<TestApp>,
+		_tapestry_fragment: TapestryFragment<T>,
 		_increment: bool,
 	) -> crate::Result<u64> {
 		Ok(0)
@@ -39,8 +40,8 @@ impl TapestryChestHandler<TestApp> for TestChest {
 	async fn get_tapestry_fragment<TID: TapestryId>(
 		_tapestry_id: TID,
 		_instance: Option<u64>,
-	) -> crate::Result<Option<TapestryFragment<TestApp>>> {
-		Ok(Some(TapestryFragment { context_tokens: 0, context_messages: vec![] }))
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		Ok(Some(TapestryFragment::default()))
 	}
 
 	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
@@ -59,6 +60,39 @@ impl TapestryChestHandler<TestApp> for TestChest {
 	) -> crate::Result<()> {
 		Ok(())
 	}
+
+	async fn lock<TID: TapestryId>(
+		_tapestry_id: &TID,
+		_timeout: std::time::Duration,
+	) -> crate::Result<String> {
+		Ok(String::new())
+	}
+
+	async fn unlock<TID: TapestryId>(_tapestry_id: &TID, _token: String) -> crate::Result<()> {
+		Ok(())
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(
+		_tapestry_id: &TID,
+		tokens: u64,
+	) -> crate::Result<u64> {
+		Ok(tokens)
+	}
+
+	async fn release_tokens<TID: TapestryId>(
+		_tapestry_id: &TID,
+		_tokens: u64,
+	) -> crate::Result<()> {
+		Ok(())
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(_tapestry_id: &TID) -> crate::Result<u64> {
+		Ok(1)
+	}
+
+	async fn get_weave_count<TID: TapestryId>(_tapestry_id: &TID) -> crate::Result<u64> {
+		Ok(0)
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -74,6 +108,10 @@ pub struct TestApp;
 impl Config for TestApp {
 	const TOKEN_THRESHOLD_PERCENTILE: BoundedU8<0, 100> = BoundedU8::new(70).unwrap();
 	const MINIMUM_RESPONSE_LENGTH: u64 = 300;
+	const ASSISTANT_NAME: &'static str = "Weaver";
+	const SEED: Option<u64> = Some(42);
+	const SUMMARIZATION_PROMPT: &'static str =
+		"Summarize the conversation in {words} words or less";
 
 	type PromptModel = TestLlm;
 	type SummaryModel = TestLlm;
@@ -84,6 +122,11 @@ impl Config for TestApp {
 	) -> SummaryModelTokens<Self> {
 		tokens
 	}
+
+	#[cfg(feature = "encryption")]
+	fn encryption_key() -> [u8; 32] {
+		[7; 32]
+	}
 }
 
 impl<T: Config> Loom<T> for TestApp {}
@@ -99,8 +142,7 @@ impl Llm<TestApp> for TestLlm {
 	type Response = TestLlmResponse;
 
 	fn count_tokens(content: &str) -> Result<Self::Tokens> {
-		let bpe = p50k_base().unwrap();
-		let tokens = bpe.encode_with_special_tokens(&content.to_string());
+		let tokens = tokenizer::p50k_base().encode_with_special_tokens(content);
 
 		tokens.len().try_into().map_err(|_| {
 			LoomError::from(WeaveError::BadConfig(format!(
@@ -143,6 +185,118 @@ impl Llm<TestApp> for TestLlm {
 	}
 }
 
+/// [`Llm`] whose prompts succeed or fail depending on the variant.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScriptedLlm {
+	#[default]
+	Ok,
+	/// Fails with an error recognized by [`Llm::is_quota_error`].
+	QuotaExceeded,
+	/// Fails with an error which is not a quota error.
+	Failing,
+	/// Succeeds, counting its prompts in [`FALLBACK_PROMPTS`].
+	Fallback,
+}
+
+/// Number of prompts answered by [`ScriptedLlm::Fallback`].
+pub static FALLBACK_PROMPTS: AtomicUsize = AtomicUsize::new(0);
+
+#[async_trait]
+impl<T: Config> Llm<T> for ScriptedLlm {
+	type Tokens = u16;
+	type Parameters = ();
+	type Request = TestLlmRequest;
+	type Response = TestLlmResponse;
+
+	fn count_tokens(content: &str) -> Result<Self::Tokens> {
+		Ok(content.split_whitespace().count().try_into().unwrap_or(u16::MAX))
+	}
+
+	fn name(&self) -> &'static str {
+		match self {
+			Self::Ok => "ScriptedLlm",
+			Self::QuotaExceeded => "ScriptedLlmQuotaExceeded",
+			Self::Failing => "ScriptedLlmFailing",
+			Self::Fallback => "ScriptedLlmFallback",
+		}
+	}
+
+	fn alias(&self) -> &'static str {
+		<Self as Llm<T>>::name(self)
+	}
+
+	async fn prompt(
+		&self,
+		_is_summarize: bool,
+		_prompt_tokens: Self::Tokens,
+		_msgs: Vec<Self::Request>,
+		_params: &Self::Parameters,
+		_max_tokens: Self::Tokens,
+	) -> Result<Self::Response> {
+		match self {
+			Self::Ok => Ok(TestLlmResponse),
+			Self::QuotaExceeded => Err(LoomError::Error("quota exceeded".to_string()).into()),
+			Self::Failing => Err(LoomError::Error("internal error".to_string()).into()),
+			Self::Fallback => {
+				FALLBACK_PROMPTS.fetch_add(1, Ordering::SeqCst);
+				Ok(TestLlmResponse)
+			},
+		}
+	}
+
+	fn max_context_length(&self) -> Self::Tokens {
+		1000
+	}
+
+	fn is_quota_error(&self, error: &(dyn std::error::Error + Send + Sync)) -> bool {
+		error.to_string().contains("quota")
+	}
+}
+
+/// [`Config`] prompting a [`ScriptedLlm`] which falls back to [`ScriptedLlm::Fallback`].
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct FallbackApp;
+impl Config for FallbackApp {
+	const TOKEN_THRESHOLD_PERCENTILE: BoundedU8<0, 100> = BoundedU8::new(70).unwrap();
+	const MINIMUM_RESPONSE_LENGTH: u64 = 300;
+
+	type PromptModel = ScriptedLlm;
+	type SummaryModel = ScriptedLlm;
+	type Chest = TestChest;
+
+	fn fallback_prompt_model() -> Option<Self::PromptModel> {
+		Some(ScriptedLlm::Fallback)
+	}
+
+	fn convert_prompt_tokens_to_summary_model_tokens(
+		tokens: PromptModelTokens<Self>,
+	) -> SummaryModelTokens<Self> {
+		tokens
+	}
+}
+
+/// [`Config`] limiting [`Loom::weave`] to a single call per tapestry, storing tapestries in
+/// memory.
+#[cfg(feature = "testing")]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct QuotaApp;
+#[cfg(feature = "testing")]
+impl Config for QuotaApp {
+	const TOKEN_THRESHOLD_PERCENTILE: BoundedU8<0, 100> = BoundedU8::new(70).unwrap();
+	const MINIMUM_RESPONSE_LENGTH: u64 = 300;
+	const MAX_WEAVE_CALLS: Option<u64> = Some(1);
+
+	type PromptModel = ScriptedLlm;
+	type SummaryModel = ScriptedLlm;
+	type Chest = crate::testing::MockTapestryChest;
+
+	fn convert_prompt_tokens_to_summary_model_tokens(
+		tokens: PromptModelTokens<Self>,
+	) -> SummaryModelTokens<Self> {
+		tokens
+	}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TestLlmRequest {
 	pub id: u32,
@@ -161,8 +315,8 @@ impl Display for TestLlmRequest {
 	}
 }
 
-impl From<ContextMessage<TestApp>> for TestLlmRequest {
-	fn from(_msg: ContextMessage<TestApp>) -> Self {
+impl<T: Config> From<ContextMessage<T>> for TestLlmRequest {
+	fn from(_msg: ContextMessage<T>) -> Self {
 		Self {
 			id: 0,                      // or some logic to assign an ID
 			msg: "default".to_string(), // or use data from _msg
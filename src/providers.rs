@@ -0,0 +1,30 @@
+//! Built-in [`Llm`](crate::Llm) implementations for LLM providers.
+//!
+//! Each provider is gated behind a feature of the same name.
+#[cfg(feature = "gemini")]
+pub mod gemini;
+#[cfg(feature = "ollama")]
+pub mod ollama;
+
+#[cfg(any(feature = "gemini", feature = "ollama"))]
+use tracing::warn;
+
+#[cfg(any(feature = "gemini", feature = "ollama"))]
+use crate::Config;
+
+/// [`Config::TOP_P`] to forward to the provider, `None` when left at its default.
+///
+/// Setting both the `temperature` and [`Config::TOP_P`] is not recommended, so a warning is logged
+/// when both are set. Both are still forwarded since some use cases do rely on setting both.
+#[cfg(any(feature = "gemini", feature = "ollama"))]
+pub(crate) fn top_p<T: Config>(temperature: Option<f32>) -> Option<f32> {
+	if T::TOP_P == 1.0 {
+		return None;
+	}
+
+	if temperature.is_some() {
+		warn!("Both temperature and TOP_P are set, altering only one of them is recommended");
+	}
+
+	Some(T::TOP_P)
+}
@@ -0,0 +1,297 @@
+//! [Google Gemini](https://ai.google.dev) provider.
+//!
+//! Prompts are sent to the `generateContent` endpoint of the Gemini REST API. The API key is read
+//! from the `GEMINI_API_KEY` environment variable.
+//!
+//! Gemini only knows the `user` and `model` roles. System messages are sent as the system
+//! instruction preamble of the prompt, assistant messages with the `model` role and function
+//! messages with the `user` role.
+//!
+//! [`Config::STOP_SEQUENCES`], [`Config::SEED`] and [`Config::TOP_P`] are forwarded to Gemini.
+//!
+//! Gemini's tokenizer is not available locally, so tokens are approximated as one token per
+//! [`CHARS_PER_TOKEN`] characters.
+use std::{fmt::Display, sync::OnceLock};
+
+use async_openai::types::Role;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{
+	types::{LoomError, WrapperRole},
+	Config, ContextMessage, Llm, Result,
+};
+
+/// Number of characters approximated as a single token.
+pub const CHARS_PER_TOKEN: usize = 4;
+
+/// Role of a system instruction message.
+const SYSTEM_ROLE: &str = "system";
+
+/// A Gemini model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum GeminiModel {
+	#[default]
+	Pro,
+	Ultra,
+}
+
+impl GeminiModel {
+	/// Name of the model as known by the Gemini API.
+	pub const fn model_name(&self) -> &'static str {
+		match self {
+			Self::Pro => "gemini-1.0-pro",
+			Self::Ultra => "gemini-1.0-ultra",
+		}
+	}
+}
+
+/// Parameters for a Gemini prompt.
+#[derive(Debug, Clone, Default)]
+pub struct GeminiParameters {
+	pub temperature: Option<f32>,
+}
+
+/// A single chat message sent to Gemini.
+///
+/// The `role` is either `user`, `model` or `system`. `system` messages are moved to the system
+/// instruction before prompting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiMessage {
+	pub role: String,
+	pub content: String,
+}
+
+impl Display for GeminiMessage {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.content)
+	}
+}
+
+impl<T: Config> From<ContextMessage<T>> for GeminiMessage {
+	fn from(msg: ContextMessage<T>) -> Self {
+		let role = match msg.role {
+			WrapperRole::Role(Role::System) => SYSTEM_ROLE,
+			WrapperRole::Role(Role::Assistant) => "model",
+			WrapperRole::Role(_) => "user",
+		};
+
+		Self { role: role.to_string(), content: msg.content }
+	}
+}
+
+/// Response of the Gemini `generateContent` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiResponse {
+	#[serde(default)]
+	pub candidates: Vec<GeminiCandidate>,
+	#[serde(default)]
+	pub usage_metadata: Option<GeminiUsage>,
+}
+
+/// A generated response candidate.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiCandidate {
+	pub content: GeminiContent,
+	#[serde(default)]
+	pub finish_reason: Option<String>,
+}
+
+/// Token usage reported by Gemini.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiUsage {
+	#[serde(default)]
+	pub prompt_token_count: Option<u32>,
+	#[serde(default)]
+	pub candidates_token_count: Option<u32>,
+}
+
+/// Content of a Gemini message, made of text parts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiContent {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub role: Option<String>,
+	#[serde(default)]
+	pub parts: Vec<GeminiPart>,
+}
+
+/// A text part of a Gemini message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiPart {
+	#[serde(default)]
+	pub text: String,
+}
+
+impl From<GeminiResponse> for Option<String> {
+	fn from(res: GeminiResponse) -> Self {
+		let candidate = res.candidates.into_iter().next()?;
+		Some(candidate.content.parts.into_iter().map(|part| part.text).collect())
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiRequest {
+	contents: Vec<GeminiContent>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	system_instruction: Option<GeminiContent>,
+	generation_config: GeminiGenerationConfig,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerationConfig {
+	max_output_tokens: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	temperature: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	top_p: Option<f32>,
+	#[serde(skip_serializing_if = "<[_]>::is_empty")]
+	stop_sequences: &'static [&'static str],
+	#[serde(skip_serializing_if = "Option::is_none")]
+	seed: Option<u64>,
+}
+
+/// Split `msgs` into the system instruction preamble and the conversation contents.
+fn build_contents(msgs: Vec<GeminiMessage>) -> (Option<GeminiContent>, Vec<GeminiContent>) {
+	let (system, conversation): (Vec<_>, Vec<_>) =
+		msgs.into_iter().partition(|msg| msg.role == SYSTEM_ROLE);
+
+	let system_instruction = (!system.is_empty()).then(|| GeminiContent {
+		role: None,
+		parts: system.into_iter().map(|msg| GeminiPart { text: msg.content }).collect(),
+	});
+
+	let contents = conversation
+		.into_iter()
+		.map(|msg| GeminiContent {
+			role: Some(msg.role),
+			parts: vec![GeminiPart { text: msg.content }],
+		})
+		.collect();
+
+	(system_instruction, contents)
+}
+
+#[async_trait]
+impl<T: Config> Llm<T> for GeminiModel {
+	type Tokens = u32;
+	type Request = GeminiMessage;
+	type Response = GeminiResponse;
+	type Parameters = GeminiParameters;
+
+	fn max_context_length(&self) -> Self::Tokens {
+		match self {
+			Self::Pro | Self::Ultra => 30_720,
+		}
+	}
+
+	fn name(&self) -> &'static str {
+		self.model_name()
+	}
+
+	fn alias(&self) -> &'static str {
+		match self {
+			Self::Pro => "gemini-pro",
+			Self::Ultra => "gemini-ultra",
+		}
+	}
+
+	/// Functions are not forwarded to Gemini.
+	fn supports_function_calling(&self) -> bool {
+		false
+	}
+
+	/// Images are not forwarded to Gemini.
+	fn supports_vision(&self) -> bool {
+		false
+	}
+
+	fn count_tokens(content: &str) -> Result<Self::Tokens> {
+		let chars = content.chars().count();
+
+		u32::try_from(chars.div_ceil(CHARS_PER_TOKEN)).map_err(|_| {
+			LoomError::Error(format!("Number of tokens exceeds max tokens for model: {}", content))
+				.into()
+		})
+	}
+
+	async fn prompt(
+		&self,
+		_is_summarizing: bool,
+		_prompt_tokens: Self::Tokens,
+		msgs: Vec<Self::Request>,
+		params: &Self::Parameters,
+		max_tokens: Self::Tokens,
+	) -> Result<Self::Response> {
+		let client = get_client();
+
+		let Some(api_key) = client.api_key.as_deref() else {
+			error!("GEMINI_API_KEY is not set");
+			return Err(LoomError::Error("GEMINI_API_KEY is not set".to_string()).into());
+		};
+
+		let (system_instruction, contents) = build_contents(msgs);
+		let req = GeminiRequest {
+			contents,
+			system_instruction,
+			generation_config: GeminiGenerationConfig {
+				max_output_tokens: max_tokens,
+				temperature: params.temperature,
+				top_p: super::top_p::<T>(params.temperature),
+				stop_sequences: T::STOP_SEQUENCES,
+				seed: T::SEED,
+			},
+		};
+
+		debug!("Prompting Gemini model {}", self.model_name());
+
+		let res = client
+			.http
+			.post(format!("{}/v1beta/models/{}:generateContent", client.host, self.model_name()))
+			.query(&[("key", api_key)])
+			.json(&req)
+			.send()
+			.await
+			.and_then(|res| res.error_for_status())
+			.map_err(|e| {
+				error!("Failed to prompt Gemini: {}", e);
+				e
+			})?
+			.json::<GeminiResponse>()
+			.await
+			.map_err(|e| {
+				error!("Failed to parse Gemini response: {}", e);
+				e
+			})?;
+
+		Ok(res)
+	}
+}
+
+struct GeminiClient {
+	http: reqwest::Client,
+	host: String,
+	api_key: Option<String>,
+}
+
+/// Gemini HTTP client
+static GEMINI_CLIENT: OnceLock<GeminiClient> = OnceLock::new();
+
+/// Get the Gemini client.
+fn get_client() -> &'static GeminiClient {
+	GEMINI_CLIENT.get_or_init(|| {
+		debug!("Initializing Gemini client");
+
+		GeminiClient {
+			http: reqwest::Client::new(),
+			host: "https://generativelanguage.googleapis.com".to_string(),
+			api_key: std::env::var("GEMINI_API_KEY").ok(),
+		}
+	})
+}
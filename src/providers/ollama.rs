@@ -0,0 +1,206 @@
+//! [Ollama](https://ollama.com) provider for locally hosted LLMs.
+//!
+//! Prompts are sent to the `/api/chat` endpoint of the Ollama REST API. The Ollama host is read
+//! from the `OLLAMA_HOST` environment variable and defaults to `http://localhost:11434`.
+//!
+//! [`Config::STOP_SEQUENCES`], [`Config::SEED`] and [`Config::TOP_P`] are forwarded to Ollama.
+//!
+//! Ollama models use a variety of tokenizers, so tokens are approximated from the number of
+//! whitespace separated words using [`Llm::TOKEN_WORD_RATIO`].
+use std::{fmt::Display, sync::OnceLock};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{types::LoomError, Config, ContextMessage, Llm, Result};
+
+/// An Ollama model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OllamaModel {
+	/// Name of the model as known by Ollama (e.g. `llama3`).
+	pub name: &'static str,
+	/// Maximum number of tokens the model can process at once.
+	pub context_length: u32,
+}
+
+impl OllamaModel {
+	pub const fn new(name: &'static str, context_length: u32) -> Self {
+		Self { name, context_length }
+	}
+}
+
+impl Default for OllamaModel {
+	fn default() -> Self {
+		Self::new("llama3", 8192)
+	}
+}
+
+/// Parameters for an Ollama prompt.
+#[derive(Debug, Clone, Default)]
+pub struct OllamaParameters {
+	pub temperature: Option<f32>,
+}
+
+/// A single chat message sent to or received from Ollama.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaMessage {
+	pub role: String,
+	pub content: String,
+}
+
+impl Display for OllamaMessage {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.content)
+	}
+}
+
+impl<T: Config> From<ContextMessage<T>> for OllamaMessage {
+	fn from(msg: ContextMessage<T>) -> Self {
+		Self { role: msg.role.into(), content: msg.content }
+	}
+}
+
+/// Response of the Ollama `/api/chat` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OllamaResponse {
+	pub model: String,
+	pub message: OllamaMessage,
+	#[serde(default)]
+	pub prompt_eval_count: Option<u32>,
+	#[serde(default)]
+	pub eval_count: Option<u32>,
+}
+
+impl From<OllamaResponse> for Option<String> {
+	fn from(res: OllamaResponse) -> Self {
+		Some(res.message.content)
+	}
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+	model: &'a str,
+	messages: Vec<OllamaMessage>,
+	stream: bool,
+	options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+	num_predict: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	temperature: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	top_p: Option<f32>,
+	#[serde(skip_serializing_if = "<[_]>::is_empty")]
+	stop: &'static [&'static str],
+	#[serde(skip_serializing_if = "Option::is_none")]
+	seed: Option<u64>,
+}
+
+#[async_trait]
+impl<T: Config> Llm<T> for OllamaModel {
+	type Tokens = u32;
+	type Request = OllamaMessage;
+	type Response = OllamaResponse;
+	type Parameters = OllamaParameters;
+
+	fn max_context_length(&self) -> Self::Tokens {
+		self.context_length
+	}
+
+	fn name(&self) -> &'static str {
+		self.name
+	}
+
+	fn alias(&self) -> &'static str {
+		self.name
+	}
+
+	/// Functions are not forwarded to Ollama.
+	fn supports_function_calling(&self) -> bool {
+		false
+	}
+
+	/// Images are not forwarded to Ollama.
+	fn supports_vision(&self) -> bool {
+		false
+	}
+
+	fn count_tokens(content: &str) -> Result<Self::Tokens> {
+		let words = content.split_whitespace().count();
+		let ratio = <Self as Llm<T>>::TOKEN_WORD_RATIO.get().max(1) as usize;
+
+		u32::try_from(words.saturating_mul(100).div_ceil(ratio)).map_err(|_| {
+			LoomError::Error(format!("Number of tokens exceeds max tokens for model: {}", content))
+				.into()
+		})
+	}
+
+	async fn prompt(
+		&self,
+		_is_summarizing: bool,
+		_prompt_tokens: Self::Tokens,
+		msgs: Vec<Self::Request>,
+		params: &Self::Parameters,
+		max_tokens: Self::Tokens,
+	) -> Result<Self::Response> {
+		let client = get_client();
+
+		let req = OllamaChatRequest {
+			model: self.name,
+			messages: msgs,
+			stream: false,
+			options: OllamaOptions {
+				num_predict: max_tokens,
+				temperature: params.temperature,
+				top_p: super::top_p::<T>(params.temperature),
+				stop: T::STOP_SEQUENCES,
+				seed: T::SEED,
+			},
+		};
+
+		debug!("Prompting Ollama model {}", self.name);
+
+		let res = client
+			.http
+			.post(format!("{}/api/chat", client.host))
+			.json(&req)
+			.send()
+			.await
+			.and_then(|res| res.error_for_status())
+			.map_err(|e| {
+				error!("Failed to prompt Ollama: {}", e);
+				e
+			})?
+			.json::<OllamaResponse>()
+			.await
+			.map_err(|e| {
+				error!("Failed to parse Ollama response: {}", e);
+				e
+			})?;
+
+		Ok(res)
+	}
+}
+
+struct OllamaClient {
+	http: reqwest::Client,
+	host: String,
+}
+
+/// Ollama HTTP client
+static OLLAMA_CLIENT: OnceLock<OllamaClient> = OnceLock::new();
+
+/// Get the Ollama client.
+fn get_client() -> &'static OllamaClient {
+	OLLAMA_CLIENT.get_or_init(|| {
+		debug!("Initializing Ollama client");
+
+		let host =
+			std::env::var("OLLAMA_HOST").unwrap_or_else(|_| "http://localhost:11434".to_string());
+
+		OllamaClient { http: reqwest::Client::new(), host: host.trim_end_matches('/').to_string() }
+	})
+}
@@ -0,0 +1,70 @@
+//! Masking of sensitive content before it is stored.
+//!
+//! Regulatory environments may require that PII (Personally Identifiable Information) is never
+//! persisted. The [`Config::RedactionFilter`](crate::Config::RedactionFilter) is applied to the
+//! content of every [`ContextMessage`](crate::ContextMessage) before it is saved by
+//! [`Config::Chest`](crate::Config::Chest).
+#[cfg(feature = "redaction")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "redaction")]
+use regex::Regex;
+
+/// Replacement for redacted content.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Masks sensitive content of a message.
+pub trait RedactionFilter: Default + Send + Sync {
+	/// Return `content` with all sensitive content masked.
+	fn redact(&self, content: &str) -> String;
+}
+
+/// [`RedactionFilter`] which leaves content untouched.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoRedaction;
+
+impl RedactionFilter for NoRedaction {
+	fn redact(&self, content: &str) -> String {
+		content.to_string()
+	}
+}
+
+/// [`RedactionFilter`] replacing email addresses, phone numbers and credit card numbers with
+/// [`REDACTED`].
+///
+/// Patterns are matched on a best effort basis and are not a substitute for a dedicated PII
+/// detection service.
+#[cfg(feature = "redaction")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RegexRedactionFilter;
+
+#[cfg(feature = "redaction")]
+static PII_PATTERNS: OnceLock<Vec<Regex>> = OnceLock::new();
+
+#[cfg(feature = "redaction")]
+impl RegexRedactionFilter {
+	fn patterns() -> &'static [Regex] {
+		PII_PATTERNS.get_or_init(|| {
+			[
+				// Email addresses
+				r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+				// Credit card numbers, optionally grouped by spaces or dashes
+				r"\b(?:\d[ -]?){12,18}\d\b",
+				// Phone numbers, optionally with a country code
+				r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{1,4}\)[ .-]?)?\d{2,4}[ .-]\d{3,4}[ .-]\d{3,4}\b",
+			]
+			.into_iter()
+			.map(|pattern| Regex::new(pattern).expect("Invalid PII pattern"))
+			.collect()
+		})
+	}
+}
+
+#[cfg(feature = "redaction")]
+impl RedactionFilter for RegexRedactionFilter {
+	fn redact(&self, content: &str) -> String {
+		Self::patterns().iter().fold(content.to_string(), |content, pattern| {
+			pattern.replace_all(&content, REDACTED).into_owned()
+		})
+	}
+}
@@ -0,0 +1,68 @@
+//! Relevance scoring of messages against a query using [Okapi BM25].
+//!
+//! Used by [`TapestryFragment::compress_by_relevance`](crate::TapestryFragment::compress_by_relevance)
+//! to keep the messages most relevant to a prompt instead of summarizing or sliding.
+//!
+//! Only available with the `relevance-compression` feature.
+//!
+//! [Okapi BM25]: https://en.wikipedia.org/wiki/Okapi_BM25
+use std::collections::{HashMap, HashSet};
+
+/// Term frequency saturation.
+const K1: f64 = 1.2;
+/// Document length normalization.
+const B: f64 = 0.75;
+
+/// BM25 score of every document of `documents` against `query`, in the same order.
+///
+/// Terms are the lowercase alphanumeric words of the texts. Documents sharing no term with the
+/// `query` score `0`.
+pub fn bm25_scores<'a>(query: &str, documents: impl IntoIterator<Item = &'a str>) -> Vec<f64> {
+	let documents = documents.into_iter().map(terms).collect::<Vec<_>>();
+	if documents.is_empty() {
+		return vec![];
+	}
+
+	let query_terms = terms(query).into_iter().collect::<HashSet<_>>();
+	let avg_len = documents.iter().map(Vec::len).sum::<usize>() as f64 / documents.len() as f64;
+
+	// Number of documents containing each query term
+	let mut document_frequencies = HashMap::<&str, usize>::new();
+	for document in &documents {
+		for term in document
+			.iter()
+			.filter(|term| query_terms.contains(*term))
+			.collect::<HashSet<_>>()
+		{
+			*document_frequencies.entry(term).or_default() += 1;
+		}
+	}
+
+	documents
+		.iter()
+		.map(|document| {
+			let len_norm = 1.0 - B + B * document.len() as f64 / avg_len.max(1.0);
+
+			document_frequencies
+				.iter()
+				.map(|(term, document_frequency)| {
+					let tf = document.iter().filter(|t| t == term).count() as f64;
+					let idf = ((documents.len() as f64 - *document_frequency as f64 + 0.5) /
+						(*document_frequency as f64 + 0.5) +
+						1.0)
+					.ln();
+
+					idf * tf * (K1 + 1.0) / (tf + K1 * len_norm)
+				})
+				.sum()
+		})
+		.collect()
+}
+
+/// Lowercase alphanumeric words of `text`.
+fn terms(text: &str) -> Vec<String> {
+	text.split(|c: char| !c.is_alphanumeric())
+		.filter(|word| !word.is_empty())
+		.map(str::to_lowercase)
+		.collect()
+}
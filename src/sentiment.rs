@@ -0,0 +1,44 @@
+//! Sentiment analysis of messages before they are stored.
+//!
+//! The [`Config::SentimentAnalyzer`](crate::Config::SentimentAnalyzer) sets the `sentiment` of
+//! every [`ContextMessage`](crate::ContextMessage) saved by
+//! [`Config::Chest`](crate::Config::Chest) which does not have one yet, e.g. to track the
+//! emotional arc of a conversation.
+use serde::{Deserialize, Serialize};
+
+/// Sentiment expressed by a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Sentiment {
+	Positive,
+	Negative,
+	Neutral,
+	/// The sentiment could not be determined.
+	Unknown,
+}
+
+/// Determines the sentiment of a message.
+pub trait SentimentAnalyzer: Default + Send + Sync {
+	/// Sentiment expressed by `content`.
+	fn analyze(&self, content: &str) -> Sentiment;
+	/// Sentiment to store alongside `content`.
+	///
+	/// Defaults to the result of [`SentimentAnalyzer::analyze`].
+	fn sentiment(&self, content: &str) -> Option<Sentiment> {
+		Some(self.analyze(content))
+	}
+}
+
+/// [`SentimentAnalyzer`] which leaves the sentiment of messages unset.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoSentimentAnalysis;
+
+impl SentimentAnalyzer for NoSentimentAnalysis {
+	fn analyze(&self, _content: &str) -> Sentiment {
+		Sentiment::Unknown
+	}
+
+	fn sentiment(&self, _content: &str) -> Option<Sentiment> {
+		None
+	}
+}
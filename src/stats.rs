@@ -0,0 +1,90 @@
+//! Aggregated statistics of a tapestry.
+use std::collections::HashSet;
+
+use async_openai::types::Role;
+use chrono::{DateTime, Utc};
+use num_traits::ToPrimitive;
+
+use crate::{
+	storage::with_storage_timeout,
+	types::{LoomError, StorageError, WrapperRole},
+	Config, TapestryChestHandler, TapestryFragment, TapestryId,
+};
+
+/// Statistics aggregated across all [`TapestryFragment`] instances of a [`TapestryId`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversationStats {
+	pub total_fragments: usize,
+	pub total_messages: usize,
+	/// Sum of the `context_tokens` of all tapestry fragments.
+	pub total_tokens: u64,
+	pub user_messages: usize,
+	pub assistant_messages: usize,
+	pub first_message_at: Option<DateTime<Utc>>,
+	pub last_message_at: Option<DateTime<Utc>>,
+	pub unique_account_ids: HashSet<String>,
+}
+
+impl ConversationStats {
+	/// Add the messages of `tapestry_fragment` to the statistics.
+	pub(crate) fn record_fragment<T: Config>(&mut self, tapestry_fragment: &TapestryFragment<T>) {
+		self.total_fragments += 1;
+		self.total_tokens += tapestry_fragment.context_tokens.to_u64().unwrap_or_default();
+
+		for msg in &tapestry_fragment.context_messages {
+			self.total_messages += 1;
+
+			match msg.role {
+				WrapperRole::Role(Role::User) => self.user_messages += 1,
+				WrapperRole::Role(Role::Assistant) => self.assistant_messages += 1,
+				_ => {},
+			}
+
+			if let Ok(timestamp) = DateTime::parse_from_rfc3339(&msg.timestamp) {
+				let timestamp = timestamp.with_timezone(&Utc);
+				self.first_message_at =
+					Some(self.first_message_at.map_or(timestamp, |t| t.min(timestamp)));
+				self.last_message_at =
+					Some(self.last_message_at.map_or(timestamp, |t| t.max(timestamp)));
+			}
+
+			if let Some(account_id) = &msg.account_id {
+				self.unique_account_ids.insert(account_id.clone());
+			}
+		}
+	}
+}
+
+/// Compute the [`ConversationStats`] of `tapestry_id`.
+///
+/// Tapestry fragment instances are loaded from [`Config::Chest`] one at a time so that the whole
+/// tapestry is never held in memory at once. Deleted instances are skipped.
+pub async fn compute_stats<T: Config, TID: TapestryId>(
+	tapestry_id: TID,
+) -> crate::Result<ConversationStats> {
+	let mut stats = ConversationStats::default();
+
+	let instance_count =
+		match with_storage_timeout::<T, _>(T::Chest::get_tapestry(tapestry_id.clone())).await? {
+			Some(instance_count) => instance_count,
+			None => return Ok(stats),
+		};
+
+	for instance in 1..=instance_count as u64 {
+		match with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+			tapestry_id.clone(),
+			Some(instance),
+		))
+		.await
+		{
+			Ok(Some(tapestry_fragment)) => stats.record_fragment(&tapestry_fragment),
+			Ok(None) => {},
+			Err(e) => match LoomError::from(e) {
+				LoomError::Storage(StorageError::NotFound) => {},
+				e => return Err(e.into()),
+			},
+		}
+	}
+
+	Ok(stats)
+}
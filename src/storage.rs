@@ -1,17 +1,49 @@
 use async_trait::async_trait;
-use redis::{AsyncCommands, Client, Commands, Connection, RedisError, ToRedisArgs};
+use futures_util::{stream, Stream};
+use num_traits::{SaturatingSub, ToPrimitive};
+use redis::{
+	aio::MultiplexedConnection, AsyncCommands, Client, Commands, Connection, RedisError,
+	RedisResult, Script, ToRedisArgs,
+};
 use serde::de::DeserializeOwned;
-use std::fmt::{Debug, Display};
-use tokio::sync::OnceCell;
-use tracing::{debug, error, instrument};
+use std::{
+	fmt::{Debug, Display},
+	future::Future,
+	marker::PhantomData,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		OnceLock,
+	},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{broadcast, OnceCell};
+use tokio_stream::StreamExt;
+use tracing::{debug, error, instrument, warn, Instrument};
 
 use crate::{
-	types::{LoomError, PromptModelTokens, StorageError},
-	Config, ContextMessage, TapestryFragment, TapestryId,
+	types::{LoomError, PromptModelTokens, StorageError, StorageFormat, TapestryEvent, WeaveError},
+	Config, ContextMessage, HierarchicalId, Llm, TapestryFragment, TapestryId,
 };
 
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+pub mod fs;
+pub mod logging;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "s3")]
+pub mod s3;
+
 /// The key used to store the number of instances of a tapestry.
 const INSTANCE_COUNT: &str = "instance_count";
+/// Time after which a tapestry lock expires if it was never released.
+///
+/// Prevents a crashed process from holding a lock forever.
+const LOCK_EXPIRY: Duration = Duration::from_secs(120);
+/// Base delay between attempts to acquire a tapestry lock.
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(25);
+/// Number of [`TapestryEvent`]s buffered for each [`TapestryChestHandler::watch`] receiver.
+const EVENT_CHANNEL_CAPACITY: usize = 64;
 
 /// A storage handler trait designed for saving and retrieving fragments of a tapestry.
 ///
@@ -55,6 +87,12 @@ pub trait TapestryChestHandler<T: Config> {
 		tapestry_id: TID,
 		metadata: M,
 	) -> crate::Result<()>;
+	/// Checks whether a tapestry exists without loading any of its data.
+	///
+	/// Defaults to checking whether [`TapestryChestHandler::get_tapestry`] finds the tapestry.
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		Ok(Self::get_tapestry(tapestry_id).await?.is_some())
+	}
 	/// Retrieves the number of instances of a tapestry.
 	///
 	/// Returns None if the tapestry does not exist.
@@ -79,13 +117,283 @@ pub trait TapestryChestHandler<T: Config> {
 	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
 		tapestry_id: TID,
 	) -> crate::Result<Option<M>>;
-	/// Deletes a tapestry and all its instances.
+	/// Deletes a tapestry and all its instances, keeping its lock and reserved tokens.
 	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()>;
 	/// Deletes a tapestry fragment.
 	async fn delete_tapestry_fragment<TID: TapestryId>(
 		tapestry_id: TID,
 		instance: Option<u64>,
 	) -> crate::Result<()>;
+	/// Acquires an advisory lock on a tapestry, retrying until `timeout` has elapsed.
+	///
+	/// Returns a token identifying the lock holder which must be passed to
+	/// [`TapestryChestHandler::unlock`]. Fails with [`WeaveError::LockTimeout`] if the lock could
+	/// not be acquired in time.
+	///
+	/// Prefer using [`TapestryLock`] which releases the lock when dropped.
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String>;
+	/// Releases an advisory lock on a tapestry previously acquired with
+	/// [`TapestryChestHandler::lock`].
+	///
+	/// Does nothing if the lock is no longer held by `token`.
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()>;
+	/// Adds `tokens` to the number of tokens reserved on a tapestry.
+	///
+	/// Returns the total number of tokens reserved after the reservation.
+	///
+	/// Prefer using [`TokenBudgetGuard`] which releases the reservation when dropped.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn reserve_tokens<TID: TapestryId>(
+		_tapestry_id: &TID,
+		_tokens: u64,
+	) -> crate::Result<u64> {
+		unsupported("reserve_tokens")
+	}
+	/// Removes `tokens` from the number of tokens reserved on a tapestry.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn release_tokens<TID: TapestryId>(
+		_tapestry_id: &TID,
+		_tokens: u64,
+	) -> crate::Result<()> {
+		unsupported("release_tokens")
+	}
+	/// Gets the total number of tokens reserved on a tapestry.
+	///
+	/// Defaults to `0`, since no tokens can be reserved without
+	/// [`TapestryChestHandler::reserve_tokens`].
+	async fn get_reserved_tokens<TID: TapestryId>(_tapestry_id: &TID) -> crate::Result<u64> {
+		Ok(0)
+	}
+	/// Increments the number of [`Loom::weave`](crate::Loom::weave) calls made on a tapestry.
+	///
+	/// Returns the number of calls after the increment. The count is deleted along with the
+	/// tapestry.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`], which [`crate::Loom::weave`]
+	/// ignores unless [`Config::MAX_WEAVE_CALLS`] is set.
+	async fn increment_weave_count<TID: TapestryId>(_tapestry_id: &TID) -> crate::Result<u64> {
+		unsupported("increment_weave_count")
+	}
+	/// Gets the number of [`Loom::weave`](crate::Loom::weave) calls made on a tapestry.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn get_weave_count<TID: TapestryId>(_tapestry_id: &TID) -> crate::Result<u64> {
+		unsupported("get_weave_count")
+	}
+	/// Recounts the tokens of every tapestry fragment instance and re-saves the ones with a stale
+	/// `context_tokens` value.
+	///
+	/// See [`TapestryFragment::recount_tokens`].
+	///
+	/// Returns the number of tapestry fragment instances that were repaired.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn repair_token_counts<TID: TapestryId>(_tapestry_id: TID) -> crate::Result<usize> {
+		unsupported("repair_token_counts")
+	}
+	/// Sets the time after which a tapestry and all its instances expire.
+	///
+	/// Saving a tapestry fragment resets the expiry to [`Config::FRAGMENT_TTL_SECONDS`] if set.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn set_ttl<TID: TapestryId>(_tapestry_id: TID, _ttl: Duration) -> crate::Result<()> {
+		unsupported("set_ttl")
+	}
+	/// Gets the number of bytes taken by all tapestry fragment instances of a tapestry.
+	///
+	/// Returns `0` if the tapestry does not exist.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn get_total_storage_bytes<TID: TapestryId>(_tapestry_id: TID) -> crate::Result<u64> {
+		unsupported("get_total_storage_bytes")
+	}
+	/// Lists the tapestries directly below `parent`, sorted by their base key.
+	///
+	/// Only children which exist themselves are listed, regardless of any deeper descendants.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn list_children(_parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		unsupported("list_children")
+	}
+	/// Lists the base keys of all stored tapestries starting with [`Config::KEY_SCAN_PREFIX`],
+	/// sorted.
+	///
+	/// Raw base keys are returned since the [`TapestryId`] type is chosen by the caller.
+	///
+	/// Defaults to failing with [`StorageError::Unsupported`].
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		unsupported("list_all_tapestry_ids")
+	}
+	/// Lazily loads the tapestry fragment instances of a tapestry in ascending order.
+	///
+	/// Instances are fetched from storage one at a time as the stream is polled, so callers can
+	/// stop early without loading the whole tapestry. Deleted instances are skipped.
+	fn iter_fragments<TID: TapestryId>(
+		tapestry_id: TID,
+	) -> impl Stream<Item = crate::Result<(u64, TapestryFragment<T>)>> + Send {
+		stream::unfold(
+			(tapestry_id, None::<u64>, 1),
+			|(tapestry_id, instance_count, mut instance)| async move {
+				let instance_count = match instance_count {
+					Some(instance_count) => instance_count,
+					None => match Self::get_tapestry(tapestry_id.clone()).await {
+						Ok(instance_count) => instance_count.unwrap_or(0) as u64,
+						Err(e) => return Some((Err(e), (tapestry_id, Some(0), instance))),
+					},
+				};
+
+				while instance <= instance_count {
+					let next_state = (tapestry_id.clone(), Some(instance_count), instance + 1);
+
+					match Self::get_tapestry_fragment(tapestry_id.clone(), Some(instance)).await {
+						Ok(Some(tapestry_fragment)) =>
+							return Some((Ok((instance, tapestry_fragment)), next_state)),
+						Ok(None) => {},
+						Err(e) => match LoomError::from(e) {
+							LoomError::Storage(StorageError::NotFound) => {},
+							e => return Some((Err(e.into()), next_state)),
+						},
+					}
+
+					instance += 1;
+				}
+
+				None
+			},
+		)
+	}
+	/// Runs `f` while holding the lock of `tapestry_id`, so that the operations it performs on
+	/// the tapestry are not interleaved with those of [`crate::Loom::weave`] or other
+	/// transactions.
+	///
+	/// This only provides isolation. No backend rolls back the operations already performed if
+	/// `f` fails. Redis `MULTI`/`EXEC` blocks cannot be used since they cannot return the results
+	/// of reads to `f` before the block is executed. Locks are only honored by callers taking
+	/// them, so other writers are not excluded.
+	///
+	/// `f` must not call methods which lock the tapestry themselves, such as
+	/// [`crate::Loom::weave`], or it waits until the lock times out.
+	async fn transaction<TID, F, Fut, R>(tapestry_id: &TID, f: F) -> crate::Result<R>
+	where
+		TID: TapestryId,
+		F: FnOnce() -> Fut + Send,
+		Fut: Future<Output = crate::Result<R>> + Send,
+		R: Send,
+	{
+		let token = Self::lock(tapestry_id, Duration::from_millis(T::LOCK_TIMEOUT_MS)).await?;
+
+		let res = f().await;
+		if res.is_err() {
+			error!("Transaction on {} failed", tapestry_id.base_key());
+		}
+
+		let unlocked = Self::unlock(tapestry_id, token).await;
+		let res = res?;
+		unlocked?;
+
+		Ok(res)
+	}
+	/// Retrieves the tapestry fragments at the specified `instances` of a tapestry.
+	///
+	/// Returns the tapestry fragments in the same order as `instances`, with `None` for instances
+	/// which do not exist.
+	///
+	/// Defaults to calling [`TapestryChestHandler::get_tapestry_fragment`] for each instance in
+	/// turn. Storage backends able to batch reads should override this.
+	async fn get_tapestry_fragment_batch<TID: TapestryId>(
+		tapestry_id: TID,
+		instances: Vec<u64>,
+	) -> crate::Result<Vec<Option<TapestryFragment<T>>>> {
+		let mut tapestry_fragments = Vec::with_capacity(instances.len());
+		for instance in instances {
+			match Self::get_tapestry_fragment(tapestry_id.clone(), Some(instance)).await {
+				Ok(tapestry_fragment) => tapestry_fragments.push(tapestry_fragment),
+				Err(e) => match LoomError::from(e) {
+					LoomError::Storage(StorageError::NotFound) => tapestry_fragments.push(None),
+					e => return Err(e.into()),
+				},
+			}
+		}
+
+		Ok(tapestry_fragments)
+	}
+	/// Searches the messages of all tapestry fragment instances of a tapestry for `query`.
+	///
+	/// Returns `(instance, message_index, message)` for every message whose `content` contains
+	/// `query`, in chronological order.
+	///
+	/// Defaults to a substring scan of [`TapestryChestHandler::iter_fragments`], which is
+	/// acceptable for moderate history sizes. Storage backends with native text search should
+	/// override this.
+	async fn search_messages<TID: TapestryId>(
+		tapestry_id: TID,
+		query: &str,
+		case_sensitive: bool,
+	) -> crate::Result<Vec<(u64, usize, ContextMessage<T>)>> {
+		let query = if case_sensitive { query.to_string() } else { query.to_lowercase() };
+
+		let mut results = vec![];
+		let mut fragments = std::pin::pin!(Self::iter_fragments(tapestry_id));
+		while let Some(fragment) = fragments.next().await {
+			let (instance, tapestry_fragment) = fragment?;
+
+			for (index, msg) in tapestry_fragment.context_messages.into_iter().enumerate() {
+				let is_match = if case_sensitive {
+					msg.content.contains(&query)
+				} else {
+					msg.content.to_lowercase().contains(&query)
+				};
+
+				if is_match {
+					results.push((instance, index, msg));
+				}
+			}
+		}
+
+		Ok(results)
+	}
+	/// Appends `msgs` to the current tapestry fragment instance of a tapestry and keeps only its
+	/// last `max_messages` messages, creating the tapestry if it does not exist.
+	///
+	/// Returns the saved tapestry fragment, with its `context_tokens` recounted.
+	///
+	/// Defaults to reading and saving the tapestry fragment while holding
+	/// [`TapestryChestHandler::lock`] for at most [`Config::LOCK_TIMEOUT_MS`]. Storage backends
+	/// with atomic operations should override this.
+	async fn atomic_append_and_trim<TID: TapestryId>(
+		tapestry_id: TID,
+		msgs: Vec<ContextMessage<T>>,
+		max_messages: usize,
+	) -> crate::Result<TapestryFragment<T>> {
+		let token = Self::lock(&tapestry_id, Duration::from_millis(T::LOCK_TIMEOUT_MS)).await?;
+
+		let result = async {
+			let mut tapestry_fragment = Self::get_tapestry_fragment(tapestry_id.clone(), None)
+				.await?
+				.unwrap_or_default();
+			append_and_trim(&mut tapestry_fragment, msgs, max_messages)?;
+
+			Self::save_tapestry_fragment(&tapestry_id, tapestry_fragment.clone(), false).await?;
+
+			Ok(tapestry_fragment)
+		}
+		.await;
+
+		if let Err(e) = Self::unlock(&tapestry_id, token).await {
+			error!("Failed to unlock {}: {}", tapestry_id.base_key(), e);
+		}
+
+		result
+	}
+	/// Subscribes to changes of a tapestry, including changes made by other processes.
+	///
+	/// Defaults to a receiver which is closed without receiving any events, for storage backends
+	/// which do not support change notifications.
+	fn watch<TID: TapestryId>(_tapestry_id: TID) -> broadcast::Receiver<TapestryEvent<T>> {
+		broadcast::channel(1).1
+	}
 }
 
 /// Default implementation of [`Config::Chest`]
@@ -93,6 +401,47 @@ pub trait TapestryChestHandler<T: Config> {
 /// Storing and retrieving data using a Redis instance.
 pub struct TapestryChest;
 
+/// Connection settings of [`TapestryChest`], see [`TapestryChest::with_config`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct RedisConfig {
+	pub url: String,
+	/// Number of times opening a connection is retried after a connection refusal or an I/O
+	/// error. Other errors, such as authentication failures, are never retried.
+	pub max_retries: u32,
+	/// Delay before the first retry, doubled before every following retry.
+	pub retry_delay_ms: u64,
+}
+
+impl RedisConfig {
+	/// Settings for the Redis instance at `url`, retrying 3 times starting after 100
+	/// milliseconds.
+	pub fn new(url: &str) -> Self {
+		Self { url: url.to_string(), max_retries: 3, retry_delay_ms: 100 }
+	}
+
+	/// Settings for the Redis instance configured by the `REDIS_PROTOCOL`, `REDIS_HOST`,
+	/// `REDIS_PORT` and `REDIS_PASSWORD` environment variables, see [`RedisConfig::new`].
+	pub fn from_env() -> Self {
+		let protocol = std::env::var("REDIS_PROTOCOL").unwrap_or_else(|_| "redis".to_string());
+		let host = std::env::var("REDIS_HOST").unwrap_or_else(|_| "redis".to_string());
+		let port = std::env::var("REDIS_PORT").unwrap_or_else(|_| "6379".to_string());
+		let password = std::env::var("REDIS_PASSWORD").unwrap_or_default();
+
+		Self::new(&format!("{}://:{}@{}:{}", protocol, password, host, port))
+	}
+}
+
+/// Redacts the password of the `url`.
+impl Debug for RedisConfig {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("RedisConfig")
+			.field("url", &redacted_url(&self.url))
+			.field("max_retries", &self.max_retries)
+			.field("retry_delay_ms", &self.retry_delay_ms)
+			.finish()
+	}
+}
+
 #[async_trait]
 impl<T: Config> TapestryChestHandler<T> for TapestryChest {
 	type Error = StorageError;
@@ -102,53 +451,103 @@ impl<T: Config> TapestryChestHandler<T> for TapestryChest {
 		tapestry_fragment: TapestryFragment<T>,
 		increment: bool,
 	) -> crate::Result<u64> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_connection()?;
-		let base_key = &tapestry_id.base_key();
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = connection(&client).await?;
+			let base_key = &validated_base_key(tapestry_id)?;
+
+			let mut tapestry_instance =
+				verify_and_get_instance(&mut con, base_key, None).await?.unwrap_or(0);
+
+			let context_messages =
+				T::STORAGE_FORMAT.serialize(&tapestry_fragment.context_messages)?;
+
+			// Events are only built when someone is watching since finding the added messages
+			// requires loading the current tapestry fragment instance
+			let channel = event_channel(base_key);
+			let is_watched = event_subscribers(&mut con, &channel) > 0;
+			let previous_messages = if is_watched && !increment && tapestry_instance > 0 {
+				let bytes: Option<Vec<u8>> = con
+					.hget(format!("{base_key}:{tapestry_instance}"), "context_messages")
+					.map_err(|e| {
+						error!("Failed to get {} tapestry fragment: {}", base_key, e);
+						LoomError::from(StorageError::Redis(e))
+					})?;
+				match bytes {
+					Some(bytes) =>
+						T::STORAGE_FORMAT.deserialize::<Vec<ContextMessage<T>>>(&bytes)?.len(),
+					None => 0,
+				}
+			} else {
+				0
+			};
 
-		let mut tapestry_instance =
-			verify_and_get_instance(&mut con, base_key, None).await?.unwrap_or(0);
+			redis::transaction(&mut con, &[base_key], |con, pipe| {
+				// If the tapestry does not exist (i.e. instance is at 0), then set it to 1
+				if tapestry_instance == 0 {
+					pipe.hset(base_key, INSTANCE_COUNT, 1).ignore();
+					debug!("Saved \"instance_count\" member to {} key", base_key);
 
-		redis::transaction(&mut con, &[base_key], |con, pipe| {
-			// If the tapestry does not exist (i.e. instance is at 0), then set it to 1
-			if tapestry_instance == 0 {
-				pipe.hset(base_key, INSTANCE_COUNT, 1).ignore();
-				debug!("Saved \"instance_count\" member to {} key", base_key);
+					tapestry_instance = 1
+				};
 
-				tapestry_instance = 1
-			};
+				if increment {
+					pipe.hincr(base_key, INSTANCE_COUNT, 1).ignore();
 
-			if increment {
-				pipe.hincr(base_key, INSTANCE_COUNT, 1).ignore();
+					tapestry_instance += 1;
 
-				tapestry_instance += 1;
+					debug!("Incremented instance to {} for {}", tapestry_instance, base_key);
+				}
 
-				debug!("Incremented instance to {} for {}", tapestry_instance, base_key);
-			}
+				let instance_key = format!("{base_key}:{tapestry_instance}");
 
-			let instance_key = format!("{base_key}:{tapestry_instance}");
+				pipe.hset(&instance_key, "context_tokens", tapestry_fragment.context_tokens)
+					.ignore();
+				debug!("Saved \"context_tokens\" member to {} key", instance_key);
 
-			pipe.hset(&instance_key, "context_tokens", tapestry_fragment.context_tokens)
-				.ignore();
-			debug!("Saved \"context_tokens\" member to {} key", instance_key);
+				pipe.hset(&instance_key, "context_messages", &context_messages).ignore();
+				debug!("Saved \"context_messages\" member to {} key", instance_key);
 
-			pipe.hset(
-				&instance_key,
-				"context_messages",
-				serde_json::to_vec(&tapestry_fragment.context_messages)
-					.map_err(RedisError::from)?,
-			)
-			.ignore();
-			debug!("Saved \"context_messages\" member to {} key", instance_key);
+				if let Some(parent_instance) = tapestry_fragment.parent_instance {
+					pipe.hset(&instance_key, "parent_instance", parent_instance).ignore();
+					debug!("Saved \"parent_instance\" member to {} key", instance_key);
+				}
 
-			pipe.query(con)
-		})
-		.map_err(|e| {
-			error!("Failed to save tapestry fragment: {}", e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+				// Expire all instances together with the tapestry
+				if let Some(ttl) = T::FRAGMENT_TTL_SECONDS {
+					for key in tapestry_keys(base_key, tapestry_instance) {
+						pipe.expire(&key, ttl as i64).ignore();
+					}
+					debug!("Set {} tapestry to expire in {} seconds", base_key, ttl);
+				}
+
+				pipe.query::<Option<()>>(con)
+			})
+			.map_err(|e| {
+				error!("Failed to save tapestry fragment: {}", e);
+				LoomError::from(StorageError::TransactionFailed(e.to_string()))
+			})?;
+
+			tracing::Span::current().record("tapestry.instance", tapestry_instance);
+
+			if is_watched {
+				let summarized = increment.then_some(TapestryEvent::FragmentSummarized);
+				let added = tapestry_fragment
+					.context_messages
+					.into_iter()
+					.skip(previous_messages)
+					.map(TapestryEvent::MessageAdded);
 
-		Ok(tapestry_instance)
+				for event in summarized.into_iter().chain(added) {
+					publish_event(&mut con, &channel, &event);
+				}
+			}
+
+			Ok(tapestry_instance)
+		}
+		.instrument(span)
+		.await
 	}
 
 	async fn save_tapestry_metadata<
@@ -158,210 +557,1298 @@ impl<T: Config> TapestryChestHandler<T> for TapestryChest {
 		tapestry_id: TID,
 		metadata: M,
 	) -> crate::Result<()> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_multiplexed_async_connection().await?;
-		debug!("Connected to Redis");
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+			debug!("Connected to Redis");
 
-		let key: &String = &tapestry_id.base_key();
+			let key: &String = &validated_base_key(&tapestry_id)?;
 
-		con.hset(key, "metadata", metadata.clone()).await.map_err(|e| {
-			error!("Failed to save \"metadata\" member to {} key: {}", key, e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+			con.hset::<_, _, _, ()>(key, "metadata", metadata.clone()).await.map_err(|e| {
+				error!("Failed to save \"metadata\" member to {} key: {}", key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
 
-		debug!("Saved \"metadata\" member to {} key with metadata {:?}", key, metadata.clone());
+			debug!("Saved \"metadata\" member to {} key with metadata {:?}", key, metadata.clone());
 
-		Ok(())
+			Ok(())
+		}
+		.instrument(span)
+		.await
 	}
 
-	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_multiplexed_async_connection().await?;
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
 
-		let base_key = &tapestry_id.base_key();
+			let base_key = &validated_base_key(&tapestry_id)?;
 
-		let exists: bool = con.exists(base_key).await.map_err(|e| {
-			error!("Failed to check if {} tapestry_id exists: {}", base_key, e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+			let exists: bool = con.exists(base_key).await.map_err(|e| {
+				error!("Failed to check if {} tapestry_id exists: {}", base_key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
 
-		if !exists {
-			return Ok(None);
+			Ok(exists)
 		}
+		.instrument(span)
+		.await
+	}
 
-		let tapestry: u16 = con.hget(base_key, INSTANCE_COUNT).await.map_err(|e| {
-			error!("Failed to get {} tapestry_id: {}", base_key, e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let base_key = &validated_base_key(&tapestry_id)?;
+
+			let exists: bool = con.exists(base_key).await.map_err(|e| {
+				error!("Failed to check if {} tapestry_id exists: {}", base_key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			if !exists {
+				return Ok(None);
+			}
+
+			let tapestry: u16 = con.hget(base_key, INSTANCE_COUNT).await.map_err(|e| {
+				error!("Failed to get {} tapestry_id: {}", base_key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
 
-		Ok(Some(tapestry))
+			Ok(Some(tapestry))
+		}
+		.instrument(span)
+		.await
 	}
 
 	async fn get_tapestry_fragment<TID: TapestryId>(
 		tapestry_id: TID,
 		instance: Option<u64>,
 	) -> crate::Result<Option<TapestryFragment<T>>> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_connection()?;
-		debug!("Connected to Redis");
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = connection(&client).await?;
+			debug!("Connected to Redis");
 
-		let base_key = &tapestry_id.base_key();
+			let base_key = &validated_base_key(&tapestry_id)?;
 
-		let instance = match verify_and_get_instance(&mut con, base_key, instance).await? {
-			Some(instance) => instance,
-			None => return Ok(None),
-		};
+			let instance = match verify_and_get_instance(&mut con, base_key, instance).await? {
+				Some(instance) => instance,
+				None => return Ok(None),
+			};
+
+			let key = format!("{base_key}:{instance}");
+
+			let tapestry_fragment = TapestryFragment {
+				context_tokens: {
+					let context_tokens_str: String =
+						con.hget(&key, "context_tokens").map_err(|e| {
+							error!(
+								"Failed to get \"context_tokens\" member from {} key: {}",
+								key, e
+							);
+							LoomError::from(StorageError::Redis(e))
+						})?;
+					context_tokens_str.parse::<PromptModelTokens<T>>().map_err(|_| {
+						error!("Failed to parse \"context_tokens\" member from key: {}", key);
+						StorageError::Parsing
+					})?
+				},
+				context_messages: {
+					let context_messages_raw: Vec<u8> =
+						con.hget(&key, "context_messages").map_err(|e| {
+							error!(
+								"Failed to get \"context_messages\" member from {} key: {}",
+								key, e
+							);
+							LoomError::from(StorageError::Redis(e))
+						})?;
+
+					T::STORAGE_FORMAT
+						.deserialize::<Vec<ContextMessage<T>>>(&context_messages_raw)?
+				},
+				parent_instance: con.hget(&key, "parent_instance").map_err(|e| {
+					error!("Failed to get \"parent_instance\" member from {} key: {}", key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?,
+			};
 
-		let key = format!("{base_key}:{instance}");
+			Ok(Some(tapestry_fragment))
+		}
+		.instrument(span)
+		.await
+	}
 
-		let tapestry_fragment = TapestryFragment {
-			context_tokens: {
-				let context_tokens_str: String = con.hget(&key, "context_tokens").map_err(|e| {
-					error!("Failed to get \"context_tokens\" member from {} key: {}", key, e);
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+			debug!("Connected to Redis");
+
+			let key = &validated_base_key(&tapestry_id)?;
+
+			let metadata_raw: Vec<u8> = con.hget(key, "metadata").await.map_err(|e| {
+				error!("Failed to get \"metadata\" member from {} key: {}", key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			let tapestry_metadata = serde_json::from_slice::<M>(&metadata_raw).map_err(|e| {
+				error!("Failed to parse tapestry fragment metadata: {}", e);
+				StorageError::SerializationFailed(e.to_string())
+			})?;
+
+			Ok(Some(tapestry_metadata))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let tapestry_id = &validated_base_key(&tapestry_id)?;
+
+			let exists: bool = con.exists(tapestry_id).await.map_err(|e| {
+				error!("Failed to check if {} tapestry_id exists: {}", tapestry_id, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			if !exists {
+				debug!("{} tapestry_id does not exist", tapestry_id);
+				return Ok(());
+			}
+
+			let instance_count: u16 = con.hget(tapestry_id, INSTANCE_COUNT).await.map_err(|e| {
+				error!("Failed to get {} tapestry_id: {}", tapestry_id, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			for i in 1..=instance_count {
+				let instance_key = format!("{}:{}", tapestry_id, i);
+
+				debug!("Deleting {} instance", instance_key);
+
+				con.del::<_, ()>(&instance_key).await.map_err(|e| {
+					error!("Failed to delete {} tapestry_id: {}", tapestry_id, e);
 					LoomError::from(StorageError::Redis(e))
 				})?;
-				context_tokens_str.parse::<PromptModelTokens<T>>().map_err(|_| {
-					error!("Failed to parse \"context_tokens\" member from key: {}", key);
-					StorageError::Parsing
-				})?
-			},
-			context_messages: {
-				let context_messages_raw: Vec<u8> =
-					con.hget(&key, "context_messages").map_err(|e| {
-						error!("Failed to get \"context_messages\" member from {} key: {}", key, e);
+			}
+
+			con.del::<_, ()>(&[tapestry_id.clone(), weave_count_key(tapestry_id)])
+				.await
+				.map_err(|e| {
+					error!("Failed to delete {} tapestry_id: {}", tapestry_id, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+
+			debug!("Deleted {} tapestry_id and {} instances", tapestry_id, instance_count);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = connection(&client).await?;
+			let base_key = &validated_base_key(&tapestry_id)?;
+
+			let instance = match verify_and_get_instance(&mut con, base_key, instance).await? {
+				Some(instance) => instance,
+				None => return Ok(()),
+			};
+
+			let key = format!("{base_key}:{instance}");
+
+			debug!("Deleting {} instance", key);
+
+			con.del::<_, ()>(&key).map_err(|e| {
+				error!("Failed to delete {} tapestry_id: {}", key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			debug!("Deleted {} instance", key);
+
+			publish_event::<T>(&mut con, &event_channel(base_key), &TapestryEvent::FragmentDeleted);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let key = format!("lock:{}", validated_base_key(tapestry_id)?);
+			let token = new_lock_token();
+			let started_at = Instant::now();
+
+			loop {
+				let acquired: Option<String> = redis::cmd("SET")
+					.arg(&key)
+					.arg(&token)
+					.arg("NX")
+					.arg("PX")
+					.arg(LOCK_EXPIRY.as_millis() as u64)
+					.query_async(&mut con)
+					.await
+					.map_err(|e| {
+						error!("Failed to acquire {} lock: {}", key, e);
 						LoomError::from(StorageError::Redis(e))
 					})?;
 
-				serde_json::from_slice::<Vec<ContextMessage<T>>>(&context_messages_raw).map_err(
-					|e| {
-						error!("Failed to parse tapestry fragment context_messages: {}", e);
-						StorageError::Parsing
-					},
-				)?
-			},
-		};
+				if acquired.is_some() {
+					debug!("Acquired {} lock", key);
+					return Ok(token);
+				}
+
+				if started_at.elapsed() >= timeout {
+					error!("Timed out acquiring {} lock after {:?}", key, timeout);
+					return Err(LoomError::from(WeaveError::LockTimeout).into());
+				}
+
+				tokio::time::sleep(LOCK_RETRY_DELAY + jitter(LOCK_RETRY_DELAY)).await;
+			}
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let key = format!("lock:{}", validated_base_key(tapestry_id)?);
+
+			// Only delete the lock if it is still held by this token
+			Script::new(
+				r#"
+				if redis.call("GET", KEYS[1]) == ARGV[1] then
+					return redis.call("DEL", KEYS[1])
+				else
+					return 0
+				end
+				"#,
+			)
+			.key(&key)
+			.arg(&token)
+			.invoke_async::<_, ()>(&mut con)
+			.await
+			.map_err(|e| {
+				error!("Failed to release {} lock: {}", key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
 
-		Ok(Some(tapestry_fragment))
+			debug!("Released {} lock", key);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
 	}
 
-	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
-		tapestry_id: TID,
-	) -> crate::Result<Option<M>> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_multiplexed_async_connection().await?;
-		debug!("Connected to Redis");
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
 
-		let key = &tapestry_id.base_key();
+			let key = format!("reserved:{}", validated_base_key(tapestry_id)?);
 
-		let metadata_raw: Vec<u8> = con.hget(key, "metadata").await.map_err(|e| {
-			error!("Failed to get \"metadata\" member from {} key: {}", key, e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+			let reserved: u64 = con.incr(&key, tokens).await.map_err(|e| {
+				error!("Failed to reserve {} tokens on {}: {}", tokens, key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
 
-		let tapestry_metadata = serde_json::from_slice::<M>(&metadata_raw).map_err(|e| {
-			error!("Failed to parse tapestry fragment metadata: {}", e);
-			StorageError::Parsing
-		})?;
+			debug!("Reserved {} tokens on {}, {} reserved in total", tokens, key, reserved);
 
-		Ok(Some(tapestry_metadata))
+			Ok(reserved)
+		}
+		.instrument(span)
+		.await
 	}
 
-	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_multiplexed_async_connection().await?;
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let key = format!("reserved:{}", validated_base_key(tapestry_id)?);
+
+			// Delete the key once nothing is reserved anymore
+			Script::new(
+				r#"
+				local reserved = redis.call("DECRBY", KEYS[1], ARGV[1])
+				if reserved <= 0 then
+					redis.call("DEL", KEYS[1])
+				end
+				return reserved
+				"#,
+			)
+			.key(&key)
+			.arg(tokens)
+			.invoke_async::<_, ()>(&mut con)
+			.await
+			.map_err(|e| {
+				error!("Failed to release {} tokens on {}: {}", tokens, key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
 
-		let tapestry_id = &tapestry_id.base_key();
+			debug!("Released {} tokens on {}", tokens, key);
 
-		let exists: bool = con.exists(tapestry_id).await.map_err(|e| {
-			error!("Failed to check if {} tapestry_id exists: {}", tapestry_id, e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
 
-		if !exists {
-			debug!("{} tapestry_id does not exist", tapestry_id);
-			return Ok(());
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let key = format!("reserved:{}", validated_base_key(tapestry_id)?);
+
+			let reserved: Option<u64> = con.get(&key).await.map_err(|e| {
+				error!("Failed to get reserved tokens of {}: {}", key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			Ok(reserved.unwrap_or(0))
 		}
+		.instrument(span)
+		.await
+	}
 
-		let instance_count: u16 = con.hget(tapestry_id, INSTANCE_COUNT).await.map_err(|e| {
-			error!("Failed to get {} tapestry_id: {}", tapestry_id, e);
-			LoomError::from(StorageError::Redis(e))
-		})?;
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let key = weave_count_key(&validated_base_key(tapestry_id)?);
+
+			// The count is created after the fragment is saved, so it needs its own expiry
+			let mut pipe = redis::pipe();
+			pipe.incr(&key, 1);
+			if let Some(ttl) = T::FRAGMENT_TTL_SECONDS {
+				pipe.expire(&key, ttl as i64).ignore();
+			}
+
+			let (weave_count,): (u64,) = pipe.query_async(&mut con).await.map_err(|e| {
+				error!("Failed to increment weave count of {}: {}", key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			debug!("Incremented weave count of {} to {}", key, weave_count);
+
+			Ok(weave_count)
+		}
+		.instrument(span)
+		.await
+	}
 
-		for i in 1..=instance_count {
-			let instance_key = format!("{}:{}", tapestry_id, i);
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
 
-			debug!("Deleting {} instance", instance_key);
+			let key = weave_count_key(&validated_base_key(tapestry_id)?);
 
-			con.del(&instance_key).await.map_err(|e| {
-				error!("Failed to delete {} tapestry_id: {}", tapestry_id, e);
+			let weave_count: Option<u64> = con.get(&key).await.map_err(|e| {
+				error!("Failed to get weave count of {}: {}", key, e);
 				LoomError::from(StorageError::Redis(e))
 			})?;
+
+			Ok(weave_count.unwrap_or(0))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let instance_count =
+				match <Self as TapestryChestHandler<T>>::get_tapestry(tapestry_id.clone()).await? {
+					Some(instance_count) => instance_count,
+					None => return Ok(0),
+				};
+
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+			let base_key = &validated_base_key(&tapestry_id)?;
+
+			let mut repaired = 0;
+			for instance in 1..=instance_count as u64 {
+				let key = format!("{base_key}:{instance}");
+
+				// Deleted instances are skipped
+				let exists: bool = con.exists(&key).await.map_err(|e| {
+					error!("Failed to check if {} instance exists: {}", key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+				if !exists {
+					continue;
+				}
+
+				let mut tapestry_fragment =
+					match <Self as TapestryChestHandler<T>>::get_tapestry_fragment(
+						tapestry_id.clone(),
+						Some(instance),
+					)
+					.await?
+					{
+						Some(tapestry_fragment) => tapestry_fragment,
+						None => continue,
+					};
+
+				if !tapestry_fragment.recount_tokens()? {
+					continue;
+				}
+
+				con.hset::<_, _, _, ()>(&key, "context_tokens", tapestry_fragment.context_tokens)
+					.await
+					.map_err(|e| {
+						error!("Failed to save \"context_tokens\" member to {} key: {}", key, e);
+						LoomError::from(StorageError::Redis(e))
+					})?;
+
+				debug!("Repaired \"context_tokens\" member of {} key", key);
+
+				repaired += 1;
+			}
+
+			Ok(repaired)
 		}
+		.instrument(span)
+		.await
+	}
 
-		con.del(tapestry_id).await.map_err(|e| {
-			error!("Failed to delete {} tapestry_id: {}", tapestry_id, e);
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, ttl: Duration) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let base_key = &validated_base_key(&tapestry_id)?;
+
+			let instance_count: Option<u64> =
+				con.hget(base_key, INSTANCE_COUNT).await.map_err(|e| {
+					error!("Failed to get {} tapestry_id: {}", base_key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+
+			let mut pipe = redis::pipe();
+			for key in tapestry_keys(base_key, instance_count.unwrap_or(0)) {
+				pipe.cmd("PEXPIRE").arg(&key).arg(ttl.as_millis() as u64).ignore();
+			}
+
+			pipe.query_async::<_, ()>(&mut con).await.map_err(|e| {
+				error!("Failed to set {} tapestry ttl: {}", base_key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			debug!("Set {} tapestry to expire in {:?}", base_key, ttl);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let base_key = &validated_base_key(&tapestry_id)?;
+
+			let instance_count: Option<u64> =
+				con.hget(base_key, INSTANCE_COUNT).await.map_err(|e| {
+					error!("Failed to get {} tapestry_id: {}", base_key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+
+			let mut pipe = redis::pipe();
+			for instance in 1..=instance_count.unwrap_or(0) {
+				let key = format!("{base_key}:{instance}");
+				pipe.cmd("HSTRLEN").arg(&key).arg("context_tokens");
+				pipe.cmd("HSTRLEN").arg(&key).arg("context_messages");
+			}
+
+			let sizes: Vec<u64> = pipe.query_async(&mut con).await.map_err(|e| {
+				error!("Failed to get {} storage size: {}", base_key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			Ok(sizes.into_iter().sum())
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Reads all `instances` in a single pipeline.
+	async fn get_tapestry_fragment_batch<TID: TapestryId>(
+		tapestry_id: TID,
+		instances: Vec<u64>,
+	) -> crate::Result<Vec<Option<TapestryFragment<T>>>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let base_key = &validated_base_key(&tapestry_id)?;
+
+			let mut pipe = redis::pipe();
+			for instance in &instances {
+				pipe.hget(
+					format!("{base_key}:{instance}"),
+					&["context_tokens", "context_messages", "parent_instance"],
+				);
+			}
+
+			let fields: Vec<(Option<String>, Option<Vec<u8>>, Option<u64>)> =
+				pipe.query_async(&mut con).await.map_err(|e| {
+					error!("Failed to get {} tapestry fragments: {}", base_key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+
+			debug!("Got {} tapestry fragments of {}", instances.len(), base_key);
+
+			fields
+				.into_iter()
+				.map(|fields| {
+					let (Some(context_tokens), Some(context_messages), parent_instance) = fields
+					else {
+						return Ok(None);
+					};
+
+					Ok(Some(TapestryFragment {
+						context_tokens: context_tokens.parse::<PromptModelTokens<T>>().map_err(
+							|_| {
+								error!("Failed to parse \"context_tokens\" member of {}", base_key);
+								LoomError::from(StorageError::Parsing)
+							},
+						)?,
+						context_messages: T::STORAGE_FORMAT.deserialize(&context_messages)?,
+						parent_instance,
+					}))
+				})
+				.collect()
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Scans for `{parent}/*` keys holding an `instance_count`.
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		let span = tapestry_span!(parent);
+		async move {
+			let client = get_client().await?;
+			let mut con = async_connection(&client).await?;
+
+			let base_key = validated_base_key(parent)?;
+			let prefix = format!("{base_key}/");
+
+			let mut candidates = vec![];
+			let mut keys = con
+				.scan_match::<_, String>(format!("{}*", escape_glob(&prefix)))
+				.await
+				.map_err(|e| {
+					error!("Failed to scan {} children: {}", base_key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+			while let Some(key) = keys.next_item().await {
+				// Skip deeper descendants
+				if key.strip_prefix(&prefix).is_some_and(|name| !name.contains('/')) {
+					candidates.push(key);
+				}
+			}
+			drop(keys);
+
+			// Instance keys also match, only tapestry keys have an `instance_count`
+			let mut pipe = redis::pipe();
+			for key in &candidates {
+				pipe.hexists(key, INSTANCE_COUNT);
+			}
+			let is_tapestry: Vec<bool> = pipe.query_async(&mut con).await.map_err(|e| {
+				error!("Failed to get {} children: {}", base_key, e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+
+			let mut children = candidates
+				.into_iter()
+				.zip(is_tapestry)
+				.filter(|(_, is_tapestry)| *is_tapestry)
+				.map(|(key, _)| parent.child(&key[prefix.len()..]))
+				.collect::<Vec<_>>();
+			children.sort_by_key(|child| child.base_key());
+
+			Ok(children)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Scans for hash keys starting with [`Config::KEY_SCAN_PREFIX`] holding an `instance_count`.
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		let client = get_client().await?;
+		let mut con = async_connection(&client).await?;
+
+		// Locks, reservations and weave counts are not hashes
+		let mut candidates = vec![];
+		let mut keys = redis::cmd("SCAN")
+			.cursor_arg(0)
+			.arg("MATCH")
+			.arg(format!("{}*", escape_glob(T::KEY_SCAN_PREFIX)))
+			.arg("TYPE")
+			.arg("hash")
+			.clone()
+			.iter_async::<String>(&mut con)
+			.await
+			.map_err(|e| {
+				error!("Failed to scan tapestries: {}", e);
+				LoomError::from(StorageError::Redis(e))
+			})?;
+		while let Some(key) = keys.next_item().await {
+			candidates.push(key);
+		}
+		drop(keys);
+
+		// Instance keys also match, only tapestry keys have an `instance_count`
+		let mut pipe = redis::pipe();
+		for key in &candidates {
+			pipe.hexists(key, INSTANCE_COUNT);
+		}
+		let is_tapestry: Vec<bool> = pipe.query_async(&mut con).await.map_err(|e| {
+			error!("Failed to list tapestries: {}", e);
 			LoomError::from(StorageError::Redis(e))
 		})?;
 
-		debug!("Deleted {} tapestry_id and {} instances", tapestry_id, instance_count);
+		let mut base_keys = candidates
+			.into_iter()
+			.zip(is_tapestry)
+			.filter(|(_, is_tapestry)| *is_tapestry)
+			.map(|(key, _)| key)
+			.collect::<Vec<_>>();
+		base_keys.sort();
 
-		Ok(())
+		Ok(base_keys)
 	}
 
-	async fn delete_tapestry_fragment<TID: TapestryId>(
+	/// Subscribes to the `tapestry-events:{base_key}` Redis channel.
+	///
+	/// Must be called from within a tokio runtime.
+	fn watch<TID: TapestryId>(tapestry_id: TID) -> broadcast::Receiver<TapestryEvent<T>> {
+		let (sender, receiver) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+		let span = tapestry_span!(tapestry_id);
+		tokio::spawn(
+			async move {
+				if let Err(e) = forward_events(&tapestry_id, sender).await {
+					error!("Failed to watch {} tapestry_id: {}", tapestry_id.base_key(), e);
+				}
+			}
+			.instrument(span),
+		);
+
+		receiver
+	}
+}
+
+/// Advisory lock on a tapestry acquired through [`Config::Chest`].
+///
+/// The lock is released by [`TapestryLock::release`] or, failing that, in the background when the
+/// guard is dropped.
+pub struct TapestryLock<T: Config, TID: TapestryId> {
+	tapestry_id: TID,
+	token: Option<String>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config, TID: TapestryId> TapestryLock<T, TID> {
+	/// Acquire the lock on `tapestry_id`, waiting up to `timeout` for it to become available.
+	pub async fn acquire(tapestry_id: TID, timeout: Duration) -> crate::Result<Self> {
+		let token = T::Chest::lock(&tapestry_id, timeout).await?;
+
+		Ok(Self { tapestry_id, token: Some(token), _phantom: PhantomData })
+	}
+
+	/// Release the lock.
+	pub async fn release(mut self) -> crate::Result<()> {
+		match self.token.take() {
+			Some(token) =>
+				with_storage_timeout::<T, _>(T::Chest::unlock(&self.tapestry_id, token)).await,
+			None => Ok(()),
+		}
+	}
+
+	/// Release the lock, logging a failure instead of returning it.
+	///
+	/// Meant for callers whose work is already saved, the lock expires on its own anyway.
+	pub(crate) async fn release_or_log(self) {
+		let base_key = self.tapestry_id.base_key();
+		if let Err(e) = self.release().await {
+			error!(
+				"Failed to release {} lock, it will expire after {:?}: {}",
+				base_key, LOCK_EXPIRY, e
+			);
+		}
+	}
+}
+
+impl<T: Config, TID: TapestryId> Drop for TapestryLock<T, TID> {
+	fn drop(&mut self) {
+		let Some(token) = self.token.take() else {
+			return;
+		};
+
+		let tapestry_id = self.tapestry_id.clone();
+		match tokio::runtime::Handle::try_current() {
+			Ok(handle) => {
+				handle.spawn(async move {
+					if let Err(e) =
+						with_storage_timeout::<T, _>(T::Chest::unlock(&tapestry_id, token)).await
+					{
+						error!("Failed to release {} lock: {}", tapestry_id.base_key(), e);
+					}
+				});
+			},
+			Err(_) => error!(
+				"Failed to release {} lock: no tokio runtime, lock will expire after {:?}",
+				tapestry_id.base_key(),
+				LOCK_EXPIRY
+			),
+		}
+	}
+}
+
+/// Tokens reserved on a tapestry through [`Config::Chest`].
+///
+/// While the guard is alive, [`Loom::weave`](crate::Loom::weave) subtracts the reserved tokens
+/// from the tokens available for the response. The same applies to the other methods that save a
+/// response on the same [`TapestryId`] ([`Loom::weave_with_cot`](crate::Loom::weave_with_cot),
+/// [`Loom::multi_persona_weave`](crate::Loom::multi_persona_weave),
+/// [`Loom::continue_from`](crate::Loom::continue_from) and the wrappers around `weave`), which
+/// allows a multi-step agent to guarantee room for a final synthesis step by releasing the guard
+/// right before it. Methods that do not save a response, such as
+/// [`Loom::dry_run`](crate::Loom::dry_run) or
+/// [`Loom::analyze_conversation`](crate::Loom::analyze_conversation), ignore reservations.
+///
+/// The reservation is released by [`TokenBudgetGuard::release`] or, failing that, in the
+/// background when the guard is dropped.
+pub struct TokenBudgetGuard<T: Config, TID: TapestryId> {
+	tapestry_id: TID,
+	reserved_tokens: Option<u64>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config, TID: TapestryId> TokenBudgetGuard<T, TID> {
+	/// Reserve `tokens` on `tapestry_id`.
+	///
+	/// Fails with [`WeaveError::InsufficientTokens`] if the current tapestry fragment instance,
+	/// the existing reservations and `tokens` would exceed the maximum prompt token limit of
+	/// `prompt_model`.
+	pub async fn reserve(
+		prompt_model: T::PromptModel,
 		tapestry_id: TID,
-		instance: Option<u64>,
-	) -> crate::Result<()> {
-		let client = get_client().await.expect("Failed to get redis client");
-		let mut con = client.get_connection()?;
-		let base_key = &tapestry_id.base_key();
+		tokens: u64,
+	) -> crate::Result<Self> {
+		let context_tokens = with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+			tapestry_id.clone(),
+			None,
+		))
+		.await?
+		.map(|tapestry_fragment| tapestry_fragment.context_tokens)
+		.unwrap_or_default();
+		let available = prompt_model
+			.get_max_prompt_token_limit()
+			.saturating_sub(&context_tokens)
+			.to_u64()
+			.unwrap_or_default();
+
+		// Reserve first so that concurrent reservations cannot both succeed
+		let reserved =
+			with_storage_timeout::<T, _>(T::Chest::reserve_tokens(&tapestry_id, tokens)).await?;
+		let guard = Self { tapestry_id, reserved_tokens: Some(tokens), _phantom: PhantomData };
+
+		if reserved > available {
+			let already_reserved = reserved - tokens;
+			guard.release().await?;
+
+			error!("Cannot reserve {} tokens, {} are already reserved", tokens, already_reserved);
+			return Err(LoomError::from(WeaveError::InsufficientTokens {
+				requested: tokens,
+				available: available.saturating_sub(already_reserved),
+			})
+			.into());
+		}
 
-		let instance = match verify_and_get_instance(&mut con, base_key, instance).await? {
-			Some(instance) => instance,
-			None => return Ok(()),
+		Ok(guard)
+	}
+
+	/// Number of tokens reserved by this guard.
+	pub fn reserved_tokens(&self) -> u64 {
+		self.reserved_tokens.unwrap_or(0)
+	}
+
+	/// Release the reservation.
+	pub async fn release(mut self) -> crate::Result<()> {
+		match self.reserved_tokens.take() {
+			Some(tokens) =>
+				with_storage_timeout::<T, _>(T::Chest::release_tokens(&self.tapestry_id, tokens))
+					.await,
+			None => Ok(()),
+		}
+	}
+}
+
+impl<T: Config, TID: TapestryId> Drop for TokenBudgetGuard<T, TID> {
+	fn drop(&mut self) {
+		let Some(tokens) = self.reserved_tokens.take() else {
+			return;
+		};
+
+		let tapestry_id = self.tapestry_id.clone();
+		match tokio::runtime::Handle::try_current() {
+			Ok(handle) => {
+				handle.spawn(async move {
+					if let Err(e) =
+						with_storage_timeout::<T, _>(T::Chest::release_tokens(&tapestry_id, tokens))
+							.await
+					{
+						error!(
+							"Failed to release {} tokens on {}: {}",
+							tokens,
+							tapestry_id.base_key(),
+							e
+						);
+					}
+				});
+			},
+			Err(_) => error!(
+				"Failed to release {} tokens on {}: no tokio runtime",
+				tokens,
+				tapestry_id.base_key()
+			),
+		}
+	}
+}
+
+impl TapestryChest {
+	/// Connect to the Redis instance of `config` and verify the connection with a `PING`, see
+	/// [`TapestryChest::with_config`].
+	///
+	/// Call at startup to discover connection errors immediately rather than on the first
+	/// [`crate::Loom::weave`], e.g. with [`RedisConfig::from_env`]. Fails with
+	/// [`StorageError::ConnectionFailed`] if Redis is unreachable or a different configuration is
+	/// already set.
+	pub async fn connect(config: RedisConfig) -> crate::Result<Self> {
+		Self::with_config(config)?;
+
+		let connection_failed = |e: redis::RedisError| {
+			let m = format!("{}: {}", redis_url(true), e);
+			error!("Failed to connect to Redis at {}", m);
+			LoomError::from(StorageError::ConnectionFailed(m))
 		};
 
-		let key = format!("{base_key}:{instance}");
+		let client = get_client().await?;
+		let mut con = async_connection(&client).await.map_err(connection_failed)?;
+		redis::cmd("PING")
+			.query_async::<_, ()>(&mut con)
+			.await
+			.map_err(connection_failed)?;
+
+		debug!("Connected to Redis at {}", redis_url(true));
+
+		Ok(Self)
+	}
+
+	/// Use the Redis instance at `url` instead of the `REDIS_*` environment variables and verify
+	/// the connection with a `PING`, see [`TapestryChest::connect`].
+	///
+	/// Fails with [`StorageError::ConnectionFailed`] if `url` is invalid, Redis is unreachable or
+	/// a different Redis instance is already configured, see [`TapestryChest::with_config`].
+	pub async fn try_new(url: &str) -> crate::Result<Self> {
+		Self::connect(RedisConfig::new(url)).await
+	}
+
+	/// Use the Redis instance at `url` instead of the `REDIS_*` environment variables without
+	/// connecting to it, see [`TapestryChest::with_config`].
+	pub fn new_lazy(url: &str) -> crate::Result<Self> {
+		Self::with_config(RedisConfig::new(url))
+	}
+
+	/// Use `config` instead of [`RedisConfig::from_env`] without connecting to Redis.
+	///
+	/// The connection is established on the first storage operation, which fails with
+	/// [`StorageError::ConnectionFailed`] if the `url` is invalid.
+	///
+	/// Only one configuration is used for the lifetime of the process. Fails with
+	/// [`StorageError::ConnectionFailed`] if a different configuration was already set, either by
+	/// an earlier call or by a storage operation falling back to [`RedisConfig::from_env`].
+	pub fn with_config(config: RedisConfig) -> crate::Result<Self> {
+		let configured = REDIS_CONFIG.get_or_init(|| config.clone());
+		if *configured != config {
+			let m = format!("already configured with {:?}, cannot use {:?}", configured, config);
+			error!("Failed to configure Redis: {}", m);
+			return Err(LoomError::from(StorageError::ConnectionFailed(m)).into());
+		}
+
+		Ok(Self)
+	}
+
+	/// Rewrite the `context_messages` of every tapestry fragment instance of `tapestry_id` from
+	/// the `from` [`StorageFormat`] to the `to` [`StorageFormat`].
+	///
+	/// Used to migrate existing data after changing [`Config::STORAGE_FORMAT`].
+	///
+	/// Returns the number of tapestry fragment instances that were converted.
+	pub async fn convert_storage_format<TID: TapestryId>(
+		tapestry_id: TID,
+		from: StorageFormat,
+		to: StorageFormat,
+	) -> crate::Result<usize> {
+		let client = get_client().await?;
+		let mut con = async_connection(&client).await?;
+		let base_key = &validated_base_key(&tapestry_id)?;
+
+		let exists: bool = con.exists(base_key).await.map_err(|e| {
+			error!("Failed to check if {} tapestry_id exists: {}", base_key, e);
+			LoomError::from(StorageError::Redis(e))
+		})?;
 
-		debug!("Deleting {} instance", key);
+		if !exists || from == to {
+			return Ok(0);
+		}
 
-		con.del(&key).map_err(|e| {
-			error!("Failed to delete {} tapestry_id: {}", key, e);
+		let instance_count: u64 = con.hget(base_key, INSTANCE_COUNT).await.map_err(|e| {
+			error!("Failed to get {} tapestry_id: {}", base_key, e);
 			LoomError::from(StorageError::Redis(e))
 		})?;
 
-		debug!("Deleted {} instance", key);
+		let mut converted = 0;
+		for instance in 1..=instance_count {
+			let key = format!("{base_key}:{instance}");
+
+			let context_messages_raw: Option<Vec<u8>> =
+				con.hget(&key, "context_messages").await.map_err(|e| {
+					error!("Failed to get \"context_messages\" member from {} key: {}", key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+
+			// Deleted instances are skipped
+			let Some(context_messages_raw) = context_messages_raw else {
+				continue;
+			};
+
+			let context_messages =
+				to.serialize(&from.deserialize::<serde_json::Value>(&context_messages_raw)?)?;
+
+			con.hset::<_, _, _, ()>(&key, "context_messages", context_messages)
+				.await
+				.map_err(|e| {
+					error!("Failed to save \"context_messages\" member to {} key: {}", key, e);
+					LoomError::from(StorageError::Redis(e))
+				})?;
+
+			debug!("Converted {} key from {:?} to {:?}", key, from, to);
+
+			converted += 1;
+		}
+
+		Ok(converted)
+	}
+}
+
+/// Keys of the tapestry `base_key`, its instances up to `instance_count` and its weave count.
+fn tapestry_keys(base_key: &str, instance_count: u64) -> Vec<String> {
+	std::iter::once(base_key.to_string())
+		.chain((1..=instance_count).map(|instance| format!("{base_key}:{instance}")))
+		.chain(std::iter::once(weave_count_key(base_key)))
+		.collect()
+}
+
+/// Redis key holding the number of [`Loom::weave`](crate::Loom::weave) calls made on `base_key`.
+fn weave_count_key(base_key: &str) -> String {
+	format!("weave_count:{base_key}")
+}
+
+/// Redis channel the [`TapestryEvent`]s of `base_key` are published to.
+fn event_channel(base_key: &str) -> String {
+	format!("tapestry-events:{base_key}")
+}
+
+/// Number of subscribers to `channel`, or `0` if it could not be determined.
+fn event_subscribers(con: &mut Connection, channel: &str) -> usize {
+	let subscribers: RedisResult<Vec<(String, usize)>> =
+		redis::cmd("PUBSUB").arg("NUMSUB").arg(channel).query(con);
+
+	match subscribers {
+		Ok(subscribers) => subscribers.into_iter().map(|(_, count)| count).sum(),
+		Err(e) => {
+			error!("Failed to get {} subscribers: {}", channel, e);
+			0
+		},
+	}
+}
+
+/// Publish `event` to `channel`.
+///
+/// Failures are only logged since the change the event describes has already been saved.
+fn publish_event<T: Config>(con: &mut Connection, channel: &str, event: &TapestryEvent<T>) {
+	let payload = match serde_json::to_vec(event) {
+		Ok(payload) => payload,
+		Err(e) => {
+			error!("Failed to serialize {:?}: {}", event, e);
+			return;
+		},
+	};
+
+	if let Err(e) = con.publish::<_, _, ()>(channel, payload) {
+		error!("Failed to publish event to {}: {}", channel, e);
+	}
+}
+
+/// Forward the [`TapestryEvent`]s published for `tapestry_id` to `sender` until all receivers are
+/// dropped.
+async fn forward_events<T: Config, TID: TapestryId>(
+	tapestry_id: &TID,
+	sender: broadcast::Sender<TapestryEvent<T>>,
+) -> crate::Result<()> {
+	let client = get_client().await?;
+	let channel = event_channel(&validated_base_key(tapestry_id)?);
+
+	let mut pubsub = with_retry(|| client.get_async_pubsub()).await?;
+	pubsub.subscribe(&channel).await.map_err(|e| {
+		error!("Failed to subscribe to {}: {}", channel, e);
+		LoomError::from(StorageError::Redis(e))
+	})?;
+
+	debug!("Subscribed to {}", channel);
+
+	let mut messages = pubsub.on_message();
+	while let Some(msg) = messages.next().await {
+		let event = match serde_json::from_slice::<TapestryEvent<T>>(msg.get_payload_bytes()) {
+			Ok(event) => event,
+			Err(e) => {
+				error!("Failed to parse event from {}: {}", channel, e);
+				continue;
+			},
+		};
 
-		Ok(())
+		if sender.send(event).is_err() {
+			debug!("No receivers left for {}", channel);
+			break;
+		}
 	}
+
+	Ok(())
 }
 
 /// Storage client to access GCP Storage
 static REDIS_CLIENT: OnceCell<Client> = OnceCell::const_new();
 
-/// Get the Redis Client.
+/// Configured by [`TapestryChest::with_config`], or [`RedisConfig::from_env`] on first use.
+static REDIS_CONFIG: OnceLock<RedisConfig> = OnceLock::new();
+
+/// Get the Redis Client, initializing it on first use.
+///
+/// Fails with [`StorageError::ConnectionFailed`] if the configured URL is invalid. The client is
+/// initialized again on the next call after a failure.
 #[instrument]
-async fn get_client() -> Result<Client, redis::RedisError> {
-	Ok(REDIS_CLIENT
-		.get_or_init(async || {
+async fn get_client() -> crate::Result<Client> {
+	REDIS_CLIENT
+		.get_or_try_init(async || {
 			debug!("Initializing Redis client");
 
-			let protocol = std::env::var("REDIS_PROTOCOL").unwrap_or_else(|_| "redis".to_string());
-			let host = std::env::var("REDIS_HOST").unwrap_or_else(|_| "redis".to_string());
-			let port = std::env::var("REDIS_PORT").unwrap_or_else(|_| "6379".to_string());
-			let password = std::env::var("REDIS_PASSWORD").unwrap_or_default();
-
-			match redis::Client::open(format!("{}://:{}@{}:{}", protocol, password, host, port)) {
-				Ok(client) => client,
-				Err(e) => {
-					let m = format!("Failed to initialize Redis client: {}", e);
-					error!(m);
-					panic!("{}", m)
-				},
-			}
+			redis::Client::open(redis_url(false)).map_err(|e| {
+				let m = format!("{}: {}", redis_url(true), e);
+				error!("Failed to initialize Redis client for {}", m);
+				LoomError::from(StorageError::ConnectionFailed(m))
+			})
 		})
 		.await
-		.clone())
+		.cloned()
+		.map_err(Into::into)
+}
+
+/// The [`RedisConfig`] of [`TapestryChest`].
+fn redis_config() -> &'static RedisConfig {
+	REDIS_CONFIG.get_or_init(RedisConfig::from_env)
+}
+
+/// Redis URL of the [`RedisConfig`].
+///
+/// The password is replaced with `***` if `redact_password` is set, e.g. for logging.
+fn redis_url(redact_password: bool) -> String {
+	match redact_password {
+		true => redacted_url(&redis_config().url),
+		false => redis_config().url.clone(),
+	}
+}
+
+/// Run `f` until it succeeds, retrying connection refusals and I/O errors up to
+/// [`RedisConfig::max_retries`] times with exponential backoff.
+///
+/// Errors such as `WRONGTYPE` or authentication failures are returned immediately.
+async fn with_retry<R, F, Fut>(mut f: F) -> RedisResult<R>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = RedisResult<R>>,
+{
+	let config = redis_config();
+	let mut retry = 0;
+
+	loop {
+		match f().await {
+			Err(e) if retry < config.max_retries && is_retryable(&e) => {
+				let delay = Duration::from_millis(
+					config.retry_delay_ms.saturating_mul(2u64.saturating_pow(retry)),
+				);
+				retry += 1;
+				warn!(
+					"Redis failed, retrying in {:?} ({}/{}): {}",
+					delay, retry, config.max_retries, e
+				);
+				tokio::time::sleep(delay).await;
+			},
+			result => return result,
+		}
+	}
+}
+
+/// Whether `e` is a transient error worth retrying.
+fn is_retryable(e: &RedisError) -> bool {
+	e.is_connection_refusal() || e.is_io_error()
+}
+
+/// Open a connection to Redis, see [`with_retry`].
+async fn connection(client: &Client) -> RedisResult<Connection> {
+	with_retry(|| std::future::ready(client.get_connection())).await
+}
+
+/// Open a multiplexed asynchronous connection to Redis, see [`with_retry`].
+async fn async_connection(client: &Client) -> RedisResult<MultiplexedConnection> {
+	with_retry(|| client.get_multiplexed_async_connection()).await
+}
+
+/// `url` with the password of its user info, if any, replaced with `***`.
+fn redacted_url(url: &str) -> String {
+	let Some((scheme, rest)) = url.split_once("://") else {
+		return url.to_string();
+	};
+	let Some((user_info, host)) = rest.rsplit_once('@') else {
+		return url.to_string();
+	};
+
+	match user_info.split_once(':') {
+		Some((user, _)) => format!("{}://{}:***@{}", scheme, user, host),
+		None => url.to_string(),
+	}
+}
+
+/// Fail with [`StorageError::Unsupported`], the default of the [`TapestryChestHandler`] methods
+/// which storage backends are not required to implement.
+fn unsupported<R>(operation: &str) -> crate::Result<R> {
+	error!("{} is unsupported by this storage backend", operation);
+	Err(LoomError::from(StorageError::Unsupported(operation.to_string())).into())
+}
+
+/// Get the base key of `tapestry_id` after validating it.
+///
+/// Fails with [`StorageError::InvalidKey`] if the base key cannot be used as a Redis key.
+pub(crate) fn validated_base_key<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<String> {
+	tapestry_id.validate().map_err(|e| {
+		error!("Invalid tapestry_id {:?}: {}", tapestry_id, e);
+		LoomError::from(StorageError::InvalidKey(e.to_string()))
+	})?;
+
+	Ok(tapestry_id.base_key())
+}
+
+/// Append `msgs` to `tapestry_fragment`, keep only its last `max_messages` messages and recount
+/// its tokens.
+fn append_and_trim<T: Config>(
+	tapestry_fragment: &mut TapestryFragment<T>,
+	msgs: Vec<ContextMessage<T>>,
+	max_messages: usize,
+) -> crate::Result<()> {
+	tapestry_fragment.context_messages.extend(msgs);
+
+	let excess = tapestry_fragment.context_messages.len().saturating_sub(max_messages);
+	tapestry_fragment.context_messages.drain(..excess);
+
+	tapestry_fragment.recount_tokens()?;
+
+	Ok(())
+}
+
+/// Escape the Redis glob special characters of `key`.
+fn escape_glob(key: &str) -> String {
+	let mut escaped = String::with_capacity(key.len());
+	for c in key.chars() {
+		if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+			escaped.push('\\');
+		}
+		escaped.push(c);
+	}
+	escaped
+}
+
+/// Run the storage operation `future`, failing with [`StorageError::Timeout`] if it does not
+/// complete within [`Config::STORAGE_TIMEOUT_MS`].
+pub(crate) async fn with_storage_timeout<T: Config, R>(
+	future: impl Future<Output = crate::Result<R>>,
+) -> crate::Result<R> {
+	let timeout = Duration::from_millis(T::STORAGE_TIMEOUT_MS);
+
+	tokio::time::timeout(timeout, future).await.map_err(|_| {
+		error!("Storage operation timed out after {:?}", timeout);
+		LoomError::from(StorageError::Timeout(timeout))
+	})?
+}
+
+/// Generate a token which uniquely identifies a lock holder.
+pub(crate) fn new_lock_token() -> String {
+	static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+	let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+	format!("{}:{}:{}", std::process::id(), nanos, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Pseudo-random duration between zero and `max` to spread out lock retries.
+fn jitter(max: Duration) -> Duration {
+	let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+	max.mul_f64((nanos % 1000) as f64 / 1000.0)
 }
 
 /// Get the last instance number of a tapestry.
@@ -378,7 +1865,7 @@ async fn verify_and_get_instance(
 	Ok(match con.exists(base_key)? {
 		true => match instance {
 			Some(instance) =>
-				if con.exists(&format!("{}:{}", base_key, instance))? {
+				if con.exists(format!("{}:{}", base_key, instance))? {
 					Some(instance)
 				} else {
 					return Err(LoomError::from(StorageError::NotFound).into());
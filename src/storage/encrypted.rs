@@ -0,0 +1,224 @@
+//! Storage decorator encrypting tapestry fragments at rest.
+//!
+//! Only available with the `encryption` feature.
+use std::{fmt::Debug, marker::PhantomData, time::Duration};
+
+use aes_gcm::{
+	aead::{Aead, AeadCore, KeyInit, OsRng},
+	Aes256Gcm, Nonce,
+};
+use async_openai::types::Role;
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use redis::ToRedisArgs;
+use serde::de::DeserializeOwned;
+use tracing::error;
+
+use super::TapestryChestHandler;
+use crate::{
+	types::{LoomError, StorageError, StorageFormat, WrapperRole},
+	Config, ContextMessage, HierarchicalId, TapestryFragment, TapestryId,
+};
+
+/// Length in bytes of an AES-GCM nonce.
+const NONCE_LENGTH: usize = 12;
+
+/// [`TapestryChestHandler`] encrypting the messages of tapestry fragments before delegating to
+/// `S`.
+///
+/// The `context_messages` are serialized to JSON and encrypted with AES-256-GCM using
+/// [`Config::encryption_key`]. `S` stores a tapestry fragment with a single message holding the
+/// base64 encoded nonce followed by the ciphertext. The `context_tokens` are stored unencrypted.
+///
+/// ```ignore
+/// type Chest = EncryptedStorage<TapestryChest>;
+/// ```
+///
+/// Metadata is not encrypted. [`TapestryChestHandler::repair_token_counts`] is not supported
+/// since `S` cannot count the tokens of encrypted messages, and
+/// [`TapestryChestHandler::watch`] never receives any events.
+pub struct EncryptedStorage<S>(PhantomData<S>);
+
+#[async_trait]
+impl<T: Config, S: TapestryChestHandler<T> + Send + Sync> TapestryChestHandler<T>
+	for EncryptedStorage<S>
+{
+	type Error = S::Error;
+
+	async fn save_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: &TID,
+		tapestry_fragment: TapestryFragment<T>,
+		increment: bool,
+	) -> crate::Result<u64> {
+		S::save_tapestry_fragment(tapestry_id, encrypt(tapestry_fragment)?, increment).await
+	}
+
+	async fn save_tapestry_metadata<
+		TID: TapestryId,
+		M: ToRedisArgs + Debug + Clone + Send + Sync,
+	>(
+		tapestry_id: TID,
+		metadata: M,
+	) -> crate::Result<()> {
+		S::save_tapestry_metadata(tapestry_id, metadata).await
+	}
+
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		S::exists(tapestry_id).await
+	}
+
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		S::get_tapestry(tapestry_id).await
+	}
+
+	async fn get_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		match S::get_tapestry_fragment(tapestry_id, instance).await? {
+			Some(tapestry_fragment) => Ok(Some(decrypt(tapestry_fragment)?)),
+			None => Ok(None),
+		}
+	}
+
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		S::get_tapestry_metadata(tapestry_id).await
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		S::delete_tapestry(tapestry_id).await
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		S::delete_tapestry_fragment(tapestry_id, instance).await
+	}
+
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String> {
+		S::lock(tapestry_id, timeout).await
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		S::unlock(tapestry_id, token).await
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		S::reserve_tokens(tapestry_id, tokens).await
+	}
+
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		S::release_tokens(tapestry_id, tokens).await
+	}
+
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		S::get_reserved_tokens(tapestry_id).await
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		S::increment_weave_count(tapestry_id).await
+	}
+
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		S::get_weave_count(tapestry_id).await
+	}
+
+	/// Not supported, fails with [`StorageError::Unsupported`].
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		error!("Cannot repair token counts of {}: tapestry is encrypted", tapestry_id.base_key());
+		Err(LoomError::from(StorageError::Unsupported("repair_token_counts".to_string())).into())
+	}
+
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, ttl: Duration) -> crate::Result<()> {
+		S::set_ttl(tapestry_id, ttl).await
+	}
+
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		S::get_total_storage_bytes(tapestry_id).await
+	}
+
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		S::list_children(parent).await
+	}
+
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		S::list_all_tapestry_ids().await
+	}
+}
+
+/// Replace the `context_messages` of `tapestry_fragment` with a single encrypted message.
+fn encrypt<T: Config>(
+	tapestry_fragment: TapestryFragment<T>,
+) -> crate::Result<TapestryFragment<T>> {
+	let plaintext = StorageFormat::Json.serialize(&tapestry_fragment.context_messages)?;
+
+	let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+	let ciphertext = Aes256Gcm::new(&T::encryption_key().into())
+		.encrypt(&nonce, plaintext.as_slice())
+		.map_err(|e| {
+			error!("Failed to encrypt tapestry fragment: {}", e);
+			LoomError::from(StorageError::Encryption(e.to_string()))
+		})?;
+
+	let mut bytes = nonce.to_vec();
+	bytes.extend(ciphertext);
+
+	Ok(TapestryFragment {
+		context_tokens: tapestry_fragment.context_tokens,
+		context_messages: vec![ContextMessage::new(
+			WrapperRole::Role(Role::System),
+			BASE64_STANDARD.encode(bytes),
+			None,
+			String::new(),
+		)],
+		parent_instance: tapestry_fragment.parent_instance,
+	})
+}
+
+/// Restore the `context_messages` of a tapestry fragment encrypted by [`encrypt`].
+fn decrypt<T: Config>(
+	tapestry_fragment: TapestryFragment<T>,
+) -> crate::Result<TapestryFragment<T>> {
+	let encryption_failed = |m: &str| {
+		error!("Failed to decrypt tapestry fragment: {}", m);
+		LoomError::from(StorageError::Encryption(m.to_string()))
+	};
+
+	let [msg] = tapestry_fragment.context_messages.as_slice() else {
+		return Err(encryption_failed("tapestry fragment is not encrypted").into());
+	};
+	let bytes = BASE64_STANDARD
+		.decode(&msg.content)
+		.map_err(|e| encryption_failed(&e.to_string()))?;
+	if bytes.len() < NONCE_LENGTH {
+		return Err(encryption_failed("ciphertext is too short").into());
+	}
+
+	let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+	let plaintext = Aes256Gcm::new(&T::encryption_key().into())
+		.decrypt(Nonce::from_slice(nonce), ciphertext)
+		.map_err(|e| encryption_failed(&e.to_string()))?;
+
+	Ok(TapestryFragment {
+		context_tokens: tapestry_fragment.context_tokens,
+		context_messages: StorageFormat::Json.deserialize(&plaintext)?,
+		parent_instance: tapestry_fragment.parent_instance,
+	})
+}
+
+/// Parse a 32 byte key from 64 hexadecimal characters.
+pub(crate) fn parse_hex_key(hex: &str) -> Option<[u8; 32]> {
+	if hex.len() != 64 || !hex.is_ascii() {
+		return None;
+	}
+
+	let mut key = [0; 32];
+	for (i, byte) in key.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+	}
+
+	Some(key)
+}
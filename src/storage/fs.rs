@@ -0,0 +1,679 @@
+//! Filesystem storage backend.
+use std::{
+	io::ErrorKind,
+	path::{Component, Path, PathBuf},
+	sync::OnceLock,
+	time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use redis::ToRedisArgs;
+use serde::de::DeserializeOwned;
+use tokio::fs;
+use tracing::{debug, error, Instrument};
+
+use super::{
+	jitter, new_lock_token, validated_base_key, TapestryChestHandler, LOCK_EXPIRY, LOCK_RETRY_DELAY,
+};
+use crate::{
+	types::{LoomError, StorageError, StorageFormat, WeaveError},
+	Config, Debug, HierarchicalId, TapestryFragment, TapestryId,
+};
+
+/// Name of the file holding the tapestry metadata.
+const METADATA_FILE: &str = "metadata.json";
+/// Name of the file holding the tapestry lock.
+const LOCK_FILE: &str = "lock";
+/// Name of the file holding the number of tokens reserved on the tapestry.
+const RESERVED_FILE: &str = "reserved";
+/// Name of the file holding the number of weave calls made on the tapestry.
+const WEAVE_COUNT_FILE: &str = "weave_count";
+
+/// [`TapestryChestHandler`] storing tapestry fragments as JSON files on the local filesystem.
+///
+/// Intended for development and single-user deployments where running Redis is overkill.
+///
+/// Each tapestry fragment instance is stored as `{base_dir}/{base_key}/{instance}.json`. The
+/// `base_dir` is read from the `TAPESTRY_CHEST_DIR` environment variable and defaults to
+/// `tapestries`. Files are written to a temporary file first and then renamed so that a tapestry
+/// fragment is never partially written.
+///
+/// The number of instances of a tapestry is the highest instance found in its directory.
+///
+/// Token reservations and weave counts are not atomic across processes, and tapestries never
+/// expire, so [`Config::FRAGMENT_TTL_SECONDS`] is ignored.
+pub struct FilesystemTapestryChest;
+
+#[async_trait]
+impl<T: Config> TapestryChestHandler<T> for FilesystemTapestryChest {
+	type Error = StorageError;
+
+	async fn save_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: &TID,
+		tapestry_fragment: TapestryFragment<T>,
+		increment: bool,
+	) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(tapestry_id)?;
+			fs::create_dir_all(&dir).await.map_err(|e| io_error("create", &dir, e))?;
+
+			// Same instance semantics as the Redis `TapestryChest`
+			let mut tapestry_instance = last_instance(&dir).await?.unwrap_or(0).max(1);
+			if increment {
+				tapestry_instance += 1;
+
+				debug!("Incremented instance to {} for {}", tapestry_instance, dir.display());
+			}
+
+			let path = instance_path(&dir, tapestry_instance);
+			write_atomic(&path, &StorageFormat::Json.serialize(&tapestry_fragment)?).await?;
+
+			debug!("Saved tapestry fragment to {}", path.display());
+			tracing::Span::current().record("tapestry.instance", tapestry_instance);
+
+			Ok(tapestry_instance)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn save_tapestry_metadata<
+		TID: TapestryId,
+		M: ToRedisArgs + Debug + Clone + Send + Sync,
+	>(
+		tapestry_id: TID,
+		metadata: M,
+	) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+			fs::create_dir_all(&dir).await.map_err(|e| io_error("create", &dir, e))?;
+
+			let path = dir.join(METADATA_FILE);
+			write_atomic(&path, &metadata.to_redis_args().concat()).await?;
+
+			debug!("Saved metadata to {} with metadata {:?}", path.display(), metadata);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			is_tapestry_dir(&dir).await
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			// Locks, reservations and weave counts do not make a tapestry
+			let Some(instance_count) = last_instance(&dir).await? else {
+				return Ok(None);
+			};
+
+			Ok(Some(u16::try_from(instance_count).map_err(|_| {
+				LoomError::from(StorageError::QuotaExceeded {
+					limit: u16::MAX as usize,
+					actual: instance_count as usize,
+				})
+			})?))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			let instance = match instance {
+				Some(instance) => instance,
+				None => match last_instance(&dir).await? {
+					Some(instance) => instance,
+					None => return Ok(None),
+				},
+			};
+
+			let path = instance_path(&dir, instance);
+			let bytes = fs::read(&path).await.map_err(|e| match e.kind() {
+				ErrorKind::NotFound => LoomError::from(StorageError::NotFound),
+				_ => io_error("read", &path, e),
+			})?;
+
+			Ok(Some(StorageFormat::Json.deserialize(&bytes)?))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let path = tapestry_dir(&tapestry_id)?.join(METADATA_FILE);
+
+			let bytes = match fs::read(&path).await {
+				Ok(bytes) => bytes,
+				Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+				Err(e) => return Err(io_error("read", &path, e).into()),
+			};
+
+			Ok(Some(StorageFormat::Json.deserialize(&bytes)?))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			let mut entries = match fs::read_dir(&dir).await {
+				Ok(entries) => entries,
+				Err(e) if e.kind() == ErrorKind::NotFound => {
+					debug!("{} does not exist", dir.display());
+					return Ok(());
+				},
+				Err(e) => return Err(io_error("read", &dir, e).into()),
+			};
+
+			// Only the files of this tapestry are deleted, leaving any nested tapestries intact.
+			// Like the Redis `TapestryChest`, the lock and the reserved tokens are kept since they
+			// may be held while the tapestry is deleted.
+			while let Some(entry) =
+				entries.next_entry().await.map_err(|e| io_error("read", &dir, e))?
+			{
+				let path = entry.path();
+				if entry.file_name() == LOCK_FILE || entry.file_name() == RESERVED_FILE {
+					continue;
+				}
+				if !entry.file_type().await.map_err(|e| io_error("read", &path, e))?.is_dir() {
+					fs::remove_file(&path).await.map_err(|e| io_error("delete", &path, e))?;
+				}
+			}
+
+			if let Err(e) = fs::remove_dir(&dir).await {
+				debug!("Kept {} directory: {}", dir.display(), e);
+			}
+
+			debug!("Deleted {} tapestry", dir.display());
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			let instance = match instance {
+				Some(instance) => instance,
+				None => match last_instance(&dir).await? {
+					Some(instance) => instance,
+					None => return Ok(()),
+				},
+			};
+
+			let path = instance_path(&dir, instance);
+			fs::remove_file(&path).await.map_err(|e| match e.kind() {
+				ErrorKind::NotFound => LoomError::from(StorageError::NotFound),
+				_ => io_error("delete", &path, e),
+			})?;
+
+			debug!("Deleted {}", path.display());
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(tapestry_id)?;
+			fs::create_dir_all(&dir).await.map_err(|e| io_error("create", &dir, e))?;
+
+			let path = dir.join(LOCK_FILE);
+			let token = new_lock_token();
+			let started_at = Instant::now();
+
+			loop {
+				match fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+					Ok(_) => {
+						fs::write(&path, &token).await.map_err(|e| io_error("write", &path, e))?;
+						debug!("Acquired {} lock", path.display());
+						return Ok(token);
+					},
+					Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+						// Remove locks left behind by crashed processes
+						if is_expired(&path).await {
+							debug!("Removing expired {} lock", path.display());
+							let _ = fs::remove_file(&path).await;
+							continue;
+						}
+					},
+					Err(e) => return Err(io_error("create", &path, e).into()),
+				}
+
+				if started_at.elapsed() >= timeout {
+					error!("Timed out acquiring {} lock after {:?}", path.display(), timeout);
+					return Err(LoomError::from(WeaveError::LockTimeout).into());
+				}
+
+				tokio::time::sleep(LOCK_RETRY_DELAY + jitter(LOCK_RETRY_DELAY)).await;
+			}
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let path = tapestry_dir(tapestry_id)?.join(LOCK_FILE);
+
+			// Only delete the lock if it is still held by this token
+			match fs::read_to_string(&path).await {
+				Ok(holder) if holder == token => {
+					fs::remove_file(&path).await.map_err(|e| io_error("delete", &path, e))?;
+					debug!("Released {} lock", path.display());
+				},
+				Ok(_) => {},
+				Err(e) if e.kind() == ErrorKind::NotFound => {},
+				Err(e) => return Err(io_error("read", &path, e).into()),
+			}
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(tapestry_id)?;
+			fs::create_dir_all(&dir).await.map_err(|e| io_error("create", &dir, e))?;
+
+			let path = dir.join(RESERVED_FILE);
+			let reserved = read_count(&path).await?.saturating_add(tokens);
+			write_atomic(&path, reserved.to_string().as_bytes()).await?;
+
+			debug!(
+				"Reserved {} tokens on {}, {} reserved in total",
+				tokens,
+				dir.display(),
+				reserved
+			);
+
+			Ok(reserved)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let path = tapestry_dir(tapestry_id)?.join(RESERVED_FILE);
+
+			match read_count(&path).await?.saturating_sub(tokens) {
+				0 => match fs::remove_file(&path).await {
+					Ok(()) => {},
+					Err(e) if e.kind() == ErrorKind::NotFound => {},
+					Err(e) => return Err(io_error("delete", &path, e).into()),
+				},
+				reserved => write_atomic(&path, reserved.to_string().as_bytes()).await?,
+			}
+
+			debug!("Released {} tokens on {}", tokens, path.display());
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move { read_count(&tapestry_dir(tapestry_id)?.join(RESERVED_FILE)).await }
+			.instrument(span)
+			.await
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(tapestry_id)?;
+			fs::create_dir_all(&dir).await.map_err(|e| io_error("create", &dir, e))?;
+
+			let path = dir.join(WEAVE_COUNT_FILE);
+			let weave_count = read_count(&path).await?.saturating_add(1);
+			write_atomic(&path, weave_count.to_string().as_bytes()).await?;
+
+			debug!("Incremented weave count of {} to {}", dir.display(), weave_count);
+
+			Ok(weave_count)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move { read_count(&tapestry_dir(tapestry_id)?.join(WEAVE_COUNT_FILE)).await }
+			.instrument(span)
+			.await
+	}
+
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			let mut repaired = 0;
+			for instance in instances(&dir).await? {
+				let path = instance_path(&dir, instance);
+				let bytes = fs::read(&path).await.map_err(|e| io_error("read", &path, e))?;
+
+				let mut tapestry_fragment: TapestryFragment<T> =
+					StorageFormat::Json.deserialize(&bytes)?;
+				if !tapestry_fragment.recount_tokens()? {
+					continue;
+				}
+
+				write_atomic(&path, &StorageFormat::Json.serialize(&tapestry_fragment)?).await?;
+
+				debug!("Repaired context_tokens of {}", path.display());
+
+				repaired += 1;
+			}
+
+			Ok(repaired)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Not supported, fails with [`StorageError::Unsupported`].
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, _ttl: Duration) -> crate::Result<()> {
+		error!("Cannot set ttl of {}: unsupported by the filesystem", tapestry_id.base_key());
+		Err(LoomError::from(StorageError::Unsupported("set_ttl".to_string())).into())
+	}
+
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_dir(&tapestry_id)?;
+
+			let mut total = 0;
+			for instance in instances(&dir).await? {
+				let path = instance_path(&dir, instance);
+				match fs::metadata(&path).await {
+					Ok(metadata) => total += metadata.len(),
+					Err(e) if e.kind() == ErrorKind::NotFound => {},
+					Err(e) => return Err(io_error("read", &path, e).into()),
+				}
+			}
+
+			Ok(total)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		let span = tapestry_span!(parent);
+		async move {
+			let dir = tapestry_dir(parent)?;
+
+			let mut entries = match fs::read_dir(&dir).await {
+				Ok(entries) => entries,
+				Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+				Err(e) => return Err(io_error("read", &dir, e).into()),
+			};
+
+			// Every tapestry is a directory, see `exists`
+			let mut children = vec![];
+			while let Some(entry) =
+				entries.next_entry().await.map_err(|e| io_error("read", &dir, e))?
+			{
+				let path = entry.path();
+				if !entry.file_type().await.map_err(|e| io_error("read", &path, e))?.is_dir() {
+					continue;
+				}
+				if let Some(name) = entry.file_name().to_str() {
+					children.push(parent.child(name));
+				}
+			}
+			children.sort_by_key(|child| child.base_key());
+
+			Ok(children)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Walks the `base_dir`, every directory holding tapestry fragment instances or metadata is a
+	/// tapestry, see `exists`.
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		let mut base_keys = vec![];
+		let mut dirs = vec![base_dir().to_path_buf()];
+
+		while let Some(dir) = dirs.pop() {
+			let mut entries = match fs::read_dir(&dir).await {
+				Ok(entries) => entries,
+				Err(e) if e.kind() == ErrorKind::NotFound => continue,
+				Err(e) => return Err(io_error("read", &dir, e).into()),
+			};
+
+			while let Some(entry) =
+				entries.next_entry().await.map_err(|e| io_error("read", &dir, e))?
+			{
+				let path = entry.path();
+				if !entry.file_type().await.map_err(|e| io_error("read", &path, e))?.is_dir() {
+					continue;
+				}
+
+				let base_key = path
+					.strip_prefix(base_dir())
+					.ok()
+					.and_then(|relative| relative.to_str())
+					.map(|relative| relative.replace(std::path::MAIN_SEPARATOR, "/"));
+				if let Some(base_key) = base_key {
+					if base_key.starts_with(T::KEY_SCAN_PREFIX) && is_tapestry_dir(&path).await? {
+						base_keys.push(base_key);
+					}
+				}
+				dirs.push(path);
+			}
+		}
+		base_keys.sort();
+
+		Ok(base_keys)
+	}
+}
+
+impl FilesystemTapestryChest {
+	/// Remove empty or corrupt tapestry fragment files, as well as temporary files left behind by
+	/// interrupted writes, from the whole `base_dir`.
+	///
+	/// Returns the number of files removed.
+	pub async fn cleanup_directory<T: Config>() -> crate::Result<usize> {
+		let mut removed = 0;
+		let mut dirs = vec![base_dir().to_path_buf()];
+
+		while let Some(dir) = dirs.pop() {
+			let mut entries = match fs::read_dir(&dir).await {
+				Ok(entries) => entries,
+				Err(e) if e.kind() == ErrorKind::NotFound => continue,
+				Err(e) => return Err(io_error("read", &dir, e).into()),
+			};
+
+			while let Some(entry) =
+				entries.next_entry().await.map_err(|e| io_error("read", &dir, e))?
+			{
+				let path = entry.path();
+				let file_type = entry.file_type().await.map_err(|e| io_error("read", &path, e))?;
+
+				if file_type.is_dir() {
+					dirs.push(path);
+					continue;
+				}
+
+				let is_corrupt = if path.extension().is_some_and(|ext| ext == "tmp") {
+					true
+				} else if parse_instance(&path).is_some() {
+					let bytes = fs::read(&path).await.map_err(|e| io_error("read", &path, e))?;
+					bytes.is_empty() ||
+						StorageFormat::Json.deserialize::<TapestryFragment<T>>(&bytes).is_err()
+				} else {
+					false
+				};
+
+				if is_corrupt {
+					fs::remove_file(&path).await.map_err(|e| io_error("delete", &path, e))?;
+					debug!("Removed corrupt {}", path.display());
+					removed += 1;
+				}
+			}
+		}
+
+		Ok(removed)
+	}
+}
+
+/// Directory all tapestries are stored in.
+static BASE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Get the directory all tapestries are stored in.
+fn base_dir() -> &'static Path {
+	BASE_DIR.get_or_init(|| {
+		PathBuf::from(
+			std::env::var("TAPESTRY_CHEST_DIR").unwrap_or_else(|_| "tapestries".to_string()),
+		)
+	})
+}
+
+/// Get the directory of a tapestry.
+///
+/// Fails with [`StorageError::InvalidKey`] if the base key would escape the `base_dir`.
+fn tapestry_dir<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<PathBuf> {
+	let base_key = validated_base_key(tapestry_id)?;
+
+	if !Path::new(&base_key).components().all(|c| matches!(c, Component::Normal(_))) {
+		error!("Invalid tapestry_id {:?}: not a relative path", tapestry_id);
+		return Err(LoomError::from(StorageError::InvalidKey(format!(
+			"{} is not a relative path",
+			base_key
+		)))
+		.into());
+	}
+
+	Ok(base_dir().join(base_key))
+}
+
+fn instance_path(dir: &Path, instance: u64) -> PathBuf {
+	dir.join(format!("{instance}.json"))
+}
+
+/// Instance of a tapestry fragment file path.
+fn parse_instance(path: &Path) -> Option<u64> {
+	path.file_name()?.to_str()?.strip_suffix(".json")?.parse().ok()
+}
+
+/// All tapestry fragment instances found in `dir` in ascending order.
+async fn instances(dir: &Path) -> crate::Result<Vec<u64>> {
+	let mut entries = match fs::read_dir(dir).await {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => return Err(io_error("read", dir, e).into()),
+	};
+
+	let mut instances = vec![];
+	while let Some(entry) = entries.next_entry().await.map_err(|e| io_error("read", dir, e))? {
+		instances.extend(parse_instance(&entry.path()));
+	}
+	instances.sort_unstable();
+
+	Ok(instances)
+}
+
+/// Whether `dir` holds tapestry fragment instances or metadata, rather than only the lock, token
+/// reservation or weave count of a tapestry.
+async fn is_tapestry_dir(dir: &Path) -> crate::Result<bool> {
+	let path = dir.join(METADATA_FILE);
+	if fs::try_exists(&path).await.map_err(|e| io_error("check", &path, e))? {
+		return Ok(true);
+	}
+
+	Ok(last_instance(dir).await?.is_some())
+}
+
+/// Highest tapestry fragment instance found in `dir`.
+async fn last_instance(dir: &Path) -> crate::Result<Option<u64>> {
+	Ok(instances(dir).await?.last().copied())
+}
+
+/// Write `bytes` to a temporary file and rename it to `path`.
+async fn write_atomic(path: &Path, bytes: &[u8]) -> crate::Result<()> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+
+	fs::write(&tmp_path, bytes)
+		.await
+		.map_err(|e| io_error("write", Path::new(&tmp_path), e))?;
+	fs::rename(&tmp_path, path).await.map_err(|e| io_error("write", path, e))?;
+
+	Ok(())
+}
+
+/// Count in the file at `path`, such as the number of tokens reserved, `0` if it does not exist.
+async fn read_count(path: &Path) -> crate::Result<u64> {
+	match fs::read_to_string(path).await {
+		Ok(count) => Ok(count.trim().parse().map_err(|_| {
+			error!("Invalid count in {}: {}", path.display(), count);
+			LoomError::from(StorageError::Parsing)
+		})?),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+		Err(e) => Err(io_error("read", path, e).into()),
+	}
+}
+
+/// Whether the lock file at `path` is older than [`LOCK_EXPIRY`].
+async fn is_expired(path: &Path) -> bool {
+	match fs::metadata(path).await.and_then(|metadata| metadata.modified()) {
+		Ok(modified) =>
+			SystemTime::now().duration_since(modified).is_ok_and(|age| age > LOCK_EXPIRY),
+		Err(_) => false,
+	}
+}
+
+fn io_error(action: &str, path: &Path, e: std::io::Error) -> LoomError {
+	error!("Failed to {} {}: {}", action, path.display(), e);
+	LoomError::from(StorageError::Io(e))
+}
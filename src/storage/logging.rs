@@ -0,0 +1,236 @@
+//! Storage decorator tracing every operation.
+use std::{
+	fmt::Debug,
+	future::Future,
+	marker::PhantomData,
+	time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::Stream;
+use redis::ToRedisArgs;
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast;
+use tracing::{debug, Instrument};
+
+use super::TapestryChestHandler;
+use crate::{
+	types::TapestryEvent, Config, ContextMessage, HierarchicalId, TapestryFragment, TapestryId,
+};
+
+/// [`TapestryChestHandler`] delegating to `S` while tracing every operation.
+///
+/// Each operation runs in a `DEBUG` level span recording the operation name, the
+/// `tapestry.base_key` and, once the operation completes, its `elapsed_ms`. Useful for debugging
+/// storage performance without changing the underlying storage backend:
+///
+/// ```ignore
+/// type Chest = LoggingTapestryChest<TapestryChest>;
+/// ```
+pub struct LoggingTapestryChest<S>(PhantomData<S>);
+
+#[async_trait]
+impl<T: Config, S: TapestryChestHandler<T> + Send + Sync> TapestryChestHandler<T>
+	for LoggingTapestryChest<S>
+{
+	type Error = S::Error;
+
+	async fn save_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: &TID,
+		tapestry_fragment: TapestryFragment<T>,
+		increment: bool,
+	) -> crate::Result<u64> {
+		traced(
+			"save_tapestry_fragment",
+			tapestry_id,
+			S::save_tapestry_fragment(tapestry_id, tapestry_fragment, increment),
+		)
+		.await
+	}
+
+	async fn save_tapestry_metadata<
+		TID: TapestryId,
+		M: ToRedisArgs + Debug + Clone + Send + Sync,
+	>(
+		tapestry_id: TID,
+		metadata: M,
+	) -> crate::Result<()> {
+		traced(
+			"save_tapestry_metadata",
+			&tapestry_id.clone(),
+			S::save_tapestry_metadata(tapestry_id, metadata),
+		)
+		.await
+	}
+
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		traced("exists", &tapestry_id.clone(), S::exists(tapestry_id)).await
+	}
+
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		traced("get_tapestry", &tapestry_id.clone(), S::get_tapestry(tapestry_id)).await
+	}
+
+	async fn get_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		traced(
+			"get_tapestry_fragment",
+			&tapestry_id.clone(),
+			S::get_tapestry_fragment(tapestry_id, instance),
+		)
+		.await
+	}
+
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		traced("get_tapestry_metadata", &tapestry_id.clone(), S::get_tapestry_metadata(tapestry_id))
+			.await
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		traced("delete_tapestry", &tapestry_id.clone(), S::delete_tapestry(tapestry_id)).await
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		traced(
+			"delete_tapestry_fragment",
+			&tapestry_id.clone(),
+			S::delete_tapestry_fragment(tapestry_id, instance),
+		)
+		.await
+	}
+
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String> {
+		traced("lock", tapestry_id, S::lock(tapestry_id, timeout)).await
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		traced("unlock", tapestry_id, S::unlock(tapestry_id, token)).await
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		traced("reserve_tokens", tapestry_id, S::reserve_tokens(tapestry_id, tokens)).await
+	}
+
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		traced("release_tokens", tapestry_id, S::release_tokens(tapestry_id, tokens)).await
+	}
+
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		traced("get_reserved_tokens", tapestry_id, S::get_reserved_tokens(tapestry_id)).await
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		traced("increment_weave_count", tapestry_id, S::increment_weave_count(tapestry_id)).await
+	}
+
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		traced("get_weave_count", tapestry_id, S::get_weave_count(tapestry_id)).await
+	}
+
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		traced("repair_token_counts", &tapestry_id.clone(), S::repair_token_counts(tapestry_id))
+			.await
+	}
+
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, ttl: Duration) -> crate::Result<()> {
+		traced("set_ttl", &tapestry_id.clone(), S::set_ttl(tapestry_id, ttl)).await
+	}
+
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		traced(
+			"get_total_storage_bytes",
+			&tapestry_id.clone(),
+			S::get_total_storage_bytes(tapestry_id),
+		)
+		.await
+	}
+
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		traced("list_children", parent, S::list_children(parent)).await
+	}
+
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		let prefix = HierarchicalId::new([T::KEY_SCAN_PREFIX]);
+		traced("list_all_tapestry_ids", &prefix, S::list_all_tapestry_ids()).await
+	}
+
+	fn iter_fragments<TID: TapestryId>(
+		tapestry_id: TID,
+	) -> impl Stream<Item = crate::Result<(u64, TapestryFragment<T>)>> + Send {
+		debug!("Iterating over {} tapestry fragments", tapestry_id.base_key());
+		S::iter_fragments(tapestry_id)
+	}
+
+	async fn search_messages<TID: TapestryId>(
+		tapestry_id: TID,
+		query: &str,
+		case_sensitive: bool,
+	) -> crate::Result<Vec<(u64, usize, ContextMessage<T>)>> {
+		traced(
+			"search_messages",
+			&tapestry_id.clone(),
+			S::search_messages(tapestry_id, query, case_sensitive),
+		)
+		.await
+	}
+
+	async fn get_tapestry_fragment_batch<TID: TapestryId>(
+		tapestry_id: TID,
+		instances: Vec<u64>,
+	) -> crate::Result<Vec<Option<TapestryFragment<T>>>> {
+		traced(
+			"get_tapestry_fragment_batch",
+			&tapestry_id.clone(),
+			S::get_tapestry_fragment_batch(tapestry_id, instances),
+		)
+		.await
+	}
+
+	async fn atomic_append_and_trim<TID: TapestryId>(
+		tapestry_id: TID,
+		msgs: Vec<ContextMessage<T>>,
+		max_messages: usize,
+	) -> crate::Result<TapestryFragment<T>> {
+		traced(
+			"atomic_append_and_trim",
+			&tapestry_id.clone(),
+			S::atomic_append_and_trim(tapestry_id, msgs, max_messages),
+		)
+		.await
+	}
+
+	fn watch<TID: TapestryId>(tapestry_id: TID) -> broadcast::Receiver<TapestryEvent<T>> {
+		debug!("Watching {} tapestry", tapestry_id.base_key());
+		S::watch(tapestry_id)
+	}
+}
+
+/// Run the `operation` future in a span recording its elapsed time.
+async fn traced<TID: TapestryId, F: Future>(
+	operation: &'static str,
+	tapestry_id: &TID,
+	future: F,
+) -> F::Output {
+	let span = tracing::debug_span!(
+		"tapestry_chest",
+		operation,
+		tapestry.base_key = %tapestry_id.base_key(),
+		elapsed_ms = tracing::field::Empty,
+	);
+
+	let started_at = Instant::now();
+	let output = future.instrument(span.clone()).await;
+	let elapsed = started_at.elapsed();
+
+	span.record("elapsed_ms", elapsed.as_millis() as u64);
+	debug!(parent: &span, "{} took {:?}", operation, elapsed);
+
+	output
+}
@@ -0,0 +1,286 @@
+//! Storage decorator recording metrics of every operation.
+//!
+//! Only available with the `metrics` feature.
+use std::{
+	fmt::Debug,
+	future::Future,
+	marker::PhantomData,
+	time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::Stream;
+use redis::ToRedisArgs;
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast;
+
+use super::TapestryChestHandler;
+use crate::{
+	types::TapestryEvent, Config, ContextMessage, HierarchicalId, TapestryFragment, TapestryId,
+};
+
+/// [`TapestryChestHandler`] delegating to `S` while recording metrics through the
+/// [`metrics`] facade.
+///
+/// Every operation increments a counter, such as `tapestry.saves` or `tapestry.gets`, and
+/// records its duration in a histogram, such as `tapestry.save_duration_ms`. Failed operations
+/// also increment `tapestry.errors`, labeled with the `operation` counter name. The metrics are
+/// exported by whichever recorder the application installs, e.g. `metrics-exporter-prometheus`:
+///
+/// ```ignore
+/// type Chest = MetricsStorage<TapestryChest>;
+/// ```
+pub struct MetricsStorage<S>(PhantomData<S>);
+
+#[async_trait]
+impl<T: Config, S: TapestryChestHandler<T> + Send + Sync> TapestryChestHandler<T>
+	for MetricsStorage<S>
+{
+	type Error = S::Error;
+
+	async fn save_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: &TID,
+		tapestry_fragment: TapestryFragment<T>,
+		increment: bool,
+	) -> crate::Result<u64> {
+		measured(
+			"tapestry.saves",
+			"tapestry.save_duration_ms",
+			S::save_tapestry_fragment(tapestry_id, tapestry_fragment, increment),
+		)
+		.await
+	}
+
+	async fn save_tapestry_metadata<
+		TID: TapestryId,
+		M: ToRedisArgs + Debug + Clone + Send + Sync,
+	>(
+		tapestry_id: TID,
+		metadata: M,
+	) -> crate::Result<()> {
+		measured(
+			"tapestry.metadata_saves",
+			"tapestry.metadata_save_duration_ms",
+			S::save_tapestry_metadata(tapestry_id, metadata),
+		)
+		.await
+	}
+
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		measured("tapestry.exists", "tapestry.exists_duration_ms", S::exists(tapestry_id)).await
+	}
+
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		measured(
+			"tapestry.instance_count_gets",
+			"tapestry.instance_count_get_duration_ms",
+			S::get_tapestry(tapestry_id),
+		)
+		.await
+	}
+
+	async fn get_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		measured(
+			"tapestry.gets",
+			"tapestry.get_duration_ms",
+			S::get_tapestry_fragment(tapestry_id, instance),
+		)
+		.await
+	}
+
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		measured(
+			"tapestry.metadata_gets",
+			"tapestry.metadata_get_duration_ms",
+			S::get_tapestry_metadata(tapestry_id),
+		)
+		.await
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		measured("tapestry.deletes", "tapestry.delete_duration_ms", S::delete_tapestry(tapestry_id))
+			.await
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		measured(
+			"tapestry.fragment_deletes",
+			"tapestry.fragment_delete_duration_ms",
+			S::delete_tapestry_fragment(tapestry_id, instance),
+		)
+		.await
+	}
+
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String> {
+		measured("tapestry.locks", "tapestry.lock_duration_ms", S::lock(tapestry_id, timeout)).await
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		measured("tapestry.unlocks", "tapestry.unlock_duration_ms", S::unlock(tapestry_id, token))
+			.await
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		measured(
+			"tapestry.token_reservations",
+			"tapestry.token_reservation_duration_ms",
+			S::reserve_tokens(tapestry_id, tokens),
+		)
+		.await
+	}
+
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		measured(
+			"tapestry.token_releases",
+			"tapestry.token_release_duration_ms",
+			S::release_tokens(tapestry_id, tokens),
+		)
+		.await
+	}
+
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		measured(
+			"tapestry.reserved_token_gets",
+			"tapestry.reserved_token_get_duration_ms",
+			S::get_reserved_tokens(tapestry_id),
+		)
+		.await
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		measured(
+			"tapestry.weave_count_increments",
+			"tapestry.weave_count_increment_duration_ms",
+			S::increment_weave_count(tapestry_id),
+		)
+		.await
+	}
+
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		measured(
+			"tapestry.weave_count_gets",
+			"tapestry.weave_count_get_duration_ms",
+			S::get_weave_count(tapestry_id),
+		)
+		.await
+	}
+
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		measured(
+			"tapestry.token_count_repairs",
+			"tapestry.token_count_repair_duration_ms",
+			S::repair_token_counts(tapestry_id),
+		)
+		.await
+	}
+
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, ttl: Duration) -> crate::Result<()> {
+		measured("tapestry.ttl_sets", "tapestry.ttl_set_duration_ms", S::set_ttl(tapestry_id, ttl))
+			.await
+	}
+
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		measured(
+			"tapestry.storage_bytes_gets",
+			"tapestry.storage_bytes_get_duration_ms",
+			S::get_total_storage_bytes(tapestry_id),
+		)
+		.await
+	}
+
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		measured(
+			"tapestry.children_lists",
+			"tapestry.children_list_duration_ms",
+			S::list_children(parent),
+		)
+		.await
+	}
+
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		measured(
+			"tapestry.tapestry_lists",
+			"tapestry.tapestry_list_duration_ms",
+			S::list_all_tapestry_ids(),
+		)
+		.await
+	}
+
+	fn iter_fragments<TID: TapestryId>(
+		tapestry_id: TID,
+	) -> impl Stream<Item = crate::Result<(u64, TapestryFragment<T>)>> + Send {
+		::metrics::counter!("tapestry.iterations").increment(1);
+		S::iter_fragments(tapestry_id)
+	}
+
+	async fn search_messages<TID: TapestryId>(
+		tapestry_id: TID,
+		query: &str,
+		case_sensitive: bool,
+	) -> crate::Result<Vec<(u64, usize, ContextMessage<T>)>> {
+		measured(
+			"tapestry.searches",
+			"tapestry.search_duration_ms",
+			S::search_messages(tapestry_id, query, case_sensitive),
+		)
+		.await
+	}
+
+	async fn get_tapestry_fragment_batch<TID: TapestryId>(
+		tapestry_id: TID,
+		instances: Vec<u64>,
+	) -> crate::Result<Vec<Option<TapestryFragment<T>>>> {
+		measured(
+			"tapestry.batch_gets",
+			"tapestry.batch_get_duration_ms",
+			S::get_tapestry_fragment_batch(tapestry_id, instances),
+		)
+		.await
+	}
+
+	async fn atomic_append_and_trim<TID: TapestryId>(
+		tapestry_id: TID,
+		msgs: Vec<ContextMessage<T>>,
+		max_messages: usize,
+	) -> crate::Result<TapestryFragment<T>> {
+		measured(
+			"tapestry.appends",
+			"tapestry.append_duration_ms",
+			S::atomic_append_and_trim(tapestry_id, msgs, max_messages),
+		)
+		.await
+	}
+
+	fn watch<TID: TapestryId>(tapestry_id: TID) -> broadcast::Receiver<TapestryEvent<T>> {
+		::metrics::counter!("tapestry.watches").increment(1);
+		S::watch(tapestry_id)
+	}
+}
+
+/// Run the `operation` future, incrementing the `counter` and recording its duration in the
+/// `histogram`.
+async fn measured<O, F: Future<Output = crate::Result<O>>>(
+	counter: &'static str,
+	histogram: &'static str,
+	operation: F,
+) -> crate::Result<O> {
+	::metrics::counter!(counter).increment(1);
+
+	let started_at = Instant::now();
+	let output = operation.await;
+	::metrics::histogram!(histogram).record(started_at.elapsed().as_secs_f64() * 1000.0);
+
+	if output.is_err() {
+		::metrics::counter!("tapestry.errors", "operation" => counter).increment(1);
+	}
+
+	output
+}
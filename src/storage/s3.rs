@@ -0,0 +1,707 @@
+//! S3 compatible object storage backend.
+//!
+//! Only available with the `s3` feature.
+use std::{
+	collections::BTreeSet,
+	io::{Read, Write},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use aws_sdk_s3::{error::DisplayErrorContext, primitives::ByteStream, Client};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use redis::ToRedisArgs;
+use serde::de::DeserializeOwned;
+use tokio::sync::OnceCell;
+use tracing::{debug, error, Instrument};
+
+use super::{
+	jitter, new_lock_token, validated_base_key, TapestryChestHandler, LOCK_EXPIRY, LOCK_RETRY_DELAY,
+};
+use crate::{
+	types::{LoomError, StorageError, StorageFormat, WeaveError},
+	Config, Debug, HierarchicalId, TapestryFragment, TapestryId,
+};
+
+/// Name of the object holding the tapestry metadata.
+const METADATA_OBJECT: &str = "metadata.json";
+/// Name of the object holding the tapestry lock.
+const LOCK_OBJECT: &str = "lock";
+/// Name of the object holding the number of tokens reserved on the tapestry.
+const RESERVED_OBJECT: &str = "reserved";
+/// Name of the object holding the number of weave calls made on the tapestry.
+const WEAVE_COUNT_OBJECT: &str = "weave_count";
+/// Extension of the tapestry fragment objects.
+const INSTANCE_EXTENSION: &str = ".json.gz";
+
+/// [`TapestryChestHandler`] storing tapestry fragments as gzip compressed JSON objects in an S3
+/// compatible bucket.
+///
+/// Intended for serverless and archival focused deployments where durable and cheap storage
+/// matters more than latency.
+///
+/// Each tapestry fragment instance is stored as `{prefix}/{base_key}/{instance}.json.gz`. The
+/// client, bucket and prefix are set with [`S3TapestryChest::configure`]. Otherwise the client is
+/// loaded from the AWS environment, the bucket is read from the `TAPESTRY_S3_BUCKET` environment
+/// variable and the prefix from `TAPESTRY_S3_PREFIX`, which defaults to no prefix.
+///
+/// The number of instances of a tapestry is the highest instance found under its key prefix.
+///
+/// Locks are created with conditional writes, which the bucket must support. Token reservations
+/// and weave counts are not atomic across processes, and tapestries never expire, so
+/// [`Config::FRAGMENT_TTL_SECONDS`] is ignored. Use bucket lifecycle rules instead.
+pub struct S3TapestryChest;
+
+/// Client, bucket and key prefix used by the [`S3TapestryChest`].
+pub struct S3Settings {
+	pub client: Client,
+	pub bucket: String,
+	pub prefix: String,
+}
+
+impl S3TapestryChest {
+	/// Set the client, bucket and key prefix to use instead of reading them from the environment.
+	///
+	/// Must be called before the first storage operation. Returns `false` if the settings were
+	/// already initialized.
+	pub fn configure(client: Client, bucket: String, prefix: String) -> bool {
+		SETTINGS.set(S3Settings { client, bucket, prefix }).is_ok()
+	}
+}
+
+#[async_trait]
+impl<T: Config> TapestryChestHandler<T> for S3TapestryChest {
+	type Error = StorageError;
+
+	async fn save_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: &TID,
+		tapestry_fragment: TapestryFragment<T>,
+		increment: bool,
+	) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(tapestry_id).await?;
+
+			// Same instance semantics as the Redis `TapestryChest`
+			let mut tapestry_instance = last_instance(&dir).await?.unwrap_or(0).max(1);
+			if increment {
+				tapestry_instance += 1;
+
+				debug!("Incremented instance to {} for {}", tapestry_instance, dir);
+			}
+
+			let key = instance_key(&dir, tapestry_instance);
+			put_object(&key, compress(&StorageFormat::Json.serialize(&tapestry_fragment)?)?)
+				.await?;
+
+			debug!("Saved tapestry fragment to {}", key);
+			tracing::Span::current().record("tapestry.instance", tapestry_instance);
+
+			Ok(tapestry_instance)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn save_tapestry_metadata<
+		TID: TapestryId,
+		M: ToRedisArgs + Debug + Clone + Send + Sync,
+	>(
+		tapestry_id: TID,
+		metadata: M,
+	) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{METADATA_OBJECT}", tapestry_prefix(&tapestry_id).await?);
+			put_object(&key, metadata.to_redis_args().concat()).await?;
+
+			debug!("Saved metadata to {} with metadata {:?}", key, metadata);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			Ok(list_objects(&dir, true).await?.0.iter().any(|(key, _)| is_tapestry_object(key)))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			// Locks, reservations and weave counts do not make a tapestry
+			let Some(instance_count) = last_instance(&dir).await? else {
+				return Ok(None);
+			};
+
+			Ok(Some(u16::try_from(instance_count).map_err(|_| {
+				LoomError::from(StorageError::QuotaExceeded {
+					limit: u16::MAX as usize,
+					actual: instance_count as usize,
+				})
+			})?))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			let instance = match instance {
+				Some(instance) => instance,
+				None => match last_instance(&dir).await? {
+					Some(instance) => instance,
+					None => return Ok(None),
+				},
+			};
+
+			let key = instance_key(&dir, instance);
+			let bytes =
+				get_object(&key).await?.ok_or_else(|| LoomError::from(StorageError::NotFound))?;
+
+			Ok(Some(StorageFormat::Json.deserialize(&decompress(&bytes)?)?))
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{METADATA_OBJECT}", tapestry_prefix(&tapestry_id).await?);
+
+			match get_object(&key).await? {
+				Some(bytes) => Ok(Some(StorageFormat::Json.deserialize(&bytes)?)),
+				None => Ok(None),
+			}
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			// Only the objects of this tapestry are listed, leaving any nested tapestries intact
+			for (key, _) in list_objects(&dir, true).await?.0 {
+				delete_object(&key).await?;
+			}
+
+			debug!("Deleted {} tapestry", dir);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			let instance = match instance {
+				Some(instance) => instance,
+				None => match last_instance(&dir).await? {
+					Some(instance) => instance,
+					None => return Ok(()),
+				},
+			};
+
+			// S3 does not fail when deleting a missing object
+			let key = instance_key(&dir, instance);
+			if get_object(&key).await?.is_none() {
+				return Err(LoomError::from(StorageError::NotFound).into());
+			}
+			delete_object(&key).await?;
+
+			debug!("Deleted {}", key);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, timeout: Duration) -> crate::Result<String> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{LOCK_OBJECT}", tapestry_prefix(tapestry_id).await?);
+			let settings = settings().await?;
+			let token = new_lock_token();
+			let started_at = Instant::now();
+
+			loop {
+				let res = settings
+					.client
+					.put_object()
+					.bucket(&settings.bucket)
+					.key(&key)
+					.if_none_match("*")
+					.body(ByteStream::from(token.clone().into_bytes()))
+					.send()
+					.await;
+
+				match res {
+					Ok(_) => {
+						debug!("Acquired {} lock", key);
+						return Ok(token);
+					},
+					Err(e) if e.raw_response().is_some_and(|res| res.status().as_u16() == 412) => {
+						// Remove locks left behind by crashed processes
+						if is_expired(&key).await? {
+							debug!("Removing expired {} lock", key);
+							delete_object(&key).await?;
+							continue;
+						}
+					},
+					Err(e) => return Err(s3_error("create", &key, DisplayErrorContext(e)).into()),
+				}
+
+				if started_at.elapsed() >= timeout {
+					error!("Timed out acquiring {} lock after {:?}", key, timeout);
+					return Err(LoomError::from(WeaveError::LockTimeout).into());
+				}
+
+				tokio::time::sleep(LOCK_RETRY_DELAY + jitter(LOCK_RETRY_DELAY)).await;
+			}
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{LOCK_OBJECT}", tapestry_prefix(tapestry_id).await?);
+
+			// Only delete the lock if it is still held by this token
+			if get_object(&key).await?.is_some_and(|holder| holder == token.as_bytes()) {
+				delete_object(&key).await?;
+				debug!("Released {} lock", key);
+			}
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{RESERVED_OBJECT}", tapestry_prefix(tapestry_id).await?);
+
+			let reserved = read_count(&key).await?.saturating_add(tokens);
+			put_object(&key, reserved.to_string().into_bytes()).await?;
+
+			debug!("Reserved {} tokens on {}, {} reserved in total", tokens, key, reserved);
+
+			Ok(reserved)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{RESERVED_OBJECT}", tapestry_prefix(tapestry_id).await?);
+
+			match read_count(&key).await?.saturating_sub(tokens) {
+				0 => delete_object(&key).await?,
+				reserved => put_object(&key, reserved.to_string().into_bytes()).await?,
+			}
+
+			debug!("Released {} tokens on {}", tokens, key);
+
+			Ok(())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			read_count(&format!("{}{RESERVED_OBJECT}", tapestry_prefix(tapestry_id).await?)).await
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let key = format!("{}{WEAVE_COUNT_OBJECT}", tapestry_prefix(tapestry_id).await?);
+
+			let weave_count = read_count(&key).await?.saturating_add(1);
+			put_object(&key, weave_count.to_string().into_bytes()).await?;
+
+			debug!("Incremented weave count of {} to {}", key, weave_count);
+
+			Ok(weave_count)
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			read_count(&format!("{}{WEAVE_COUNT_OBJECT}", tapestry_prefix(tapestry_id).await?))
+				.await
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			let mut repaired = 0;
+			for instance in instances(&dir).await? {
+				let key = instance_key(&dir, instance);
+				let Some(bytes) = get_object(&key).await? else {
+					continue;
+				};
+
+				let mut tapestry_fragment: TapestryFragment<T> =
+					StorageFormat::Json.deserialize(&decompress(&bytes)?)?;
+				if !tapestry_fragment.recount_tokens()? {
+					continue;
+				}
+
+				put_object(&key, compress(&StorageFormat::Json.serialize(&tapestry_fragment)?)?)
+					.await?;
+
+				debug!("Repaired context_tokens of {}", key);
+
+				repaired += 1;
+			}
+
+			Ok(repaired)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Not supported, fails with [`StorageError::Unsupported`].
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, _ttl: Duration) -> crate::Result<()> {
+		error!("Cannot set ttl of {}: use bucket lifecycle rules instead", tapestry_id.base_key());
+		Err(LoomError::from(StorageError::Unsupported("set_ttl".to_string())).into())
+	}
+
+	/// Sums the compressed sizes of the tapestry fragment objects.
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		let span = tapestry_span!(tapestry_id);
+		async move {
+			let dir = tapestry_prefix(&tapestry_id).await?;
+
+			Ok(list_objects(&dir, true)
+				.await?
+				.0
+				.into_iter()
+				.filter(|(key, _)| parse_instance(key).is_some())
+				.map(|(_, size)| size)
+				.sum())
+		}
+		.instrument(span)
+		.await
+	}
+
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		let span = tapestry_span!(parent);
+		async move {
+			let dir = tapestry_prefix(parent).await?;
+
+			let mut children = list_objects(&dir, true)
+				.await?
+				.1
+				.iter()
+				.filter_map(|prefix| prefix.strip_prefix(&dir)?.strip_suffix('/'))
+				.map(|name| parent.child(name))
+				.collect::<Vec<_>>();
+			children.sort_by_key(|child| child.base_key());
+
+			Ok(children)
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Lists every object of the bucket prefix, a tapestry is any key prefix holding tapestry
+	/// fragment instances or metadata.
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		let root = root_prefix().await?;
+
+		let base_keys = list_objects(&root, false)
+			.await?
+			.0
+			.iter()
+			.filter(|(key, _)| is_tapestry_object(key))
+			.filter_map(|(key, _)| Some(key.strip_prefix(&root)?.rsplit_once('/')?.0.to_string()))
+			.filter(|base_key| base_key.starts_with(T::KEY_SCAN_PREFIX))
+			.collect::<BTreeSet<_>>();
+
+		Ok(base_keys.into_iter().collect())
+	}
+}
+
+/// Client, bucket and key prefix used by the [`S3TapestryChest`].
+static SETTINGS: OnceCell<S3Settings> = OnceCell::const_new();
+
+/// Get the settings, loading them from the environment unless [`S3TapestryChest::configure`] was
+/// called.
+async fn settings() -> crate::Result<&'static S3Settings> {
+	Ok(SETTINGS
+		.get_or_try_init(|| async {
+			debug!("Initializing S3 client");
+
+			let bucket = std::env::var("TAPESTRY_S3_BUCKET").map_err(|_| {
+				error!("TAPESTRY_S3_BUCKET is not set");
+				LoomError::from(StorageError::ConnectionFailed(
+					"TAPESTRY_S3_BUCKET is not set".to_string(),
+				))
+			})?;
+			let prefix = std::env::var("TAPESTRY_S3_PREFIX").unwrap_or_default();
+			let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+
+			Ok::<_, LoomError>(S3Settings { client: Client::new(&config), bucket, prefix })
+		})
+		.await?)
+}
+
+/// Key prefix all tapestries are stored under, either empty or ending with `/`.
+async fn root_prefix() -> crate::Result<String> {
+	let prefix = settings().await?.prefix.trim_matches('/');
+
+	Ok(if prefix.is_empty() { String::new() } else { format!("{prefix}/") })
+}
+
+/// Get the key prefix of a tapestry, ending with `/`.
+///
+/// Fails with [`StorageError::InvalidKey`] if the base key contains empty segments, which would
+/// make keys ambiguous.
+async fn tapestry_prefix<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<String> {
+	let base_key = validated_base_key(tapestry_id)?;
+
+	if base_key
+		.split('/')
+		.any(|segment| segment.is_empty() || segment == "." || segment == "..")
+	{
+		error!("Invalid tapestry_id {:?}: not a relative path", tapestry_id);
+		return Err(LoomError::from(StorageError::InvalidKey(format!(
+			"{} is not a relative path",
+			base_key
+		)))
+		.into());
+	}
+
+	Ok(format!("{}{base_key}/", root_prefix().await?))
+}
+
+fn instance_key(dir: &str, instance: u64) -> String {
+	format!("{dir}{instance}{INSTANCE_EXTENSION}")
+}
+
+/// Instance of a tapestry fragment object key.
+fn parse_instance(key: &str) -> Option<u64> {
+	key.rsplit_once('/')
+		.map_or(key, |(_, name)| name)
+		.strip_suffix(INSTANCE_EXTENSION)?
+		.parse()
+		.ok()
+}
+
+/// Whether `key` is a tapestry fragment instance or the metadata of a tapestry, rather than its
+/// lock, token reservation or weave count.
+fn is_tapestry_object(key: &str) -> bool {
+	parse_instance(key).is_some() ||
+		key.rsplit_once('/').is_some_and(|(_, name)| name == METADATA_OBJECT)
+}
+
+/// All tapestry fragment instances found under `dir` in ascending order.
+async fn instances(dir: &str) -> crate::Result<Vec<u64>> {
+	let mut instances = list_objects(dir, true)
+		.await?
+		.0
+		.iter()
+		.filter_map(|(key, _)| parse_instance(key))
+		.collect::<Vec<_>>();
+	instances.sort_unstable();
+
+	Ok(instances)
+}
+
+/// Highest tapestry fragment instance found under `dir`.
+async fn last_instance(dir: &str) -> crate::Result<Option<u64>> {
+	Ok(instances(dir).await?.last().copied())
+}
+
+/// List the keys and sizes of the objects starting with `prefix`, as well as the common prefixes
+/// up to the next `/` if `shallow`.
+async fn list_objects(
+	prefix: &str,
+	shallow: bool,
+) -> crate::Result<(Vec<(String, u64)>, Vec<String>)> {
+	let settings = settings().await?;
+
+	let (mut objects, mut prefixes) = (vec![], vec![]);
+	let mut continuation_token = None;
+	loop {
+		let res = settings
+			.client
+			.list_objects_v2()
+			.bucket(&settings.bucket)
+			.prefix(prefix)
+			.set_delimiter(shallow.then(|| "/".to_string()))
+			.set_continuation_token(continuation_token)
+			.send()
+			.await
+			.map_err(|e| s3_error("list", prefix, DisplayErrorContext(e)))?;
+
+		objects.extend(res.contents().iter().filter_map(|object| {
+			Some((object.key()?.to_string(), object.size().unwrap_or(0).max(0) as u64))
+		}));
+		prefixes.extend(
+			res.common_prefixes().iter().filter_map(|p| p.prefix()).map(ToString::to_string),
+		);
+
+		continuation_token = res.next_continuation_token().map(ToString::to_string);
+		if !res.is_truncated().unwrap_or(false) || continuation_token.is_none() {
+			break;
+		}
+	}
+
+	Ok((objects, prefixes))
+}
+
+/// Get the content of the object at `key`, `None` if it does not exist.
+async fn get_object(key: &str) -> crate::Result<Option<Vec<u8>>> {
+	let settings = settings().await?;
+
+	let res = match settings.client.get_object().bucket(&settings.bucket).key(key).send().await {
+		Ok(res) => res,
+		Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+		Err(e) => return Err(s3_error("read", key, DisplayErrorContext(e)).into()),
+	};
+
+	let bytes = res.body.collect().await.map_err(|e| s3_error("read", key, e))?;
+
+	Ok(Some(bytes.to_vec()))
+}
+
+async fn put_object(key: &str, bytes: Vec<u8>) -> crate::Result<()> {
+	let settings = settings().await?;
+
+	settings
+		.client
+		.put_object()
+		.bucket(&settings.bucket)
+		.key(key)
+		.body(ByteStream::from(bytes))
+		.send()
+		.await
+		.map_err(|e| s3_error("write", key, DisplayErrorContext(e)))?;
+
+	Ok(())
+}
+
+async fn delete_object(key: &str) -> crate::Result<()> {
+	let settings = settings().await?;
+
+	settings
+		.client
+		.delete_object()
+		.bucket(&settings.bucket)
+		.key(key)
+		.send()
+		.await
+		.map_err(|e| s3_error("delete", key, DisplayErrorContext(e)))?;
+
+	Ok(())
+}
+
+/// Count in the object at `key`, such as the number of tokens reserved, `0` if it does not exist.
+async fn read_count(key: &str) -> crate::Result<u64> {
+	let Some(bytes) = get_object(key).await? else {
+		return Ok(0);
+	};
+
+	let count = String::from_utf8_lossy(&bytes);
+	Ok(count.trim().parse().map_err(|_| {
+		error!("Invalid count in {}: {}", key, count);
+		LoomError::from(StorageError::Parsing)
+	})?)
+}
+
+/// Whether the lock object at `key` is older than [`LOCK_EXPIRY`].
+async fn is_expired(key: &str) -> crate::Result<bool> {
+	let settings = settings().await?;
+
+	let res = match settings.client.head_object().bucket(&settings.bucket).key(key).send().await {
+		Ok(res) => res,
+		// Released in the meantime
+		Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(false),
+		Err(e) => return Err(s3_error("read", key, DisplayErrorContext(e)).into()),
+	};
+
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+	Ok(res
+		.last_modified()
+		.is_some_and(|modified| now - modified.secs() > LOCK_EXPIRY.as_secs() as i64))
+}
+
+fn compress(bytes: &[u8]) -> crate::Result<Vec<u8>> {
+	let mut encoder = GzEncoder::new(vec![], Compression::default());
+	encoder.write_all(bytes).map_err(|e| {
+		error!("Failed to compress tapestry fragment: {}", e);
+		LoomError::from(StorageError::Io(e))
+	})?;
+
+	Ok(encoder.finish().map_err(|e| {
+		error!("Failed to compress tapestry fragment: {}", e);
+		LoomError::from(StorageError::Io(e))
+	})?)
+}
+
+fn decompress(bytes: &[u8]) -> crate::Result<Vec<u8>> {
+	let mut decompressed = vec![];
+	GzDecoder::new(bytes).read_to_end(&mut decompressed).map_err(|e| {
+		error!("Failed to decompress tapestry fragment: {}", e);
+		LoomError::from(StorageError::Io(e))
+	})?;
+
+	Ok(decompressed)
+}
+
+fn s3_error(action: &str, key: &str, e: impl std::fmt::Display) -> LoomError {
+	error!("Failed to {} {}: {}", action, key, e);
+	LoomError::from(StorageError::S3(e.to_string()))
+}
@@ -0,0 +1,386 @@
+//! Test infrastructure for applications built on [`Loom`](crate::Loom).
+//!
+//! Only available with the `testing` feature.
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Mutex, MutexGuard, OnceLock},
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use redis::ToRedisArgs;
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+use crate::{
+	storage::{new_lock_token, validated_base_key},
+	types::{LoomError, StorageError, StorageFormat, WeaveError},
+	Config, Debug, HierarchicalId, TapestryChestHandler, TapestryFragment, TapestryId,
+};
+
+/// Number of calls made to [`MockTapestryChest`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MockCallCounts {
+	/// Calls to [`TapestryChestHandler::get_tapestry_fragment`].
+	pub gets: usize,
+	/// Calls to [`TapestryChestHandler::save_tapestry_fragment`].
+	pub saves: usize,
+	/// Calls to [`TapestryChestHandler::delete_tapestry`] and
+	/// [`TapestryChestHandler::delete_tapestry_fragment`].
+	pub deletes: usize,
+}
+
+/// In-memory state of a single tapestry.
+#[derive(Default)]
+struct MockTapestry {
+	instance_count: u64,
+	/// Serialized tapestry fragment of each instance.
+	fragments: BTreeMap<u64, Vec<u8>>,
+	/// Every serialized tapestry fragment ever saved, in order.
+	saved: Vec<Vec<u8>>,
+	metadata: Option<Vec<u8>>,
+	lock: Option<String>,
+	reserved_tokens: u64,
+	weave_count: u64,
+}
+
+#[derive(Default)]
+struct MockState {
+	tapestries: HashMap<String, MockTapestry>,
+	get_error: Option<StorageError>,
+	call_counts: MockCallCounts,
+}
+
+static STATE: OnceLock<Mutex<MockState>> = OnceLock::new();
+
+fn state() -> MutexGuard<'static, MockState> {
+	STATE
+		.get_or_init(Default::default)
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// [`TapestryChestHandler`] storing tapestry fragments in a [`HashMap`] for deterministic unit
+/// testing.
+///
+/// Tapestry fragments are serialized to JSON, exactly like the Redis
+/// [`TapestryChest`](crate::storage::TapestryChest), so serialization issues surface in tests.
+///
+/// The state is shared by the whole process. Tests running in parallel should use distinct
+/// [`TapestryId`]s, and [`MockTapestryChest::call_counts`] and
+/// [`MockTapestryChest::set_get_error`] should only be relied on by a single test at a time.
+pub struct MockTapestryChest;
+
+impl MockTapestryChest {
+	/// Assert that a tapestry fragment matching `predicate` was saved for `tapestry_id`.
+	///
+	/// # Panics
+	///
+	/// If none of the tapestry fragments saved for `tapestry_id` match `predicate`.
+	pub fn assert_fragment_saved_with<T: Config, TID: TapestryId>(
+		tapestry_id: &TID,
+		predicate: impl Fn(&TapestryFragment<T>) -> bool,
+	) {
+		let base_key = tapestry_id.base_key();
+		let saved = state().tapestries.get(&base_key).map(|t| t.saved.clone()).unwrap_or_default();
+
+		assert!(
+			saved.iter().any(|bytes| {
+				StorageFormat::Json
+					.deserialize::<TapestryFragment<T>>(bytes)
+					.is_ok_and(|tapestry_fragment| predicate(&tapestry_fragment))
+			}),
+			"No matching tapestry fragment saved for {} out of {} saved",
+			base_key,
+			saved.len()
+		);
+	}
+
+	/// Make the next call to [`TapestryChestHandler::get_tapestry_fragment`] fail with `error`.
+	pub fn set_get_error(error: StorageError) {
+		state().get_error = Some(error);
+	}
+
+	/// Number of calls made since the last [`MockTapestryChest::reset`].
+	pub fn call_counts() -> MockCallCounts {
+		state().call_counts
+	}
+
+	/// Remove all tapestries, the pending error and the call counts.
+	pub fn reset() {
+		*state() = MockState::default();
+	}
+}
+
+#[async_trait]
+impl<T: Config> TapestryChestHandler<T> for MockTapestryChest {
+	type Error = StorageError;
+
+	async fn save_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: &TID,
+		tapestry_fragment: TapestryFragment<T>,
+		increment: bool,
+	) -> crate::Result<u64> {
+		let base_key = validated_base_key(tapestry_id)?;
+		let bytes = StorageFormat::Json.serialize(&tapestry_fragment)?;
+
+		let mut state = state();
+		state.call_counts.saves += 1;
+
+		// Same instance semantics as the Redis `TapestryChest`
+		let tapestry = state.tapestries.entry(base_key).or_default();
+		tapestry.instance_count = tapestry.instance_count.max(1);
+		if increment {
+			tapestry.instance_count += 1;
+		}
+
+		let instance = tapestry.instance_count;
+		tapestry.fragments.insert(instance, bytes.clone());
+		tapestry.saved.push(bytes);
+
+		debug!("Saved tapestry fragment instance {}", instance);
+
+		Ok(instance)
+	}
+
+	async fn save_tapestry_metadata<
+		TID: TapestryId,
+		M: ToRedisArgs + Debug + Clone + Send + Sync,
+	>(
+		tapestry_id: TID,
+		metadata: M,
+	) -> crate::Result<()> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		state().tapestries.entry(base_key).or_default().metadata =
+			Some(metadata.to_redis_args().concat());
+
+		Ok(())
+	}
+
+	async fn exists<TID: TapestryId>(tapestry_id: TID) -> crate::Result<bool> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		Ok(state().tapestries.contains_key(&base_key))
+	}
+
+	async fn get_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<Option<u16>> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		match state().tapestries.get(&base_key) {
+			Some(tapestry) => Ok(Some(u16::try_from(tapestry.instance_count).map_err(|_| {
+				LoomError::from(StorageError::QuotaExceeded {
+					limit: u16::MAX as usize,
+					actual: tapestry.instance_count as usize,
+				})
+			})?)),
+			None => Ok(None),
+		}
+	}
+
+	async fn get_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<Option<TapestryFragment<T>>> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		let mut state = state();
+		state.call_counts.gets += 1;
+
+		if let Some(error) = state.get_error.take() {
+			return Err(LoomError::from(error).into());
+		}
+
+		let Some(tapestry) = state.tapestries.get(&base_key) else {
+			return Ok(None);
+		};
+
+		let bytes = match instance {
+			Some(instance) => tapestry
+				.fragments
+				.get(&instance)
+				.ok_or_else(|| LoomError::from(StorageError::NotFound))?,
+			None => match tapestry.fragments.get(&tapestry.instance_count) {
+				Some(bytes) => bytes,
+				None => return Ok(None),
+			},
+		};
+
+		Ok(Some(StorageFormat::Json.deserialize(bytes)?))
+	}
+
+	async fn get_tapestry_metadata<TID: TapestryId, M: DeserializeOwned>(
+		tapestry_id: TID,
+	) -> crate::Result<Option<M>> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		match state().tapestries.get(&base_key).and_then(|t| t.metadata.as_ref()) {
+			Some(bytes) => Ok(Some(StorageFormat::Json.deserialize(bytes)?)),
+			None => Ok(None),
+		}
+	}
+
+	async fn delete_tapestry<TID: TapestryId>(tapestry_id: TID) -> crate::Result<()> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		let mut state = state();
+		state.call_counts.deletes += 1;
+		state.tapestries.remove(&base_key);
+
+		Ok(())
+	}
+
+	async fn delete_tapestry_fragment<TID: TapestryId>(
+		tapestry_id: TID,
+		instance: Option<u64>,
+	) -> crate::Result<()> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		let mut state = state();
+		state.call_counts.deletes += 1;
+
+		let Some(tapestry) = state.tapestries.get_mut(&base_key) else {
+			return Ok(());
+		};
+
+		let instance = instance.unwrap_or(tapestry.instance_count);
+		match tapestry.fragments.remove(&instance) {
+			Some(_) => Ok(()),
+			None => Err(LoomError::from(StorageError::NotFound).into()),
+		}
+	}
+
+	/// Fails immediately with [`WeaveError::LockTimeout`] if `tapestry_id` is already locked.
+	async fn lock<TID: TapestryId>(tapestry_id: &TID, _timeout: Duration) -> crate::Result<String> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		let mut state = state();
+		let tapestry = state.tapestries.entry(base_key).or_default();
+		if tapestry.lock.is_some() {
+			return Err(LoomError::from(WeaveError::LockTimeout).into());
+		}
+
+		let token = new_lock_token();
+		tapestry.lock = Some(token.clone());
+
+		Ok(token)
+	}
+
+	async fn unlock<TID: TapestryId>(tapestry_id: &TID, token: String) -> crate::Result<()> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		if let Some(tapestry) = state().tapestries.get_mut(&base_key) {
+			if tapestry.lock.as_ref() == Some(&token) {
+				tapestry.lock = None;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn reserve_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<u64> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		let mut state = state();
+		let tapestry = state.tapestries.entry(base_key).or_default();
+		tapestry.reserved_tokens = tapestry.reserved_tokens.saturating_add(tokens);
+
+		Ok(tapestry.reserved_tokens)
+	}
+
+	async fn release_tokens<TID: TapestryId>(tapestry_id: &TID, tokens: u64) -> crate::Result<()> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		if let Some(tapestry) = state().tapestries.get_mut(&base_key) {
+			tapestry.reserved_tokens = tapestry.reserved_tokens.saturating_sub(tokens);
+		}
+
+		Ok(())
+	}
+
+	async fn get_reserved_tokens<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		Ok(state().tapestries.get(&base_key).map_or(0, |t| t.reserved_tokens))
+	}
+
+	async fn increment_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		let mut state = state();
+		let tapestry = state.tapestries.entry(base_key).or_default();
+		tapestry.weave_count += 1;
+
+		Ok(tapestry.weave_count)
+	}
+
+	async fn get_weave_count<TID: TapestryId>(tapestry_id: &TID) -> crate::Result<u64> {
+		let base_key = validated_base_key(tapestry_id)?;
+
+		Ok(state().tapestries.get(&base_key).map_or(0, |t| t.weave_count))
+	}
+
+	async fn repair_token_counts<TID: TapestryId>(tapestry_id: TID) -> crate::Result<usize> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		let mut state = state();
+		let Some(tapestry) = state.tapestries.get_mut(&base_key) else {
+			return Ok(0);
+		};
+
+		let mut repaired = 0;
+		for bytes in tapestry.fragments.values_mut() {
+			let mut tapestry_fragment: TapestryFragment<T> =
+				StorageFormat::Json.deserialize(bytes)?;
+			if tapestry_fragment.recount_tokens()? {
+				*bytes = StorageFormat::Json.serialize(&tapestry_fragment)?;
+				repaired += 1;
+			}
+		}
+
+		Ok(repaired)
+	}
+
+	/// Tapestries never expire, the `ttl` is ignored.
+	async fn set_ttl<TID: TapestryId>(tapestry_id: TID, _ttl: Duration) -> crate::Result<()> {
+		validated_base_key(&tapestry_id)?;
+
+		Ok(())
+	}
+
+	async fn get_total_storage_bytes<TID: TapestryId>(tapestry_id: TID) -> crate::Result<u64> {
+		let base_key = validated_base_key(&tapestry_id)?;
+
+		Ok(state()
+			.tapestries
+			.get(&base_key)
+			.map_or(0, |t| t.fragments.values().map(|bytes| bytes.len() as u64).sum()))
+	}
+
+	async fn list_children(parent: &HierarchicalId) -> crate::Result<Vec<HierarchicalId>> {
+		let prefix = format!("{}/", validated_base_key(parent)?);
+
+		let mut children = state()
+			.tapestries
+			.keys()
+			.filter_map(|key| key.strip_prefix(&prefix))
+			.filter(|name| !name.contains('/'))
+			.map(|name| parent.child(name))
+			.collect::<Vec<_>>();
+		children.sort_by_key(|child| child.base_key());
+
+		Ok(children)
+	}
+
+	async fn list_all_tapestry_ids() -> crate::Result<Vec<String>> {
+		let mut base_keys = state()
+			.tapestries
+			.keys()
+			.filter(|key| key.starts_with(T::KEY_SCAN_PREFIX))
+			.cloned()
+			.collect::<Vec<_>>();
+		base_keys.sort();
+
+		Ok(base_keys)
+	}
+}
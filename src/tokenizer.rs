@@ -0,0 +1,21 @@
+//! Cached [tiktoken-rs](https://github.com/zurawiki/tiktoken-rs) tokenizers.
+//!
+//! Initializing a BPE tokenizer parses its entire vocabulary, which is expensive to do on every
+//! call to [`Llm::count_tokens`](crate::Llm::count_tokens). The tokenizers exposed here are
+//! initialized once and reused for the lifetime of the program.
+use std::sync::OnceLock;
+
+use tiktoken_rs::CoreBPE;
+
+static CL100K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+static P50K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+
+/// Tokenizer used by GPT-4 and GPT-3.5 models.
+pub fn cl100k_base() -> &'static CoreBPE {
+	CL100K_BASE.get_or_init(|| tiktoken_rs::cl100k_base().expect("Failed to load cl100k_base"))
+}
+
+/// Tokenizer used by legacy GPT-3 models.
+pub fn p50k_base() -> &'static CoreBPE {
+	P50K_BASE.get_or_init(|| tiktoken_rs::p50k_base().expect("Failed to load p50k_base"))
+}
@@ -0,0 +1,46 @@
+//! Export of stored conversations as fine-tuning datasets.
+use crate::{
+	storage::with_storage_timeout,
+	types::{LoomError, StorageError},
+	Config, TapestryChestHandler, TapestryId,
+};
+
+/// Export all tapestry fragment instances of every tapestry in `tapestry_ids` as a single
+/// fine-tuning dataset in the JSONL format expected by OpenAI.
+///
+/// Tapestries are exported in order, each from its oldest instance to its newest. Missing
+/// tapestries and deleted instances are skipped. See [`TapestryFragment::as_training_jsonl`].
+///
+/// [`TapestryFragment::as_training_jsonl`]: crate::TapestryFragment::as_training_jsonl
+pub async fn export_training_dataset<T: Config, TID: TapestryId>(
+	tapestry_ids: Vec<TID>,
+) -> crate::Result<String> {
+	let mut dataset = String::new();
+
+	for tapestry_id in tapestry_ids {
+		let Some(instance_count) =
+			with_storage_timeout::<T, _>(T::Chest::get_tapestry(tapestry_id.clone())).await?
+		else {
+			continue;
+		};
+
+		for instance in 1..=instance_count as u64 {
+			match with_storage_timeout::<T, _>(T::Chest::get_tapestry_fragment(
+				tapestry_id.clone(),
+				Some(instance),
+			))
+			.await
+			{
+				Ok(Some(tapestry_fragment)) =>
+					dataset.push_str(&tapestry_fragment.as_training_jsonl()),
+				Ok(None) => {},
+				Err(e) => match LoomError::from(e) {
+					LoomError::Storage(StorageError::NotFound) => {},
+					e => return Err(e.into()),
+				},
+			}
+		}
+	}
+
+	Ok(dataset)
+}
@@ -1,12 +1,187 @@
+use std::{str::FromStr, time::Duration};
+
 use async_openai::types::Role;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::error;
 
-use crate::{Config, Llm};
+use crate::{Config, ContextMessage, Llm};
 
 pub type PromptModelTokens<T> = <<T as Config>::PromptModel as Llm<T>>::Tokens;
 pub type SummaryModelTokens<T> = <<T as Config>::SummaryModel as Llm<T>>::Tokens;
 pub type PromptModelRequest<T> = <<T as Config>::PromptModel as Llm<T>>::Request;
 
+/// Quality of a summary estimated from its compression ratio.
+///
+/// See [`Loom::estimate_summary_quality`](crate::Loom::estimate_summary_quality).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SummaryQuality {
+	/// The summary is at least 10% of the size of the source.
+	Excellent,
+	/// The summary is between 5% and 10% of the size of the source.
+	Good,
+	/// The summary is between 2% and 5% of the size of the source.
+	Poor,
+	/// The summary is less than 2% of the size of the source, or empty.
+	TooShort,
+}
+
+/// Output of [`Loom::dry_run`](crate::Loom::dry_run).
+#[derive(Debug, Clone)]
+pub struct DryRunOutput<T: Config> {
+	/// Messages that would be sent to the [`Config::PromptModel`].
+	///
+	/// When a summary would be generated, the summary message is not included since generating it
+	/// requires prompting the [`Config::SummaryModel`].
+	pub messages: Vec<ContextMessage<T>>,
+	/// Number of tokens in `messages` as counted by the [`Config::PromptModel`].
+	pub estimated_prompt_tokens: PromptModelTokens<T>,
+	/// Whether a summary would be generated and a new tapestry fragment instance created.
+	pub would_summarize: bool,
+}
+
+/// Differences between two [`TapestryFragment`](crate::TapestryFragment) instances.
+///
+/// See [`TapestryFragment::diff`](crate::TapestryFragment::diff).
+#[derive(Debug, Clone)]
+pub struct FragmentDiff<'a, T: Config> {
+	/// Messages only found in the other tapestry fragment.
+	pub added: Vec<&'a ContextMessage<T>>,
+	/// Messages only found in this tapestry fragment.
+	pub removed: Vec<&'a ContextMessage<T>>,
+	/// Change in `context_tokens` from this tapestry fragment to the other.
+	pub token_delta: i64,
+}
+
+/// Effectiveness of summarizing a [`TapestryFragment`](crate::TapestryFragment) into a new one.
+///
+/// See [`TapestryFragment::compression_report`](crate::TapestryFragment::compression_report).
+#[derive(Debug, Clone)]
+pub struct CompressionReport<T: Config> {
+	pub messages_before: usize,
+	pub messages_after: usize,
+	pub tokens_before: PromptModelTokens<T>,
+	pub tokens_after: PromptModelTokens<T>,
+	/// `tokens_after` divided by `tokens_before`, `1.0` when `tokens_before` is zero.
+	pub compression_ratio: f32,
+}
+
+/// Specification of a function the LLM may call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionSpec {
+	pub name: String,
+	pub description: Option<String>,
+	/// JSON Schema of the function arguments.
+	pub parameters: serde_json::Value,
+}
+
+/// A function call requested by the LLM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+	pub name: String,
+	/// JSON encoded arguments.
+	pub arguments: String,
+}
+
+/// Log probability of a token of a response.
+///
+/// See [`Llm::logprobs`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+	pub token: String,
+	pub logprob: f64,
+}
+
+/// Score of a response given by an LLM judge, see [`Loom::score_response`].
+///
+/// Parsed from a reply containing a `Score: <number>` line, the `reasoning` being the rest of the
+/// reply with any `Reasoning:` prefix removed.
+///
+/// [`Loom::score_response`]: crate::Loom::score_response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResponseScore {
+	pub score: f32,
+	pub reasoning: String,
+}
+
+impl FromStr for ResponseScore {
+	type Err = ParseError;
+
+	fn from_str(reply: &str) -> Result<Self, Self::Err> {
+		let (score_line, score) = reply
+			.lines()
+			.enumerate()
+			.find_map(|(i, line)| Some((i, parse_score(line)?)))
+			.ok_or(ParseError::MissingScore)?;
+
+		let reasoning = reply
+			.lines()
+			.enumerate()
+			.filter(|(i, _)| *i != score_line)
+			.map(|(_, line)| line)
+			.collect::<Vec<_>>()
+			.join("\n");
+		let reasoning = reasoning.trim();
+		let reasoning = reasoning
+			.get(..10)
+			.filter(|prefix| prefix.eq_ignore_ascii_case("reasoning:"))
+			.map_or(reasoning, |_| reasoning[10..].trim_start());
+
+		Ok(Self { score, reasoning: reasoning.to_string() })
+	}
+}
+
+/// Number following `score` in `line`, e.g. `**Score:** 7.5/10`.
+fn parse_score(line: &str) -> Option<f32> {
+	let start = line.to_ascii_lowercase().find("score")? + "score".len();
+	let rest = line[start..].trim_start_matches(|c: char| !c.is_ascii_digit());
+	let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+
+	rest[..end].trim_end_matches('.').parse().ok()
+}
+
+/// Response split into its chain of thought and final answer, see [`Loom::weave_with_cot`].
+///
+/// [`Loom::weave_with_cot`]: crate::Loom::weave_with_cot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoTResponse {
+	/// Step by step reasoning found between `<thinking>` and `</thinking>`.
+	pub reasoning: String,
+	/// Rest of the response.
+	pub answer: String,
+}
+
+impl CoTResponse {
+	/// Split a raw response into the reasoning between `<thinking>` tags and the answer.
+	///
+	/// The whole response is the answer if it has no `<thinking>` tag. An unclosed tag makes the
+	/// rest of the response the reasoning.
+	pub fn parse(raw: &str) -> Self {
+		let Some((before, rest)) = raw.split_once("<thinking>") else {
+			return Self { reasoning: String::new(), answer: raw.trim().to_string() };
+		};
+		let (reasoning, after) = rest.split_once("</thinking>").unwrap_or((rest, ""));
+
+		let answer = format!("{}\n{}", before.trim(), after.trim());
+		Self { reasoning: reasoning.trim().to_string(), answer: answer.trim().to_string() }
+	}
+}
+
+/// Change to a tapestry delivered by [`TapestryChestHandler::watch`].
+///
+/// [`TapestryChestHandler::watch`]: crate::storage::TapestryChestHandler::watch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+#[non_exhaustive]
+pub enum TapestryEvent<T: Config> {
+	/// A message was added to the current tapestry fragment instance.
+	MessageAdded(ContextMessage<T>),
+	/// A summary was generated and a new tapestry fragment instance was started.
+	FragmentSummarized,
+	/// A tapestry fragment instance was deleted.
+	FragmentDeleted,
+}
+
 /// Base type for all configuration parameters.
 pub type F32 = f32;
 
@@ -15,12 +190,141 @@ pub const ASSISTANT_ROLE: &str = "assistant";
 pub const USER_ROLE: &str = "user";
 pub const FUNCTION_ROLE: &str = "function";
 
+/// Format used to encode the messages sent to the [`Config::PromptModel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum PromptFormat {
+	/// Each message is sent as its own request message with its own role.
+	#[default]
+	OpenAI,
+	/// All messages are encoded with ChatML tokens (`<|im_start|>system\n...<|im_end|>`) into a
+	/// single user message.
+	///
+	/// Useful for locally-hosted fine-tuned models which only accept user messages.
+	ChatML,
+}
+
+/// Order in which [`TapestryFragment::truncate_to_tokens`] removes messages.
+///
+/// A leading system message and pinned messages are never removed.
+///
+/// [`TapestryFragment::truncate_to_tokens`]: crate::TapestryFragment::truncate_to_tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ContextTruncationStrategy {
+	/// Remove the oldest messages first.
+	#[default]
+	OldestFirst,
+	/// Remove the messages nearest the center of the history first, keeping both the beginning
+	/// of the conversation and the latest messages.
+	MiddleOut,
+	/// Remove the message with the most tokens first.
+	ByTokenDensity,
+}
+
+/// Serialization format used by [`TapestryChestHandler`](crate::TapestryChestHandler)
+/// implementations to persist [`TapestryFragment`](crate::TapestryFragment) data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum StorageFormat {
+	#[default]
+	Json,
+	/// Compact binary format. Requires the `msgpack` feature.
+	#[cfg(feature = "msgpack")]
+	MessagePack,
+}
+
+impl StorageFormat {
+	/// Serialize `value` to bytes in this format.
+	pub fn serialize<V: Serialize + ?Sized>(&self, value: &V) -> Result<Vec<u8>, StorageError> {
+		match self {
+			Self::Json => serde_json::to_vec(value).map_err(|e| {
+				error!("Failed to serialize to JSON: {}", e);
+				StorageError::SerializationFailed(e.to_string())
+			}),
+			#[cfg(feature = "msgpack")]
+			Self::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| {
+				error!("Failed to serialize to MessagePack: {}", e);
+				StorageError::SerializationFailed(e.to_string())
+			}),
+		}
+	}
+
+	/// Deserialize `bytes` in this format.
+	pub fn deserialize<V: DeserializeOwned>(&self, bytes: &[u8]) -> Result<V, StorageError> {
+		match self {
+			Self::Json => serde_json::from_slice(bytes).map_err(|e| {
+				error!("Failed to deserialize from JSON: {}", e);
+				StorageError::SerializationFailed(e.to_string())
+			}),
+			#[cfg(feature = "msgpack")]
+			Self::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| {
+				error!("Failed to deserialize from MessagePack: {}", e);
+				StorageError::SerializationFailed(e.to_string())
+			}),
+		}
+	}
+}
+
+/// A part of a multimodal [`ContextMessage`].
+#[cfg(feature = "multimodal")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum MessageContent {
+	Text(String),
+	/// URL of an image, or base64 encoded image data.
+	ImageUrl(String),
+}
+
 /// Wrapped [`Role`] for custom implementations.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum WrapperRole {
 	Role(Role),
 }
 
+// `Role` only derives `PartialEq` although all of its variants are fieldless.
+impl Eq for WrapperRole {}
+
+impl WrapperRole {
+	/// Position of the role when sorting messages by role precedence.
+	fn precedence(&self) -> u8 {
+		match self {
+			Self::Role(Role::System) => 0,
+			Self::Role(Role::Function | Role::Tool) => 1,
+			Self::Role(Role::User) => 2,
+			Self::Role(Role::Assistant) => 3,
+		}
+	}
+
+	/// Name of the role.
+	///
+	/// Unlike the [`String`] conversion, this also names [`Role::Tool`].
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Role(Role::System) => SYSTEM_ROLE,
+			Self::Role(Role::Assistant) => ASSISTANT_ROLE,
+			Self::Role(Role::User) => USER_ROLE,
+			Self::Role(Role::Function) => FUNCTION_ROLE,
+			Self::Role(Role::Tool) => "tool",
+		}
+	}
+}
+
+/// Roles are ordered by precedence: `System < Function < User < Assistant`.
+///
+/// [`Role::Tool`] has the same precedence as [`Role::Function`].
+impl Ord for WrapperRole {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+		self.precedence().cmp(&other.precedence())
+	}
+}
+
+impl PartialOrd for WrapperRole {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
 impl Default for WrapperRole {
 	fn default() -> Self {
 		Self::Role(Role::User)
@@ -55,11 +359,14 @@ impl From<WrapperRole> for String {
 }
 
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum LoomError {
 	#[error("Weave error: {0}")]
 	Weave(#[from] WeaveError),
 	#[error("Storage error: {0}")]
 	Storage(#[from] StorageError),
+	#[error("Parse error: {0}")]
+	Parse(#[from] ParseError),
 	#[error("Error: {0}")]
 	Error(String),
 }
@@ -74,19 +381,119 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for LoomError {
 }
 
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum WeaveError {
 	#[error("Exceeds max prompt tokens")]
 	MaxCompletionTokensIsZero,
 	#[error("Bad configuration: {0}")]
 	BadConfig(String),
+	#[error("Timed out after {0:?}")]
+	Timeout(Duration),
+	#[error("Weave was cancelled")]
+	Cancelled,
+	#[error("Timed out acquiring the tapestry lock")]
+	LockTimeout,
+	#[error("LLM still requested a function call after {0} tool iterations")]
+	MaxToolIterations(u8),
+	#[error("Unsupported OpenAI role: {0:?}")]
+	BadOpenAIRole(Role),
+	#[error("OpenAI message has no content")]
+	MissingContent,
+	#[error("Cannot reserve {requested} tokens, only {available} tokens are available")]
+	InsufficientTokens { requested: u64, available: u64 },
+	/// The new messages alone do not fit within the prompt token limit, even after summarizing.
+	#[error("New messages have {message_tokens} tokens, only {available} tokens are available")]
+	ContextExhausted { message_tokens: u64, available: u64 },
+	#[error("Invalid messages: {0:?}")]
+	InvalidMessages(Vec<MessageValidationError>),
+	#[error("Invalid message range {start}..{end} for {len} messages")]
+	InvalidRange { start: usize, end: usize, len: usize },
+	#[error("Invalid message index {index} for {len} messages")]
+	InvalidIndex { index: usize, len: usize },
+	#[error("Model does not support {0}")]
+	UnsupportedModelCapability(String),
+	#[error("Weave call {current} exceeds the limit of {limit} calls")]
+	QuotaExceeded { limit: u64, current: u64 },
+}
+
+/// Invalid [`ContextMessage`] found by [`Loom::validate_messages`](crate::Loom::validate_messages).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum MessageValidationError {
+	#[error("Message content is empty")]
+	EmptyContent,
+	#[error("Invalid role: {0}")]
+	InvalidRole(String),
+	/// The timestamp is not RFC 3339 formatted or is in the future.
+	#[error("Invalid timestamp: {0}")]
+	InvalidTimestamp(String),
+	#[error("Message {index} has {tokens} tokens, exceeding the limit of {limit} tokens")]
+	ContentTooLong { index: usize, tokens: u64, limit: u64 },
+}
+
+/// Invalid [`Config`] constants found by [`Config::validate_config`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid configuration: {}", .invalid_fields.join(", "))]
+pub struct ConfigError {
+	/// Description of every invalid constant.
+	pub invalid_fields: Vec<String>,
+}
+
+/// Unparseable input of
+/// [`TapestryFragment::from_chat_log`](crate::TapestryFragment::from_chat_log).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum ParseError {
+	#[error("No role prefix found in the first lines")]
+	UnrecognizedFormat,
+	#[error("No score found in the reply")]
+	MissingScore,
+	#[error("Reply {0:?} matches none of the categories")]
+	UnknownCategory(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum TapestryIdError {
+	#[error("Base key is empty")]
+	Empty,
+	#[error("Base key contains control character {0:?}")]
+	ControlCharacter(char),
+	#[error("Base key is {length} bytes long, exceeding the maximum of {max} bytes")]
+	TooLong { length: usize, max: usize },
 }
 
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum StorageError {
 	#[error("Redis error: {0}")]
 	Redis(redis::RedisError),
+	/// The storage backend is unreachable.
+	#[error("Connection failed: {0}")]
+	ConnectionFailed(String),
 	#[error("Parsing error")]
 	Parsing,
 	#[error("Not found")]
 	NotFound,
+	#[error("Serialization failed: {0}")]
+	SerializationFailed(String),
+	/// The [`TapestryId::base_key`](crate::TapestryId::base_key) cannot be used as a storage key.
+	#[error("Invalid key: {0}")]
+	InvalidKey(String),
+	/// A storage limit, such as the maximum number of tapestry fragments, was exceeded.
+	#[error("Quota exceeded: {actual} exceeds limit of {limit}")]
+	QuotaExceeded { limit: usize, actual: usize },
+	#[error("Transaction failed: {0}")]
+	TransactionFailed(String),
+	#[error("Unsupported by this storage backend: {0}")]
+	Unsupported(String),
+	#[error("Encryption failed: {0}")]
+	Encryption(String),
+	#[error("S3 error: {0}")]
+	S3(String),
+	/// A storage operation did not complete within [`Config::STORAGE_TIMEOUT_MS`].
+	#[error("Timed out after {0:?}")]
+	Timeout(Duration),
+	#[error("IO error: {0}")]
+	Io(std::io::Error),
 }